@@ -0,0 +1,292 @@
+use std::fs;
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+use sevenz_rust::{Password, SevenZReader};
+use tempfile::TempDir;
+use zip::ZipArchive;
+use crate::input_format::{self, InputFormat};
+use crate::parse_mesh::ParseError;
+
+/// Archives inside archives are unpacked up to this depth
+const MAX_ARCHIVE_DEPTH : usize = 8;
+
+const GZIP_MAGIC : &[u8] = b"\x1F\x8B";
+const XZ_MAGIC : &[u8] = b"\xFD7zXZ\x00";
+const BZIP2_MAGIC : &[u8] = b"BZh";
+const SEVEN_ZIP_MAGIC : &[u8] = b"7z\xBC\xAF\x27\x1C";
+const TAR_MAGIC : &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET : usize = 257;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container
+{
+    Zip,
+    Tar,
+    Gzip,
+    Xz,
+    Bzip2,
+    SevenZip,
+}
+
+pub struct ArchiveEntry
+{
+    /// Path inside the archive, nested archives are part of the path like directories
+    pub name: String,
+    /// Location the entry was extracted to
+    pub path: PathBuf,
+    pub format: InputFormat,
+}
+
+/// Models found in an archive. The extracted files are removed when this is dropped.
+pub struct ExtractedArchive
+{
+    pub entries: Vec<ArchiveEntry>,
+    _directory: TempDir,
+}
+
+/// Recognizes archives and compressed files by their header. Zips are only archives when they are not a
+/// zip based model format such as 3mf.
+pub fn is_archive(data : &[u8]) -> bool
+{
+    container(data).is_some()
+}
+
+fn container(data : &[u8]) -> Option<Container>
+{
+    if input_format::is_zip(data)
+    {
+        return match input_format::detect_zip(Cursor::new(data)) {
+            Ok(None) => Some(Container::Zip),
+            _ => None,
+        };
+    }
+
+    if data.starts_with(GZIP_MAGIC)
+    {
+        Some(Container::Gzip)
+    }
+    else if data.starts_with(XZ_MAGIC)
+    {
+        Some(Container::Xz)
+    }
+    else if data.starts_with(BZIP2_MAGIC)
+    {
+        Some(Container::Bzip2)
+    }
+    else if data.starts_with(SEVEN_ZIP_MAGIC)
+    {
+        Some(Container::SevenZip)
+    }
+    else if data.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+    {
+        Some(Container::Tar)
+    }
+    else
+    {
+        None
+    }
+}
+
+/// Extracts an archive, and any archives inside it, to a temporary directory. Every file that is a supported
+/// model becomes an entry. With a glob, only the first entry whose path or file name matches is kept.
+pub fn extract(path : &str, entry_glob : Option<&str>) -> Result<ExtractedArchive, ParseError>
+{
+    let data = fs::read(path)?;
+    let directory = tempfile::Builder::new().prefix("mesh-thumbnail-").tempdir()?;
+    let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+    let mut files = Vec::new();
+    add_file(data, name, "", directory.path(), 0, &mut files)?;
+    files.sort();
+
+    let mut entries = Vec::new();
+
+    for (name, path) in files {
+        // Textures, materials and other files next to the models are not entries of their own
+        match input_format::detect(path.to_str().unwrap()) {
+            Ok(InputFormat::Archive) | Err(_) => continue,
+            Ok(format) => entries.push(ArchiveEntry { name, path, format }),
+        }
+    }
+
+    if let Some(glob) = entry_glob
+    {
+        let regex = glob_to_regex(glob);
+        let file_name = |name : &str| name.rsplit('/').next().unwrap_or(name).to_string();
+
+        entries = entries
+            .into_iter()
+            .filter(|e| regex.is_match(&e.name) || (!glob.contains('/') && regex.is_match(&file_name(&e.name))))
+            .take(1)
+            .collect();
+
+        if entries.is_empty()
+        {
+            return Err(ParseError::MeshConvertError(format!("No archive entry matches {}", glob)));
+        }
+    }
+
+    if entries.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("No supported model found in archive")));
+    }
+
+    Ok(ExtractedArchive { entries, _directory: directory })
+}
+
+/// Writes a file to the extraction directory, or unpacks it when it is an archive itself.
+/// `prefix` is the path the contents of the file get when it is an archive.
+fn add_file(data : Vec<u8>, name : &str, prefix : &str, directory : &Path, depth : usize, files : &mut Vec<(String, PathBuf)>) -> Result<(), ParseError>
+{
+    let container = match container(&data) {
+        Some(container) if depth < MAX_ARCHIVE_DEPTH => container,
+        _ => {
+            let relative = normalize_name(name);
+
+            if relative.is_empty()
+            {
+                return Ok(());
+            }
+
+            let path = directory.join(relative);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, data)?;
+            files.push((name.to_string(), path));
+            return Ok(());
+        },
+    };
+
+    let mut add_entry = |entry_name : &str, entry_data : Vec<u8>| {
+        let entry_name = format!("{}{}", prefix, normalize_name(entry_name));
+        let entry_prefix = format!("{}/", entry_name);
+        add_file(entry_data, &entry_name, &entry_prefix, directory, depth + 1, files)
+    };
+
+    match container
+    {
+        // Compressed single files keep their place, named without the compression suffix
+        Container::Gzip | Container::Xz | Container::Bzip2 => {
+            let mut decompressed = Vec::new();
+
+            match container
+            {
+                Container::Gzip => { MultiGzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?; },
+                Container::Bzip2 => { bzip2::read::MultiBzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?; },
+                _ => lzma_rs::xz_decompress(&mut BufReader::new(data.as_slice()), &mut decompressed)
+                    .map_err(|e| ParseError::ReadError(format!("Invalid xz data: {}", e)))?,
+            }
+
+            add_file(decompressed, &decompressed_name(name), prefix, directory, depth + 1, files)?;
+        },
+        Container::Zip => {
+            let mut zip = ZipArchive::new(Cursor::new(data))?;
+
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+
+                if !file.is_file()
+                {
+                    continue;
+                }
+
+                let mut buffer = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut buffer)?;
+                let entry_name = file.name().to_string();
+                drop(file);
+
+                add_entry(&entry_name, buffer)?;
+            }
+        },
+        Container::Tar => {
+            let mut tar = tar::Archive::new(data.as_slice());
+
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+
+                if !entry.header().entry_type().is_file()
+                {
+                    continue;
+                }
+
+                let entry_name = entry.path()?.to_string_lossy().to_string();
+                let mut buffer = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut buffer)?;
+
+                add_entry(&entry_name, buffer)?;
+            }
+        },
+        Container::SevenZip => {
+            let length = data.len() as u64;
+            let mut reader = SevenZReader::new(Cursor::new(data), length, Password::empty())
+                .map_err(|e| ParseError::ReadError(format!("Invalid 7z archive: {}", e)))?;
+
+            // The closure can not return our error type, so the first failure is kept aside
+            let mut result = Ok(());
+
+            reader.for_each_entries(|entry, content| {
+                if entry.is_directory()
+                {
+                    return Ok(true);
+                }
+
+                let mut buffer = Vec::with_capacity(entry.size() as usize);
+                content.read_to_end(&mut buffer)?;
+                result = add_entry(entry.name(), buffer);
+                Ok(result.is_ok())
+            }).map_err(|e| ParseError::ReadError(format!("Invalid 7z archive: {}", e)))?;
+
+            result?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Name of the file inside a compressed file, e.g. `model.stl` for `model.stl.gz` and `parts.tar` for `parts.tgz`
+fn decompressed_name(name : &str) -> String
+{
+    let lower = name.to_lowercase();
+
+    for (suffix, replacement) in [(".tgz", ".tar"), (".txz", ".tar"), (".tbz2", ".tar"), (".tbz", ".tar"), (".gz", ""), (".xz", ""), (".bz2", "")] {
+        if lower.ends_with(suffix)
+        {
+            return format!("{}{}", &name[..name.len() - suffix.len()], replacement);
+        }
+    }
+
+    name.to_string()
+}
+
+/// Drops empty, `.` and `..` parts from an entry path, which keeps extracted files inside the extraction directory
+fn normalize_name(name : &str) -> String
+{
+    name.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != "." && *part != ".." && !part.contains(':'))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Turns a glob into a case insensitive regex. `*` and `?` stay within a directory, `**` matches across them.
+fn glob_to_regex(glob : &str) -> Regex
+{
+    let mut pattern = String::from("(?i)^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c
+        {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            },
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern).unwrap()
+}
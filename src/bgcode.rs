@@ -0,0 +1,511 @@
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use crate::gcode_thumbnail::{Thumbnail, ThumbnailFormat};
+use crate::parse_mesh::ParseError;
+
+// Binary G-code container as written by PrusaSlicer
+// https://github.com/prusa3d/libbgcode/blob/main/doc/specifications.md
+
+const MAGIC : &[u8; 4] = b"GCDE";
+
+const BLOCK_FILE_METADATA : u16 = 0;
+const BLOCK_GCODE : u16 = 1;
+const BLOCK_SLICER_METADATA : u16 = 2;
+const BLOCK_PRINTER_METADATA : u16 = 3;
+const BLOCK_PRINT_METADATA : u16 = 4;
+const BLOCK_THUMBNAIL : u16 = 5;
+
+const COMPRESSION_NONE : u16 = 0;
+const COMPRESSION_DEFLATE : u16 = 1;
+const COMPRESSION_HEATSHRINK_11_4 : u16 = 2;
+const COMPRESSION_HEATSHRINK_12_4 : u16 = 3;
+
+const ENCODING_GCODE_NONE : u16 = 0;
+const ENCODING_GCODE_MEATPACK : u16 = 1;
+const ENCODING_GCODE_MEATPACK_COMMENTS : u16 = 2;
+
+const CHECKSUM_NONE : u16 = 0;
+const CHECKSUM_CRC32 : u16 = 1;
+
+/// The uncompressed size comes from the file, buffers are only reserved up to this size in advance
+const MAX_RESERVED_SIZE : usize = 64 * 1024 * 1024;
+
+#[derive(Default)]
+pub struct BgcodeFile
+{
+    pub file_metadata: Vec<(String, String)>,
+    pub printer_metadata: Vec<(String, String)>,
+    pub print_metadata: Vec<(String, String)>,
+    pub slicer_metadata: Vec<(String, String)>,
+    pub thumbnails: Vec<Thumbnail>,
+    /// Decoded G-code text of all G-code blocks, in file order
+    pub gcode: String,
+}
+
+pub fn is_bgcode(data : &[u8]) -> bool
+{
+    data.len() >= MAGIC.len() && &data[..MAGIC.len()] == MAGIC
+}
+
+pub fn read<R>(reader : &mut R) -> Result<BgcodeFile, ParseError>
+where
+    R: Read
+{
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    parse(&data)
+}
+
+pub fn parse(data : &[u8]) -> Result<BgcodeFile, ParseError>
+{
+    let mut stream = ByteStream { data, offset: 0 };
+
+    if !is_bgcode(data)
+    {
+        return Err(ParseError::ParseError(String::from("Missing bgcode file header")));
+    }
+
+    stream.skip(MAGIC.len())?;
+    let _version = stream.u32()?;
+    let checksum_type = stream.u16()?;
+
+    if checksum_type != CHECKSUM_NONE && checksum_type != CHECKSUM_CRC32
+    {
+        return Err(ParseError::ParseError(format!("Unknown bgcode checksum type {}", checksum_type)));
+    }
+
+    let mut file = BgcodeFile::default();
+
+    while !stream.is_empty()
+    {
+        let block_start = stream.offset;
+        let block_type = stream.u16()?;
+        let compression = stream.u16()?;
+        let uncompressed_size = stream.u32()? as usize;
+        let compressed_size = if compression == COMPRESSION_NONE { uncompressed_size } else { stream.u32()? as usize };
+
+        let params_size = if block_type == BLOCK_THUMBNAIL { 6 } else { 2 };
+        let params = stream.take(params_size)?;
+        let encoding = u16::from_le_bytes([params[0], params[1]]);
+        let payload = stream.take(compressed_size)?;
+
+        if checksum_type == CHECKSUM_CRC32
+        {
+            let expected = stream.u32()?;
+            let actual = crc32fast::hash(&data[block_start..stream.offset - 4]);
+
+            if expected != actual
+            {
+                return Err(ParseError::ReadError(format!("Checksum mismatch in bgcode block at offset {}", block_start)));
+            }
+        }
+
+        let payload = decompress(payload, compression, uncompressed_size)?;
+
+        match block_type
+        {
+            BLOCK_FILE_METADATA => file.file_metadata = parse_metadata(&payload),
+            BLOCK_PRINTER_METADATA => file.printer_metadata = parse_metadata(&payload),
+            BLOCK_PRINT_METADATA => file.print_metadata = parse_metadata(&payload),
+            BLOCK_SLICER_METADATA => file.slicer_metadata = parse_metadata(&payload),
+            BLOCK_THUMBNAIL => {
+                let format = match encoding
+                {
+                    0 => ThumbnailFormat::Png,
+                    1 => ThumbnailFormat::Jpg,
+                    2 => ThumbnailFormat::Qoi,
+                    _ => return Err(ParseError::ParseError(format!("Unknown bgcode thumbnail format {}", encoding))),
+                };
+
+                file.thumbnails.push(Thumbnail {
+                    format,
+                    width: u16::from_le_bytes([params[2], params[3]]) as u32,
+                    height: u16::from_le_bytes([params[4], params[5]]) as u32,
+                    data: payload,
+                });
+            },
+            BLOCK_GCODE => {
+                match encoding
+                {
+                    ENCODING_GCODE_NONE => file.gcode.push_str(&String::from_utf8_lossy(&payload)),
+                    ENCODING_GCODE_MEATPACK | ENCODING_GCODE_MEATPACK_COMMENTS => file.gcode.push_str(&unmeatpack(&payload)),
+                    _ => return Err(ParseError::ParseError(format!("Unknown bgcode gcode encoding {}", encoding))),
+                }
+            },
+            // Unknown blocks are skipped, their size is known from the header
+            _ => {},
+        }
+    }
+
+    Ok(file)
+}
+
+fn parse_metadata(payload : &[u8]) -> Vec<(String, String)>
+{
+    // Metadata blocks are INI encoded, one key=value pair per line
+    String::from_utf8_lossy(payload)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (String::from(key.trim()), String::from(value.trim())))
+        .collect()
+}
+
+fn decompress(payload : &[u8], compression : u16, uncompressed_size : usize) -> Result<Vec<u8>, ParseError>
+{
+    let result = match compression
+    {
+        COMPRESSION_NONE => payload.to_vec(),
+        COMPRESSION_DEFLATE => {
+            let mut buffer = Vec::with_capacity(uncompressed_size.min(MAX_RESERVED_SIZE));
+            ZlibDecoder::new(payload).read_to_end(&mut buffer)?;
+            buffer
+        },
+        COMPRESSION_HEATSHRINK_11_4 => heatshrink_decompress(payload, 11, 4, uncompressed_size),
+        COMPRESSION_HEATSHRINK_12_4 => heatshrink_decompress(payload, 12, 4, uncompressed_size),
+        _ => return Err(ParseError::ParseError(format!("Unknown bgcode compression type {}", compression))),
+    };
+
+    if result.len() != uncompressed_size
+    {
+        return Err(ParseError::ReadError(format!("Bgcode block decompressed to {} bytes, expected {}", result.len(), uncompressed_size)));
+    }
+
+    Ok(result)
+}
+
+// https://github.com/atomicobject/heatshrink/blob/master/heatshrink_decoder.c
+fn heatshrink_decompress(payload : &[u8], window_bits : u32, lookahead_bits : u32, uncompressed_size : usize) -> Vec<u8>
+{
+    let mut bits = BitReader { data: payload, offset: 0, bit: 0 };
+    let mut output : Vec<u8> = Vec::with_capacity(uncompressed_size.min(MAX_RESERVED_SIZE));
+
+    while output.len() < uncompressed_size
+    {
+        let Some(tag) = bits.read(1) else { break; };
+
+        if tag == 1
+        {
+            let Some(literal) = bits.read(8) else { break; };
+            output.push(literal as u8);
+        }
+        else
+        {
+            let Some(index) = bits.read(window_bits) else { break; };
+            let Some(count) = bits.read(lookahead_bits) else { break; };
+            let index = index as usize + 1;
+            let count = count as usize + 1;
+
+            if index > output.len()
+            {
+                break;
+            }
+
+            for _ in 0..count
+            {
+                output.push(output[output.len() - index]);
+            }
+        }
+    }
+
+    output
+}
+
+struct BitReader<'a>
+{
+    data: &'a [u8],
+    offset: usize,
+    bit: u32,
+}
+
+impl BitReader<'_>
+{
+    /// Reads `count` bits, most significant bit first
+    fn read(&mut self, count : u32) -> Option<u32>
+    {
+        let mut value = 0u32;
+
+        for _ in 0..count
+        {
+            let byte = *self.data.get(self.offset)?;
+            value = (value << 1) | ((byte >> (7 - self.bit)) & 1) as u32;
+            self.bit += 1;
+
+            if self.bit == 8
+            {
+                self.bit = 0;
+                self.offset += 1;
+            }
+        }
+
+        Some(value)
+    }
+}
+
+const MEATPACK_SIGNAL_BYTE : u8 = 0xFF;
+const MEATPACK_COMMAND_ENABLE_PACKING : u8 = 0xFB;
+const MEATPACK_COMMAND_DISABLE_PACKING : u8 = 0xFA;
+const MEATPACK_COMMAND_RESET_ALL : u8 = 0xF9;
+const MEATPACK_COMMAND_ENABLE_NO_SPACES : u8 = 0xF7;
+const MEATPACK_COMMAND_DISABLE_NO_SPACES : u8 = 0xF6;
+const MEATPACK_NOT_PACKED : u8 = 0b1111;
+
+// https://github.com/prusa3d/libbgcode/blob/main/src/LibBGCode/binarize/meatpack.cpp
+fn unmeatpack(payload : &[u8]) -> String
+{
+    let mut state = MeatPackState::default();
+    let mut i = 0;
+
+    while i < payload.len()
+    {
+        let c = payload[i];
+        i += 1;
+
+        if c == MEATPACK_SIGNAL_BYTE && payload.get(i) == Some(&MEATPACK_SIGNAL_BYTE)
+        {
+            if let Some(&command) = payload.get(i + 1)
+            {
+                state.handle_command(command);
+            }
+
+            i += 2;
+            continue;
+        }
+
+        state.handle_byte(c);
+    }
+
+    state.output
+}
+
+#[derive(Default)]
+struct MeatPackState
+{
+    packing: bool,
+    no_spaces: bool,
+    /// Amount of full width characters that follow the last packed byte
+    full_char_queue: usize,
+    /// Packed character that has to be written after the pending full width character
+    char_buf: Option<char>,
+    output: String,
+    line_start: usize,
+}
+
+impl MeatPackState
+{
+    fn handle_command(&mut self, command : u8)
+    {
+        match command
+        {
+            MEATPACK_COMMAND_ENABLE_PACKING => self.packing = true,
+            MEATPACK_COMMAND_DISABLE_PACKING => self.packing = false,
+            MEATPACK_COMMAND_ENABLE_NO_SPACES => self.no_spaces = true,
+            MEATPACK_COMMAND_DISABLE_NO_SPACES => self.no_spaces = false,
+            MEATPACK_COMMAND_RESET_ALL => {
+                self.packing = false;
+                self.no_spaces = false;
+            },
+            _ => {},
+        }
+    }
+
+    fn handle_byte(&mut self, c : u8)
+    {
+        if !self.packing
+        {
+            self.push(c as char);
+            return;
+        }
+
+        if self.full_char_queue > 0
+        {
+            self.push(c as char);
+
+            if let Some(buffered) = self.char_buf.take()
+            {
+                self.push(buffered);
+            }
+
+            self.full_char_queue -= 1;
+            return;
+        }
+
+        let low = c & 0xF;
+        let high = c >> 4;
+
+        if low == MEATPACK_NOT_PACKED
+        {
+            self.full_char_queue += 1;
+
+            if high == MEATPACK_NOT_PACKED
+            {
+                self.full_char_queue += 1;
+            }
+            else
+            {
+                self.char_buf = Some(self.unpack_char(high));
+            }
+        }
+        else
+        {
+            let first = self.unpack_char(low);
+            self.push(first);
+
+            // A packed newline ends the byte, the upper half is padding
+            if first != '\n'
+            {
+                if high == MEATPACK_NOT_PACKED
+                {
+                    self.full_char_queue += 1;
+                }
+                else
+                {
+                    let second = self.unpack_char(high);
+                    self.push(second);
+                }
+            }
+        }
+    }
+
+    fn unpack_char(&self, c : u8) -> char
+    {
+        match c
+        {
+            0..=9 => (b'0' + c) as char,
+            0b1010 => '.',
+            0b1011 => if self.no_spaces { 'E' } else { ' ' },
+            0b1100 => '\n',
+            0b1101 => 'G',
+            0b1110 => 'X',
+            _ => '\0',
+        }
+    }
+
+    fn push(&mut self, c : char)
+    {
+        if c == '\n'
+        {
+            self.output.push(c);
+            self.line_start = self.output.len();
+            return;
+        }
+
+        // Spaces stripped by the no-spaces mode are restored in front of every parameter
+        let line = &self.output[self.line_start..];
+        if self.no_spaces
+            && !line.is_empty()
+            && !line.starts_with(';')
+            && !line.ends_with(' ')
+            && "XYZEFIJRPWHCAST".contains(c)
+        {
+            self.output.push(' ');
+        }
+
+        self.output.push(c);
+    }
+}
+
+struct ByteStream<'a>
+{
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteStream<'a>
+{
+    fn is_empty(&self) -> bool
+    {
+        self.offset >= self.data.len()
+    }
+
+    fn take(&mut self, len : usize) -> Result<&'a [u8], ParseError>
+    {
+        if self.offset + len > self.data.len()
+        {
+            return Err(ParseError::ReadError(String::from("Unexpected end of bgcode file")));
+        }
+
+        let slice = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, len : usize) -> Result<(), ParseError>
+    {
+        self.take(len).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError>
+    {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError>
+    {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// "abc" as literals followed by a 6 byte back reference to the start, with an 11 bit window
+    const HEATSHRINK_ABC : [u8; 6] = [0xB0, 0xD8, 0xAC, 0x60, 0x04, 0xA0];
+
+    #[test]
+    fn heatshrink_back_reference()
+    {
+        assert_eq!(heatshrink_decompress(&HEATSHRINK_ABC, 11, 4, 9), b"abcabcabc");
+        assert_eq!(decompress(&HEATSHRINK_ABC, COMPRESSION_HEATSHRINK_11_4, 9).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn heatshrink_stops_at_invalid_input()
+    {
+        // A back reference before the start of the output
+        assert_eq!(heatshrink_decompress(&[0x00, 0x00, 0x00], 11, 4, 4), b"");
+        // Running out of input ends the output early
+        assert_eq!(heatshrink_decompress(&HEATSHRINK_ABC[..2], 11, 4, 9), b"a");
+    }
+
+    #[test]
+    fn unexpected_size_is_an_error()
+    {
+        assert!(decompress(&HEATSHRINK_ABC, COMPRESSION_HEATSHRINK_11_4, usize::MAX / 2).is_err());
+        assert!(decompress(b"abc", COMPRESSION_NONE, 4).is_err());
+    }
+
+    #[test]
+    fn meatpack_packed_characters()
+    {
+        // Packing enabled, then "G1 X1\n" two characters per byte, low nibble first
+        let payload = [0xFF, 0xFF, 0xFB, 0x1D, 0xEB, 0xC1];
+        assert_eq!(unmeatpack(&payload), "G1 X1\n");
+    }
+
+    #[test]
+    fn meatpack_full_width_characters()
+    {
+        // "M" cannot be packed and follows the byte that holds the "1" after it, ";" needs a whole byte of its own
+        let payload = [0xFF, 0xFF, 0xFB, 0x1F, b'M', 0xFF, b';', b'a', 0xCC];
+        assert_eq!(unmeatpack(&payload), "M1;a\n");
+    }
+
+    #[test]
+    fn meatpack_restores_spaces()
+    {
+        // No spaces mode, "G1X1E2\n" where E takes the place of the space code
+        let payload = [0xFF, 0xFF, 0xFB, 0xFF, 0xFF, 0xF7, 0x1D, 0x1E, 0x2B, 0xCC];
+        assert_eq!(unmeatpack(&payload), "G1 X1 E2\n");
+    }
+
+    #[test]
+    fn meatpack_disabled_passes_bytes_through()
+    {
+        assert_eq!(unmeatpack(b"G28\n"), "G28\n");
+    }
+}
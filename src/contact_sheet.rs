@@ -0,0 +1,203 @@
+/// Width and height of a caption glyph before scaling
+const GLYPH_WIDTH : u32 = 5;
+const GLYPH_HEIGHT : u32 = 7;
+/// Free pixels around the caption text, before scaling
+const CAPTION_PADDING : u32 = 2;
+const CAPTION_COLOR : [u8; 4] = [0xDD, 0xDD, 0xDD, 0xFF];
+
+/// Characters of the built in caption font, lowercase letters are drawn as uppercase
+const GLYPH_CHARACTERS : &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_.()/:+,#? ";
+/// Rows of every glyph from top to bottom, the highest of the five bits is the leftmost pixel
+const GLYPHS : [[u8; 7]; 48] = [
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+    [0x01, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10],
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+/// How the cells of a contact sheet are placed inside the image
+pub struct Layout
+{
+    pub columns: u32,
+    pub rows: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Part of the bottom of every cell reserved for the caption, 0 without captions
+    pub caption_height: u32,
+}
+
+impl Layout
+{
+    /// Picks the grid with the most square cells that fits `count` cells into an image of the given size
+    pub fn new(count : usize, width : u32, height : u32, captions : bool) -> Layout
+    {
+        let count = count.max(1) as u32;
+        let columns = ((count as f32 * width as f32 / height as f32).sqrt().ceil() as u32).clamp(1, count);
+        let rows = count.div_ceil(columns);
+        let cell_width = (width / columns).max(1);
+        let cell_height = (height / rows).max(1);
+
+        let caption_height = if captions
+        {
+            ((GLYPH_HEIGHT + CAPTION_PADDING * 2) * caption_scale(cell_height)).min(cell_height / 2)
+        }
+        else
+        {
+            0
+        };
+
+        Layout { columns, rows, cell_width, cell_height, caption_height }
+    }
+
+    /// Size of the rendered model inside a cell
+    pub fn render_size(&self) -> (u32, u32)
+    {
+        (self.cell_width, (self.cell_height - self.caption_height).max(1))
+    }
+}
+
+/// Bigger cells get bigger captions, so they stay readable in large images
+fn caption_scale(cell_height : u32) -> u32
+{
+    (cell_height / 128).max(1)
+}
+
+/// Places the rendered cells, each of `Layout::render_size`, in a grid from left to right and top to bottom.
+/// Cells are centered when the grid does not fill the image exactly.
+pub fn compose(cells : &[(Vec<[u8; 4]>, String)], layout : &Layout, width : u32, height : u32, background : [u8; 4]) -> Vec<[u8; 4]>
+{
+    let mut pixels = vec![background; (width * height) as usize];
+    let (render_width, render_height) = layout.render_size();
+    let margin_x = (width - layout.columns * layout.cell_width) / 2;
+    let margin_y = (height - layout.rows * layout.cell_height) / 2;
+
+    for (index, (cell, caption)) in cells.iter().enumerate() {
+        let column = index as u32 % layout.columns;
+        let row = index as u32 / layout.columns;
+
+        if row >= layout.rows
+        {
+            break;
+        }
+
+        let left = margin_x + column * layout.cell_width;
+        let top = margin_y + row * layout.cell_height;
+
+        for y in 0..render_height {
+            let source = (y * render_width) as usize;
+            let destination = ((top + y) * width + left) as usize;
+
+            if let Some(line) = cell.get(source..source + render_width as usize)
+            {
+                pixels[destination..destination + render_width as usize].copy_from_slice(line);
+            }
+        }
+
+        if layout.caption_height > 0
+        {
+            draw_caption(&mut pixels, width, caption, left, top + render_height, layout.cell_width, layout.caption_height);
+        }
+    }
+
+    pixels
+}
+
+/// Draws a single line of text centered in the given area, cut short with `..` when it does not fit
+fn draw_caption(pixels : &mut [[u8; 4]], width : u32, caption : &str, left : u32, top : u32, area_width : u32, area_height : u32)
+{
+    let scale = (area_height / (GLYPH_HEIGHT + CAPTION_PADDING * 2)).max(1);
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let fits = ((area_width.saturating_sub(CAPTION_PADDING * 2 * scale)) / advance) as usize;
+    let mut characters : Vec<char> = caption.chars().collect();
+
+    if characters.len() > fits
+    {
+        characters.truncate(fits.saturating_sub(2));
+        characters.extend(['.', '.']);
+        characters.truncate(fits);
+    }
+
+    let text_width = (characters.len() as u32 * advance).saturating_sub(scale);
+    let text_left = left + area_width.saturating_sub(text_width) / 2;
+    let text_top = top + area_height.saturating_sub(GLYPH_HEIGHT * scale) / 2;
+
+    for (index, character) in characters.iter().enumerate() {
+        let glyph = glyph(*character);
+        let glyph_left = text_left + index as u32 * advance;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0
+                {
+                    continue;
+                }
+
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = glyph_left + column * scale + dx;
+                        let y = text_top + row as u32 * scale + dy;
+
+                        if let Some(pixel) = pixels.get_mut((y * width + x) as usize)
+                        {
+                            *pixel = CAPTION_COLOR;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(character : char) -> &'static [u8; 7]
+{
+    let index = GLYPH_CHARACTERS
+        .find(character.to_ascii_uppercase())
+        .or_else(|| GLYPH_CHARACTERS.find('?'))
+        .unwrap();
+
+    &GLYPHS[index]
+}
@@ -0,0 +1,525 @@
+use std::f32::consts::PI;
+use std::io::BufRead;
+use three_d::*;
+use crate::parse_mesh::ParseError;
+
+// G-code as written by slicers for Marlin, Klipper and RepRapFirmware
+// https://marlinfw.org/meta/gcode/
+// https://reprap.org/wiki/G-code
+
+/// Arcs are split into straight segments of at most this length in mm
+const ARC_SEGMENT_LENGTH : f32 = 0.5;
+/// and at most this angle, so small arcs stay round
+const MAX_ARC_SEGMENT_ANGLE : f32 = PI / 18.0;
+const MAX_ARC_SEGMENTS : u32 = 1024;
+const MM_PER_INCH : f32 = 25.4;
+/// Length reported for G10 and G11 firmware retractions, the real length is a printer setting
+const FIRMWARE_RETRACTION : f32 = 1.0;
+
+/// A straight move of the toolhead in machine coordinates. Arcs are reported as several moves.
+pub struct Move<'a>
+{
+    pub from: Vec3,
+    pub to: Vec3,
+    /// Filament pushed during the move, negative for retractions
+    pub extrusion: f32,
+    /// Speed in mm/min
+    pub feedrate: f32,
+    /// Extruder selected with the last tool change
+    pub tool: u32,
+    /// Slicer feature from the last `;TYPE:` or `; FEATURE:` comment, empty before the first one
+    pub feature: &'a str,
+    /// Height of the layer the toolhead is printing
+    pub layer_height: f32,
+    /// Index of the layer the toolhead is printing, counted from 0
+    pub layer: u32,
+}
+
+impl Move<'_>
+{
+    /// Moves that push filament while moving the toolhead. Travel moves and retractions without movement are not.
+    pub fn is_extrusion(&self) -> bool
+    {
+        self.extrusion > 0.0 && self.from != self.to
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Motion
+{
+    Rapid,
+    Linear,
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Axes an arc is drawn in, as indices into a position: the two plane axes and the helical axis
+#[derive(Clone, Copy)]
+struct Plane(usize, usize, usize);
+
+const PLANE_XY : Plane = Plane(0, 1, 2);
+const PLANE_ZX : Plane = Plane(2, 0, 1);
+const PLANE_YZ : Plane = Plane(1, 2, 0);
+
+/// Machine state that changes how the coordinates of a move are read
+struct Interpreter
+{
+    /// Toolhead position in machine coordinates
+    position: Vec3,
+    /// Machine position of the logical origin, moved by G92
+    offset: Vec3,
+    extruder: f32,
+    feedrate: f32,
+    absolute: bool,
+    absolute_extrusion: bool,
+    /// G90 and G91 only change the extrusion mode until it is set with M82 or M83, slicers often switch
+    /// to relative coordinates for a z hop without repeating M83 afterwards
+    extrusion_mode_set: bool,
+    /// Millimeters per unit, changed by G20 and G21
+    scale: f32,
+    plane: Plane,
+    motion: Motion,
+    tool: u32,
+    feature: String,
+    /// Height of the last extrusion and the layer it started, layers are recognized by extrusions at a new height
+    layer_z: f32,
+    layer_height: f32,
+    /// Amount of layers that have started, the current layer is the last of them
+    layers: u32,
+    /// Slicer layer change comments were found, Z changes no longer start layers
+    layer_comments: bool,
+    /// A layer change comment was read and the layer starts with the next extrusion
+    layer_pending: bool,
+    words: Vec<(char, f32)>,
+}
+
+impl Interpreter
+{
+    fn new() -> Interpreter
+    {
+        Interpreter {
+            position: vec3(0.0, 0.0, 0.0),
+            offset: vec3(0.0, 0.0, 0.0),
+            extruder: 0.0,
+            feedrate: 0.0,
+            absolute: true,
+            absolute_extrusion: true,
+            extrusion_mode_set: false,
+            scale: 1.0,
+            plane: PLANE_XY,
+            motion: Motion::Rapid,
+            tool: 0,
+            feature: String::new(),
+            layer_z: 0.0,
+            layer_height: 0.0,
+            layers: 0,
+            layer_comments: false,
+            layer_pending: false,
+            words: Vec::new(),
+        }
+    }
+
+    /// Runs a single line, reporting every move it makes
+    fn execute<F>(&mut self, line : &str, on_move : &mut F)
+    where
+        F: FnMut(&Move)
+    {
+        if let Some((_, comment)) = line.split_once(';')
+        {
+            let comment = comment.trim();
+
+            if let Some(feature) = comment.strip_prefix("TYPE:").or_else(|| comment.strip_prefix("FEATURE:"))
+            {
+                self.feature = feature.trim().to_string();
+            }
+            // PrusaSlicer and OrcaSlicer, Cura, Bambu Studio
+            else if comment == "LAYER_CHANGE" || comment.starts_with("LAYER:") || comment == "CHANGE_LAYER"
+            {
+                self.layer_comments = true;
+                self.layer_pending = true;
+            }
+        }
+
+        let code = strip_comments(line);
+
+        if !read_words(&code, &mut self.words)
+        {
+            return;
+        }
+
+        let mut motion = None;
+        let mut home = false;
+        let mut set_position = false;
+        let mut firmware_retraction = None;
+        // T is a tool change on its own, after an M code such as `M104 T1 S215` it only selects the tool the command is for
+        let has_command = self.words.iter().any(|(letter, _)| *letter == 'G' || *letter == 'M');
+
+        for &(letter, value) in &self.words {
+            match (letter, value as u32)
+            {
+                ('G', 0) => motion = Some(Motion::Rapid),
+                ('G', 1) => motion = Some(Motion::Linear),
+                ('G', 2) => motion = Some(Motion::Clockwise),
+                ('G', 3) => motion = Some(Motion::CounterClockwise),
+                ('G', 17) => self.plane = PLANE_XY,
+                ('G', 18) => self.plane = PLANE_ZX,
+                ('G', 19) => self.plane = PLANE_YZ,
+                ('G', 20) => self.scale = MM_PER_INCH,
+                ('G', 21) => self.scale = 1.0,
+                // G10 with parameters sets tool offsets and temperatures instead
+                ('G', 10) if self.words.len() == 1 => firmware_retraction = Some(-FIRMWARE_RETRACTION),
+                ('G', 11) => firmware_retraction = Some(FIRMWARE_RETRACTION),
+                ('G', 28) => home = true,
+                ('G', 90) | ('G', 91) => {
+                    self.absolute = value as u32 == 90;
+
+                    if !self.extrusion_mode_set
+                    {
+                        self.absolute_extrusion = self.absolute;
+                    }
+                },
+                ('G', 92) => set_position = true,
+                ('T', tool) if !has_command => self.tool = tool,
+                ('M', 82) | ('M', 83) => {
+                    self.absolute_extrusion = value as u32 == 82;
+                    self.extrusion_mode_set = true;
+                },
+                _ => {},
+            }
+        }
+
+        if home
+        {
+            self.home(on_move);
+            return;
+        }
+
+        if set_position
+        {
+            self.set_position();
+            return;
+        }
+
+        if let Some(extrusion) = firmware_retraction
+        {
+            self.move_to(self.position, extrusion, on_move);
+            return;
+        }
+
+        let has_coordinates = self.words.iter().any(|(letter, _)| matches!(letter, 'X' | 'Y' | 'Z' | 'E'));
+        let has_commands = self.words.iter().any(|(letter, _)| matches!(letter, 'G' | 'M' | 'T'));
+
+        // Lines with only coordinates repeat the last motion
+        let motion = match motion
+        {
+            Some(motion) => motion,
+            None if has_coordinates && !has_commands => self.motion,
+            None => return,
+        };
+
+        self.motion = motion;
+        self.motion(motion, on_move);
+    }
+
+    fn word(&self, letter : char) -> Option<f32>
+    {
+        self.words.iter().rev().find(|(l, _)| *l == letter).map(|(_, value)| *value)
+    }
+
+    fn motion<F>(&mut self, motion : Motion, on_move : &mut F)
+    where
+        F: FnMut(&Move)
+    {
+        if let Some(feedrate) = self.word('F')
+        {
+            self.feedrate = feedrate * self.scale;
+        }
+
+        let mut target = self.position;
+
+        for (axis, letter) in ['X', 'Y', 'Z'].into_iter().enumerate() {
+            if let Some(value) = self.word(letter)
+            {
+                target[axis] = if self.absolute { value * self.scale + self.offset[axis] } else { self.position[axis] + value * self.scale };
+            }
+        }
+
+        let extrusion = match self.word('E')
+        {
+            Some(value) if self.absolute_extrusion => {
+                let delta = value * self.scale - self.extruder;
+                self.extruder = value * self.scale;
+                delta
+            },
+            Some(value) => {
+                self.extruder += value * self.scale;
+                value * self.scale
+            },
+            None => 0.0,
+        };
+
+        match motion
+        {
+            Motion::Rapid | Motion::Linear => {
+                self.move_to(target, extrusion, on_move);
+            },
+            Motion::Clockwise | Motion::CounterClockwise => self.arc(target, extrusion, motion == Motion::Clockwise, on_move),
+        }
+    }
+
+    /// Splits an arc into straight moves, the extrusion is spread evenly over them
+    fn arc<F>(&mut self, target : Vec3, extrusion : f32, clockwise : bool, on_move : &mut F)
+    where
+        F: FnMut(&Move)
+    {
+        let Plane(a, b, linear) = self.plane;
+        let start = self.position;
+        let offsets = [self.word('I'), self.word('J'), self.word('K')];
+
+        let center = if let Some(radius) = self.word('R')
+        {
+            match arc_center_from_radius(start, target, radius * self.scale, clockwise, a, b)
+            {
+                Some(center) => center,
+                None => {
+                    // The radius can not reach the target, firmware moves in a straight line
+                    self.move_to(target, extrusion, on_move);
+                    return;
+                },
+            }
+        }
+        else
+        {
+            (start[a] + offsets[a].unwrap_or(0.0) * self.scale, start[b] + offsets[b].unwrap_or(0.0) * self.scale)
+        };
+
+        let start_angle = (start[b] - center.1).atan2(start[a] - center.0);
+        let end_angle = (target[b] - center.1).atan2(target[a] - center.0);
+        let start_radius = (start[a] - center.0).hypot(start[b] - center.1);
+        let end_radius = (target[a] - center.0).hypot(target[b] - center.1);
+
+        let mut sweep = if clockwise { start_angle - end_angle } else { end_angle - start_angle };
+
+        if sweep <= 1e-6
+        {
+            // Arcs ending where they start are full circles
+            sweep += 2.0 * PI;
+        }
+
+        let length = sweep * start_radius.max(end_radius);
+        let segments = ((length / ARC_SEGMENT_LENGTH).ceil().max((sweep / MAX_ARC_SEGMENT_ANGLE).ceil()) as u32).clamp(1, MAX_ARC_SEGMENTS);
+        let direction = if clockwise { -1.0 } else { 1.0 };
+
+        for segment in 1..=segments {
+            let t = segment as f32 / segments as f32;
+            let mut to = target;
+
+            if segment < segments
+            {
+                let angle = start_angle + direction * sweep * t;
+                let radius = start_radius + (end_radius - start_radius) * t;
+                to[a] = center.0 + radius * angle.cos();
+                to[b] = center.1 + radius * angle.sin();
+                to[linear] = start[linear] + (target[linear] - start[linear]) * t;
+            }
+
+            self.move_to(to, extrusion / segments as f32, on_move);
+        }
+    }
+
+    fn move_to<F>(&mut self, to : Vec3, extrusion : f32, on_move : &mut F)
+    where
+        F: FnMut(&Move)
+    {
+        let from = self.position;
+        self.position = to;
+
+        let printed = Move {
+            from,
+            to,
+            extrusion,
+            feedrate: self.feedrate,
+            tool: self.tool,
+            feature: &self.feature,
+            layer_height: self.layer_height,
+            layer: 0,
+        };
+
+        if printed.is_extrusion()
+        {
+            if self.layer_pending || (!self.layer_comments && (self.layers == 0 || to.z != self.layer_z))
+            {
+                self.layers += 1;
+                self.layer_pending = false;
+            }
+
+            if to.z != self.layer_z
+            {
+                // Moving down again is a new object printed in sequence, it keeps the layer height
+                if to.z > self.layer_z
+                {
+                    self.layer_height = to.z - self.layer_z;
+                }
+
+                self.layer_z = to.z;
+            }
+        }
+
+        on_move(&Move { layer_height: self.layer_height, layer: self.layers.saturating_sub(1), ..printed });
+    }
+
+    /// G28 moves the given axes, or all of them, to the machine origin
+    fn home<F>(&mut self, on_move : &mut F)
+    where
+        F: FnMut(&Move)
+    {
+        let axes : Vec<usize> = ['X', 'Y', 'Z'].into_iter().enumerate().filter(|(_, l)| self.word(*l).is_some()).map(|(i, _)| i).collect();
+        let mut target = self.position;
+
+        for axis in if axes.is_empty() { vec![0, 1, 2] } else { axes } {
+            target[axis] = 0.0;
+            self.offset[axis] = 0.0;
+        }
+
+        self.move_to(target, 0.0, on_move);
+    }
+
+    /// G92 changes the logical position without moving, usually to reset the extruder
+    fn set_position(&mut self)
+    {
+        let all = !self.words.iter().any(|(letter, _)| matches!(letter, 'X' | 'Y' | 'Z' | 'E'));
+
+        for (axis, letter) in ['X', 'Y', 'Z'].into_iter().enumerate() {
+            match self.word(letter)
+            {
+                Some(value) => self.offset[axis] = self.position[axis] - value * self.scale,
+                None if all => self.offset[axis] = self.position[axis],
+                None => {},
+            }
+        }
+
+        match self.word('E')
+        {
+            Some(value) => self.extruder = value * self.scale,
+            None if all => self.extruder = 0.0,
+            None => {},
+        }
+    }
+}
+
+/// G-code is Z up while the renderer is Y up, the X axis is mirrored to keep the toolpath from being drawn mirrored
+pub fn render_coordinates(position : Vec3) -> Vec3
+{
+    vec3(-position.x, position.z, position.y)
+}
+
+/// Runs every line of a G-code file
+pub fn interpret<R, F>(reader : R, mut on_move : F) -> Result<(), ParseError>
+where
+    R: BufRead,
+    F: FnMut(&Move)
+{
+    let mut interpreter = Interpreter::new();
+
+    for line in reader.lines() {
+        interpreter.execute(&line?, &mut on_move);
+    }
+
+    Ok(())
+}
+
+/// Center of an arc given by its radius. Positive radii take the short way around, negative radii the long way.
+fn arc_center_from_radius(start : Vec3, end : Vec3, radius : f32, clockwise : bool, a : usize, b : usize) -> Option<(f32, f32)>
+{
+    let chord = (end[a] - start[a], end[b] - start[b]);
+    let length = chord.0.hypot(chord.1);
+
+    if length == 0.0 || length > radius.abs() * 2.0 + 1e-3
+    {
+        return None;
+    }
+
+    let height = (radius * radius - length * length / 4.0).max(0.0).sqrt();
+    let middle = ((start[a] + end[a]) / 2.0, (start[b] + end[b]) / 2.0);
+    let left = (-chord.1 / length, chord.0 / length);
+    let side = if clockwise == (radius > 0.0) { -1.0 } else { 1.0 };
+
+    Some((middle.0 + left.0 * height * side, middle.1 + left.1 * height * side))
+}
+
+/// Drops `;` comments and `( )` comments
+fn strip_comments(line : &str) -> String
+{
+    let line = line.split(';').next().unwrap_or_default();
+    let mut code = String::with_capacity(line.len());
+    let mut depth = 0;
+
+    for c in line.chars() {
+        match c
+        {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => code.push(c),
+            _ => {},
+        }
+    }
+
+    code
+}
+
+/// Splits a line into letter and number pairs, such as `G1 X-2.5 Y.5`, `G1X10Y10` or `N12 G1 E1*57`.
+/// Returns false for lines that are not plain G-code, like Klipper macros or `M117` messages.
+fn read_words(code : &str, words : &mut Vec<(char, f32)>) -> bool
+{
+    words.clear();
+    let code = code.trim_start();
+    let mut chars = code.char_indices().peekable();
+
+    // Extended commands such as SET_VELOCITY_LIMIT start with a word instead of a letter and a number
+    match code.chars().nth(1)
+    {
+        Some(c) if c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | ' ') => {},
+        _ => return false,
+    }
+
+    while let Some((start, c)) = chars.next() {
+        let letter = c.to_ascii_uppercase();
+
+        // Checksums are not part of the command
+        if letter == '*'
+        {
+            break;
+        }
+
+        if !letter.is_ascii_alphabetic()
+        {
+            continue;
+        }
+
+        while chars.next_if(|(_, c)| *c == ' ' || *c == '\t').is_some() {}
+
+        let number_start = chars.peek().map(|(i, _)| *i).unwrap_or(code.len());
+        let mut number_end = number_start;
+
+        while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+            number_end = i + c.len_utf8();
+        }
+
+        if let Ok(value) = code[number_start..number_end].parse::<f32>()
+        {
+            words.push((letter, value));
+
+            // The rest of a message is text, not parameters
+            if letter == 'M' && (value == 117.0 || value == 118.0)
+            {
+                break;
+            }
+        }
+        else if start == 0
+        {
+            return false;
+        }
+    }
+
+    !words.is_empty()
+}
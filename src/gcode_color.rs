@@ -0,0 +1,162 @@
+use clap::ValueEnum;
+use three_d::*;
+use crate::gcode::Move;
+use crate::parse_mesh::UNSPECIFIED_COLOR;
+
+/// Filament diameter used to turn extruded length into volume
+const FILAMENT_DIAMETER : f32 = 1.75;
+
+/// What decides the color of a printed line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorBy
+{
+    /// Slicer feature, such as outer wall, infill or support
+    Feature,
+    /// Extruder the line is printed with
+    Tool,
+    LayerHeight,
+    Speed,
+    /// Volumetric flow in mm³/s
+    Flow,
+    /// The model color, the same for every line
+    #[default]
+    Single,
+}
+
+/// Feature colors as shown by PrusaSlicer, with the names used by PrusaSlicer, Bambu Studio, OrcaSlicer and Cura
+const FEATURE_COLORS : [(&[&str], Srgba); 14] = [
+    (&["external perimeter", "outer wall", "wall outer"], Srgba { r: 0xFF, g: 0x7D, b: 0x38, a: 0xFF }),
+    (&["overhang perimeter", "overhang wall"], Srgba { r: 0x1F, g: 0x1F, b: 0xFF, a: 0xFF }),
+    (&["perimeter", "inner wall", "wall inner"], Srgba { r: 0xFF, g: 0xE6, b: 0x4D, a: 0xFF }),
+    (&["internal infill", "sparse infill", "fill"], Srgba { r: 0xB0, g: 0x30, b: 0x29, a: 0xFF }),
+    (&["solid infill", "internal solid infill"], Srgba { r: 0x96, g: 0x54, b: 0xCC, a: 0xFF }),
+    (&["top solid infill", "top surface", "skin"], Srgba { r: 0xF0, g: 0x40, b: 0x40, a: 0xFF }),
+    (&["bottom surface"], Srgba { r: 0x96, g: 0x54, b: 0xCC, a: 0xFF }),
+    (&["ironing"], Srgba { r: 0xFF, g: 0x8C, b: 0x69, a: 0xFF }),
+    (&["bridge infill", "bridge", "internal bridge"], Srgba { r: 0x4D, g: 0x80, b: 0xBA, a: 0xFF }),
+    (&["gap fill", "gap infill"], Srgba { r: 0xFF, g: 0xFF, b: 0xFF, a: 0xFF }),
+    (&["skirt", "brim", "skirt/brim"], Srgba { r: 0x00, g: 0x87, b: 0x6E, a: 0xFF }),
+    (&["support material", "support", "support transition"], Srgba { r: 0x00, g: 0xFF, b: 0x00, a: 0xFF }),
+    (&["support material interface", "support interface"], Srgba { r: 0x00, g: 0x80, b: 0x00, a: 0xFF }),
+    (&["wipe tower", "prime tower"], Srgba { r: 0xB3, g: 0xE3, b: 0xAB, a: 0xFF }),
+];
+
+/// Extruder colors, repeated for tools past the end
+const TOOL_COLORS : [Srgba; 8] = [
+    Srgba { r: 0xFF, g: 0x80, b: 0x00, a: 0xFF },
+    Srgba { r: 0x00, g: 0x96, b: 0xFF, a: 0xFF },
+    Srgba { r: 0x3C, g: 0xC8, b: 0x3C, a: 0xFF },
+    Srgba { r: 0xE6, g: 0x32, b: 0x32, a: 0xFF },
+    Srgba { r: 0xA0, g: 0x50, b: 0xE6, a: 0xFF },
+    Srgba { r: 0xF0, g: 0xD2, b: 0x00, a: 0xFF },
+    Srgba { r: 0x00, g: 0xC8, b: 0xC8, a: 0xFF },
+    Srgba { r: 0xF0, g: 0x64, b: 0xB4, a: 0xFF },
+];
+
+/// Gradient from the lowest to the highest value, for the modes that color by a number
+const GRADIENT : [Srgba; 5] = [
+    Srgba { r: 0x2B, g: 0x83, b: 0xBA, a: 0xFF },
+    Srgba { r: 0xAB, g: 0xDD, b: 0xA4, a: 0xFF },
+    Srgba { r: 0xFF, g: 0xFF, b: 0xBF, a: 0xFF },
+    Srgba { r: 0xFD, g: 0xAE, b: 0x61, a: 0xFF },
+    Srgba { r: 0xD7, g: 0x19, b: 0x1C, a: 0xFF },
+];
+
+/// Colors the printed lines of a file. Numeric modes need every line before their range is known,
+/// so lines are first given a value and colored once the file has been read.
+pub struct Colorizer
+{
+    color_by: ColorBy,
+    values: Vec<f32>,
+    /// Features change rarely compared to moves, so the last lookup is kept
+    last_feature: Option<(String, f32)>,
+}
+
+impl Colorizer
+{
+    pub fn new(color_by : ColorBy) -> Colorizer
+    {
+        Colorizer { color_by, values: Vec::new(), last_feature: None }
+    }
+
+    pub fn is_enabled(&self) -> bool
+    {
+        self.color_by != ColorBy::Single
+    }
+
+    /// Remembers the value of a printed line
+    pub fn add(&mut self, printed : &Move)
+    {
+        let value = match self.color_by
+        {
+            ColorBy::Feature => match &self.last_feature
+            {
+                Some((feature, index)) if feature == printed.feature => *index,
+                _ => {
+                    let index = feature_index(printed.feature).map_or(-1.0, |i| i as f32);
+                    self.last_feature = Some((printed.feature.to_string(), index));
+                    index
+                },
+            },
+            ColorBy::Tool => printed.tool as f32,
+            ColorBy::LayerHeight => printed.layer_height,
+            ColorBy::Speed => printed.feedrate / 60.0,
+            ColorBy::Flow => {
+                let length = (printed.to - printed.from).magnitude();
+                let area = std::f32::consts::PI * (FILAMENT_DIAMETER / 2.0).powi(2);
+                let seconds = length / (printed.feedrate / 60.0).max(f32::EPSILON);
+                printed.extrusion * area / seconds.max(f32::EPSILON)
+            },
+            ColorBy::Single => return,
+        };
+
+        self.values.push(value);
+    }
+
+    /// Colors of every added line, in the order they were added
+    pub fn colors(&self) -> Vec<Srgba>
+    {
+        match self.color_by
+        {
+            ColorBy::Feature => self.values
+                .iter()
+                .map(|v| if *v < 0.0 { UNSPECIFIED_COLOR } else { FEATURE_COLORS[*v as usize].1 })
+                .collect(),
+            ColorBy::Tool => self.values.iter().map(|v| TOOL_COLORS[*v as usize % TOOL_COLORS.len()]).collect(),
+            ColorBy::Single => Vec::new(),
+            _ => {
+                let (min, max) = self.values.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(*v), max.max(*v)));
+
+                self.values
+                    .iter()
+                    .map(|v| gradient(if max - min > f32::EPSILON { (v - min) / (max - min) } else { 0.5 }))
+                    .collect()
+            },
+        }
+    }
+}
+
+/// Feature names differ in case and separators between slicers, `WALL-OUTER` and `Outer wall` are the same
+fn feature_index(feature : &str) -> Option<usize>
+{
+    let name = feature.to_lowercase().replace(['-', '_'], " ");
+
+    FEATURE_COLORS.iter().position(|(names, _)| names.contains(&name.as_str()))
+}
+
+/// Outer walls, the first entry of the feature table, are where seams are placed
+pub fn is_outer_wall(feature : &str) -> bool
+{
+    feature_index(feature) == Some(0)
+}
+
+fn gradient(t : f32) -> Srgba
+{
+    let position = t.clamp(0.0, 1.0) * (GRADIENT.len() - 1) as f32;
+    let index = (position as usize).min(GRADIENT.len() - 2);
+    let fraction = position - index as f32;
+    let (a, b) = (GRADIENT[index], GRADIENT[index + 1]);
+    let mix = |a : u8, b : u8| (a as f32 + (b as f32 - a as f32) * fraction).round() as u8;
+
+    Srgba::new_opaque(mix(a.r, b.r), mix(a.g, b.g), mix(a.b, b.b))
+}
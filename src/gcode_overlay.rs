@@ -0,0 +1,148 @@
+use clap::ValueEnum;
+use three_d::*;
+use crate::gcode::{self, Move};
+use crate::gcode_color;
+use crate::parse_mesh::Toolpath;
+
+/// Z moves up to this much above the height printing continues at are not a hop
+const Z_HOP_TOLERANCE : f32 = 1e-3;
+
+/// Extra information drawn over the printed lines of a G-code file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overlay
+{
+    /// Moves without extrusion, as thin lines
+    Travel,
+    /// Retractions and the unretractions that follow them
+    Retract,
+    /// Places where the nozzle is lifted for a travel move
+    ZHop,
+    /// Starts of the outer walls
+    Seam,
+}
+
+/// A point of interest in a toolpath, drawn as a small dot
+#[derive(Clone, Copy)]
+pub struct Marker
+{
+    /// Position in render coordinates
+    pub position: Vec3,
+    pub color: Srgba,
+}
+
+// Option colors as shown by PrusaSlicer
+pub const TRAVEL_COLOR : Srgba = Srgba { r: 0x38, g: 0x48, b: 0x9B, a: 0xFF };
+const RETRACT_COLOR : Srgba = Srgba { r: 0xCD, g: 0x22, b: 0xD6, a: 0xFF };
+const UNRETRACT_COLOR : Srgba = Srgba { r: 0x49, g: 0xAD, b: 0xCF, a: 0xFF };
+const Z_HOP_COLOR : Srgba = Srgba { r: 0xFF, g: 0xA5, b: 0x00, a: 0xFF };
+const SEAM_COLOR : Srgba = Srgba { r: 0xE6, g: 0xE6, b: 0xE6, a: 0xFF };
+
+/// Collects the enabled overlays of a file. Moves before the first extrusion are the start sequence of
+/// the printer, such as homing and heating, and are left out.
+pub struct OverlayTracer
+{
+    travel: bool,
+    retract: bool,
+    z_hop: bool,
+    seam: bool,
+    printing: bool,
+    /// The last move extruded an outer wall, the next one continues the same loop
+    outer_wall: bool,
+    /// Start of the current lift and the highest point it reached
+    hop: Option<(Vec3, f32)>,
+    /// Features change rarely compared to moves, so the last lookup is kept
+    last_feature: Option<(String, bool)>,
+}
+
+impl OverlayTracer
+{
+    pub fn new(overlays : &[Overlay]) -> OverlayTracer
+    {
+        OverlayTracer {
+            travel: overlays.contains(&Overlay::Travel),
+            retract: overlays.contains(&Overlay::Retract),
+            z_hop: overlays.contains(&Overlay::ZHop),
+            seam: overlays.contains(&Overlay::Seam),
+            printing: false,
+            outer_wall: false,
+            hop: None,
+            last_feature: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool
+    {
+        self.travel || self.retract || self.z_hop || self.seam
+    }
+
+    /// Adds the overlays a move starts or ends to the toolpath
+    pub fn add(&mut self, m : &Move, toolpath : &mut Toolpath)
+    {
+        let extruding = m.is_extrusion();
+        let from = gcode::render_coordinates(m.from);
+        let to = gcode::render_coordinates(m.to);
+
+        if extruding
+        {
+            // A lift is a hop when printing continues below it, moving up to the next layer is not
+            if let Some((start, z)) = self.hop.take()
+            {
+                if self.z_hop && m.to.z < z - Z_HOP_TOLERANCE
+                {
+                    toolpath.markers.push(Marker { position: start, color: Z_HOP_COLOR });
+                }
+            }
+
+            let outer_wall = self.is_outer_wall(m.feature);
+
+            if self.seam && outer_wall && !self.outer_wall
+            {
+                toolpath.markers.push(Marker { position: from, color: SEAM_COLOR });
+            }
+
+            self.outer_wall = outer_wall;
+            self.printing = true;
+            return;
+        }
+
+        self.outer_wall = false;
+
+        if !self.printing
+        {
+            return;
+        }
+
+        if m.to.z > m.from.z
+        {
+            let hop = self.hop.get_or_insert((from, m.to.z));
+            hop.1 = hop.1.max(m.to.z);
+        }
+
+        if self.travel && m.from != m.to
+        {
+            toolpath.travels.push((from, to));
+        }
+
+        if self.retract && m.extrusion < 0.0
+        {
+            toolpath.markers.push(Marker { position: from, color: RETRACT_COLOR });
+        }
+        else if self.retract && m.extrusion > 0.0
+        {
+            toolpath.markers.push(Marker { position: to, color: UNRETRACT_COLOR });
+        }
+    }
+
+    fn is_outer_wall(&mut self, feature : &str) -> bool
+    {
+        match &self.last_feature
+        {
+            Some((last, outer_wall)) if last == feature => *outer_wall,
+            _ => {
+                let outer_wall = gcode_color::is_outer_wall(feature);
+                self.last_feature = Some((feature.to_string(), outer_wall));
+                outer_wall
+            },
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Read};
+use std::path::PathBuf;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use regex::Regex;
+use zip::ZipArchive;
+use crate::bgcode;
+use crate::input_format;
+use crate::parse_mesh::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat
+{
+    Png,
+    Jpg,
+    Qoi,
+}
+
+pub struct Thumbnail
+{
+    pub format: ThumbnailFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Reads every embedded thumbnail from a .gcode, .gcode.zip or .bgcode file, recognized by their contents
+pub fn read_file(path : &str) -> Result<Vec<Thumbnail>, ParseError>
+{
+    let data = fs::read(path)?;
+
+    if bgcode::is_bgcode(&data)
+    {
+        return Ok(bgcode::parse(&data)?.thumbnails);
+    }
+    else if input_format::is_zip(&data)
+    {
+        let mut zip = ZipArchive::new(Cursor::new(data))?;
+
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.name().ends_with(".gcode") {
+                return read_thumbnails(&mut file);
+            }
+        }
+
+        return Err(ParseError::MeshConvertError(String::from("Failed to find .gcode file in zip")));
+    }
+
+    read_thumbnails(&mut Cursor::new(data))
+}
+
+/// Collects the base64 encoded thumbnails slicers write as comment blocks, e.g.
+/// `; thumbnail begin 300x300 12345` ... `; thumbnail end`
+pub fn read_thumbnails<R>(reader : &mut R) -> Result<Vec<Thumbnail>, ParseError>
+where
+    R: Read
+{
+    let reader = io::BufReader::new(reader);
+    let regex_begin = Regex::new(r"^;\s*(?i:thumbnail(?:_(PNG|JPG|QOI))?)\s+begin\s+(\d+)\s*x\s*(\d+)").unwrap();
+    let regex_end = Regex::new(r"^;\s*(?i:thumbnail(?:_(?:PNG|JPG|QOI))?)\s+end").unwrap();
+
+    let mut thumbnails = Vec::new();
+    let mut current : Option<(ThumbnailFormat, u32, u32, String)> = None;
+
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim();
+
+        if let Some((format, width, height, base64)) = current.as_mut()
+        {
+            if regex_end.is_match(line)
+            {
+                let data = STANDARD.decode(base64.as_bytes())
+                    .map_err(|e| ParseError::ParseError(format!("Invalid thumbnail data: {}", e)))?;
+
+                thumbnails.push(Thumbnail { format: *format, width: *width, height: *height, data });
+                current = None;
+            }
+            else
+            {
+                base64.push_str(line.trim_start_matches(';').trim());
+            }
+        }
+        else if let Some(caps) = regex_begin.captures(line)
+        {
+            let format = match caps.get(1).map(|f| f.as_str().to_uppercase()).as_deref()
+            {
+                Some("JPG") => ThumbnailFormat::Jpg,
+                Some("QOI") => ThumbnailFormat::Qoi,
+                _ => ThumbnailFormat::Png,
+            };
+
+            current = Some((
+                format,
+                caps.get(2).unwrap().as_str().parse().unwrap_or(0),
+                caps.get(3).unwrap().as_str().parse().unwrap_or(0),
+                String::new(),
+            ));
+        }
+    }
+
+    Ok(thumbnails)
+}
+
+/// Picks the thumbnail whose size is closest to the requested size, preferring the larger one on a tie
+pub fn closest_thumbnail(thumbnails : &[Thumbnail], width : u32, height : u32) -> Option<&Thumbnail>
+{
+    thumbnails
+        .iter()
+        .min_by_key(|t| (t.width.abs_diff(width) + t.height.abs_diff(height), Reverse(t.width as u64 * t.height as u64)))
+}
+
+/// Replaces all thumbnail blocks in a .gcode file with the given thumbnails.
+/// The new file is written next to the original and renamed over it, so readers never see a partial file.
+pub fn write_file(path : &str, thumbnails : &[Thumbnail]) -> Result<(), ParseError>
+{
+    let data = fs::read(path)?;
+    let regex_begin = Regex::new(r"^;\s*(?i:thumbnail(?:_(?:PNG|JPG|QOI))?)\s+begin\s").unwrap();
+    let regex_end = Regex::new(r"^;\s*(?i:thumbnail(?:_(?:PNG|JPG|QOI))?)\s+end").unwrap();
+    let line_ending = if data.windows(2).any(|w| w == b"\r\n") { "\r\n" } else { "\n" };
+
+    let mut output = Vec::with_capacity(data.len());
+    let mut insert_at = None;
+    let mut in_block = false;
+    let mut skip_separator = false;
+    let mut last_line_start = 0;
+    // End of the comment lines at the start of the file, such as the `; generated by` line
+    let mut header_end = 0;
+    let mut in_header = true;
+
+    for line in data.split_inclusive(|c| *c == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+
+        if in_block
+        {
+            in_block = !regex_end.is_match(text);
+            skip_separator = !in_block;
+        }
+        else if regex_begin.is_match(text)
+        {
+            // Drop the empty comment line slicers put around each block
+            if String::from_utf8_lossy(&output[last_line_start..]).trim() == ";"
+            {
+                output.truncate(last_line_start);
+            }
+
+            insert_at.get_or_insert(output.len());
+            in_block = true;
+        }
+        else if skip_separator && text == ";"
+        {
+            skip_separator = false;
+        }
+        else
+        {
+            skip_separator = false;
+            last_line_start = output.len();
+            output.extend_from_slice(line);
+
+            in_header = in_header && text.starts_with(';');
+
+            if in_header
+            {
+                header_end = output.len();
+            }
+        }
+    }
+
+    let mut block = encode_thumbnails(thumbnails, line_ending);
+
+    // Files without thumbnails get them after their header comments
+    let insert_at = insert_at.unwrap_or(header_end);
+
+    if insert_at > 0 && output[insert_at - 1] != b'\n'
+    {
+        block.insert_str(0, line_ending);
+    }
+
+    output.splice(insert_at..insert_at, block.into_bytes());
+
+    let mut temp_path = PathBuf::from(path).into_os_string();
+    temp_path.push(".tmp");
+
+    // The new file would otherwise get default permissions, e.g. losing the group write access of a shared folder
+    let permissions = fs::metadata(path)?.permissions();
+    fs::write(&temp_path, &output)?;
+    if let Err(e) = fs::set_permissions(&temp_path, permissions).and_then(|_| fs::rename(&temp_path, path))
+    {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+fn encode_thumbnails(thumbnails : &[Thumbnail], line_ending : &str) -> String
+{
+    let mut block = String::new();
+
+    for thumbnail in thumbnails
+    {
+        let tag = match thumbnail.format
+        {
+            ThumbnailFormat::Png => "thumbnail",
+            ThumbnailFormat::Jpg => "thumbnail_JPG",
+            ThumbnailFormat::Qoi => "thumbnail_QOI",
+        };
+
+        let base64 = STANDARD.encode(&thumbnail.data);
+
+        block.push_str(&format!(";{}", line_ending));
+        block.push_str(&format!("; {} begin {}x{} {}{}", tag, thumbnail.width, thumbnail.height, base64.len(), line_ending));
+
+        // Same line length as PrusaSlicer, so firmware with small line buffers can read it
+        for chunk in base64.as_bytes().chunks(78)
+        {
+            block.push_str(&format!("; {}{}", std::str::from_utf8(chunk).unwrap(), line_ending));
+        }
+
+        block.push_str(&format!("; {} end{}", tag, line_ending));
+        block.push_str(&format!(";{}", line_ending));
+    }
+
+    block
+}
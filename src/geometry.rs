@@ -0,0 +1,109 @@
+use three_d::*;
+
+// Triangulation of polygons, shared by the formats that store faces with more than three corners
+
+/// Splits a polygon into triangles, returned as indices into the polygon
+pub fn triangulate(vertices : &[Vector3<f64>], polygon : &[usize]) -> Vec<[usize; 3]>
+{
+    if polygon.len() < 3
+    {
+        return Vec::new();
+    }
+
+    if polygon.len() == 3
+    {
+        return vec![[0, 1, 2]];
+    }
+
+    let normal = newell_normal(&polygon.iter().map(|i| vertices[*i]).collect::<Vec<_>>());
+
+    // Project onto the plane the polygon is largest in, keeping the winding counter clockwise
+    let (abs_x, abs_y, abs_z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+    let points : Vec<(f64, f64)> = polygon
+        .iter()
+        .map(|i| {
+            let v = vertices[*i];
+
+            if abs_x >= abs_y && abs_x >= abs_z
+            {
+                if normal.x > 0.0 { (v.y, v.z) } else { (v.z, v.y) }
+            }
+            else if abs_y >= abs_z
+            {
+                if normal.y > 0.0 { (v.z, v.x) } else { (v.x, v.z) }
+            }
+            else
+            {
+                if normal.z > 0.0 { (v.x, v.y) } else { (v.y, v.x) }
+            }
+        })
+        .collect();
+
+    ear_clip(&points)
+}
+
+/// Ear clipping of a counter clockwise polygon. Falls back to a fan for whatever is left when no ear can be found,
+/// which only happens for degenerate or self intersecting polygons.
+fn ear_clip(points : &[(f64, f64)]) -> Vec<[usize; 3]>
+{
+    let mut remaining : Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|i| {
+            let a = remaining[(i + count - 1) % count];
+            let b = remaining[*i];
+            let c = remaining[(i + 1) % count];
+
+            cross(points[a], points[b], points[c]) > 0.0
+                && !remaining
+                    .iter()
+                    // Polygons with bridged holes visit some positions twice
+                    .filter(|p| points[**p] != points[a] && points[**p] != points[b] && points[**p] != points[c])
+                    .any(|p| in_triangle(points[*p], points[a], points[b], points[c]))
+        });
+
+        match ear
+        {
+            Some(i) => {
+                triangles.push([remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+
+    triangles
+}
+
+fn cross(a : (f64, f64), b : (f64, f64), c : (f64, f64)) -> f64
+{
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn in_triangle(p : (f64, f64), a : (f64, f64), b : (f64, f64), c : (f64, f64)) -> bool
+{
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// Newell's method, works for concave and slightly non planar polygons. The length is twice the area.
+pub fn newell_normal(polygon : &[Vector3<f64>]) -> Vector3<f64>
+{
+    let mut normal = Vector3::new(0f64, 0f64, 0f64);
+
+    for i in 0..polygon.len() {
+        let current = polygon[i];
+        let next = polygon[(i + 1) % polygon.len()];
+
+        normal.x += (current.y - next.y) * (current.z + next.z);
+        normal.y += (current.z - next.z) * (current.x + next.x);
+        normal.z += (current.x - next.x) * (current.y + next.y);
+    }
+
+    normal
+}
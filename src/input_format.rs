@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use clap::ValueEnum;
+use regex::Regex;
+use zip::ZipArchive;
+use crate::archive;
+use crate::bgcode;
+use crate::msla;
+use crate::parse_mesh::ParseError;
+
+/// Amount of bytes read from the start of a file to recognize its format
+const SNIFF_SIZE : u64 = 64 * 1024;
+const ZIP_MAGIC : &[u8] = b"PK\x03\x04";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat
+{
+    Stl,
+    #[value(name = "3mf")]
+    ThreeMf,
+    Obj,
+    Gcode,
+    Bgcode,
+    Ply,
+    /// Both .gltf and .glb
+    Gltf,
+    Step,
+    Amf,
+    Off,
+    #[value(name = "3ds")]
+    ThreeDs,
+    Dae,
+    /// Resin slice files, sl1/sl1s as well as the binary chitubox, photon workshop and goo formats
+    Msla,
+    /// zip, tar and 7z archives as well as gz, xz and bz2 compressed files, models inside are found by their contents
+    Archive,
+}
+
+/// File name suffixes of every format. Longer suffixes come first, so `.tar.gz` is stripped as a whole.
+const EXTENSIONS : [(&str, InputFormat); 31] = [
+    (".tar.gz", InputFormat::Archive),
+    (".tar.xz", InputFormat::Archive),
+    (".tar.bz2", InputFormat::Archive),
+    (".stl", InputFormat::Stl),
+    (".3mf", InputFormat::ThreeMf),
+    (".obj", InputFormat::Obj),
+    (".gcode", InputFormat::Gcode),
+    (".bgcode", InputFormat::Bgcode),
+    (".ply", InputFormat::Ply),
+    (".gltf", InputFormat::Gltf),
+    (".glb", InputFormat::Gltf),
+    (".step", InputFormat::Step),
+    (".stp", InputFormat::Step),
+    (".amf", InputFormat::Amf),
+    (".off", InputFormat::Off),
+    (".3ds", InputFormat::ThreeDs),
+    (".dae", InputFormat::Dae),
+    (".sl1", InputFormat::Msla),
+    (".sl1s", InputFormat::Msla),
+    (".ctb", InputFormat::Msla),
+    (".cbddlp", InputFormat::Msla),
+    (".photon", InputFormat::Msla),
+    (".pwmx", InputFormat::Msla),
+    (".goo", InputFormat::Msla),
+    (".zip", InputFormat::Archive),
+    (".tar", InputFormat::Archive),
+    (".tgz", InputFormat::Archive),
+    (".gz", InputFormat::Archive),
+    (".xz", InputFormat::Archive),
+    (".bz2", InputFormat::Archive),
+    (".7z", InputFormat::Archive),
+];
+
+impl InputFormat
+{
+    pub fn is_gcode(&self) -> bool
+    {
+        matches!(self, InputFormat::Gcode | InputFormat::Bgcode)
+    }
+
+    /// Formats that use z as the up axis, the renderer uses y
+    pub fn is_z_up(&self) -> bool
+    {
+        !matches!(self, InputFormat::Gltf | InputFormat::Dae) && !self.is_gcode()
+    }
+}
+
+pub fn is_zip(data : &[u8]) -> bool
+{
+    data.starts_with(ZIP_MAGIC)
+}
+
+/// Guesses the format from the file name alone
+pub fn from_path(path : &str) -> Option<InputFormat>
+{
+    let path = path.to_lowercase();
+
+    EXTENSIONS
+        .iter()
+        .find(|(extension, _)| path.ends_with(extension))
+        .map(|(_, format)| *format)
+}
+
+/// Removes the format suffix from a file name, including double suffixes such as `.stl.zip`
+pub fn strip_extension(filename : &str) -> &str
+{
+    let lower = filename.to_lowercase();
+
+    match EXTENSIONS.iter().find(|(extension, _)| lower.ends_with(extension)) {
+        Some((extension, _)) => &filename[..filename.len() - extension.len()],
+        None => Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or(filename),
+    }
+}
+
+/// Recognizes the format of a file by its contents. The file name is only used when the contents are ambiguous.
+pub fn detect(path : &str) -> Result<InputFormat, ParseError>
+{
+    let mut handle = File::open(path)?;
+    let size = handle.metadata()?.len();
+    let mut head = Vec::new();
+    (&mut handle).take(SNIFF_SIZE).read_to_end(&mut head)?;
+
+    let hint = from_path(path);
+
+    let format = if is_zip(&head)
+    {
+        detect_zip(handle)?.or(Some(InputFormat::Archive))
+    }
+    else if archive::is_archive(&head)
+    {
+        Some(InputFormat::Archive)
+    }
+    else
+    {
+        detect_binary(&head, size).or_else(|| detect_text(&String::from_utf8_lossy(&head))).or(hint)
+    };
+
+    format.ok_or_else(|| ParseError::ParseError(String::from("Unknown file type")))
+}
+
+/// Zip based model formats are recognized by the files inside them, any other zip is an archive.
+/// A compressed amf file holds nothing but the amf file, sl1 files hold their settings and a numbered png for every layer.
+pub fn detect_zip<R : Read + Seek>(handle : R) -> Result<Option<InputFormat>, ParseError>
+{
+    let zip = ZipArchive::new(handle)?;
+    let names : Vec<String> = zip.file_names().map(|n| n.to_lowercase()).collect();
+    let contains = |extension : &str| names.iter().any(|n| n.ends_with(extension));
+    let is_layer = |name : &String| name.strip_suffix(".png").is_some_and(|stem| !stem.contains('/') && stem.ends_with(|c : char| c.is_ascii_digit()));
+
+    Ok(if contains(".model")
+    {
+        Some(InputFormat::ThreeMf)
+    }
+    else if names.len() == 1 && names[0].ends_with(".amf")
+    {
+        Some(InputFormat::Amf)
+    }
+    else if names.iter().any(|n| n == "config.ini" || n == "prusaslicer.ini") && names.iter().any(is_layer)
+    {
+        Some(InputFormat::Msla)
+    }
+    else
+    {
+        None
+    })
+}
+
+fn detect_binary(head : &[u8], size : u64) -> Option<InputFormat>
+{
+    if bgcode::is_bgcode(head)
+    {
+        return Some(InputFormat::Bgcode);
+    }
+
+    if head.starts_with(b"glTF")
+    {
+        return Some(InputFormat::Gltf);
+    }
+
+    if msla::is_msla(head)
+    {
+        return Some(InputFormat::Msla);
+    }
+
+    // 3ds files are a single main chunk spanning the whole file
+    if head.len() >= 6 && head[..2] == [0x4D, 0x4D] && u32::from_le_bytes([head[2], head[3], head[4], head[5]]) as u64 == size
+    {
+        return Some(InputFormat::ThreeDs);
+    }
+
+    // Binary stl headers can start with "solid" too, the triangle count matching the file size is more reliable
+    if head.len() >= 84
+    {
+        let triangles = u32::from_le_bytes([head[80], head[81], head[82], head[83]]) as u64;
+
+        if 84 + triangles * 50 == size
+        {
+            return Some(InputFormat::Stl);
+        }
+    }
+
+    None
+}
+
+fn detect_text(head : &str) -> Option<InputFormat>
+{
+    let text = head.trim_start_matches('\u{feff}').trim_start();
+
+    if text.starts_with("solid")
+    {
+        return Some(InputFormat::Stl);
+    }
+
+    if text.starts_with("ply") && text[3..].starts_with(|c : char| c.is_ascii_whitespace())
+    {
+        return Some(InputFormat::Ply);
+    }
+
+    if text.starts_with("ISO-10303-21")
+    {
+        return Some(InputFormat::Step);
+    }
+
+    if text.starts_with('{') && text.contains("\"asset\"")
+    {
+        return Some(InputFormat::Gltf);
+    }
+
+    if text.starts_with('<')
+    {
+        let root = Regex::new(r"<([A-Za-z][\w:.-]*)").unwrap();
+
+        // The first element that is not a declaration, comment or doctype
+        return match root.captures(text).map(|c| c.get(1).unwrap().as_str())
+        {
+            Some("amf") => Some(InputFormat::Amf),
+            Some("COLLADA") => Some(InputFormat::Dae),
+            _ => None,
+        };
+    }
+
+    let regex_off = Regex::new(r"^(ST)?C?N?4?n?OFF\b").unwrap();
+    let regex_gcode = Regex::new(r"^[GMT]\d+\b").unwrap();
+    let obj_keywords = ["v", "vt", "vn", "vp", "f", "l", "p", "o", "g", "s", "mtllib", "usemtl"];
+    let mut gcode_comments = false;
+
+    // The first line that is not a comment decides, the last one may be cut off
+    for line in text.lines().take(text.lines().count().saturating_sub(1).max(1)) {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#')
+        {
+            continue;
+        }
+
+        if line.starts_with(';')
+        {
+            gcode_comments = true;
+            continue;
+        }
+
+        if regex_off.is_match(line)
+        {
+            return Some(InputFormat::Off);
+        }
+
+        if regex_gcode.is_match(&line.to_uppercase())
+        {
+            return Some(InputFormat::Gcode);
+        }
+
+        if line.split_whitespace().next().is_some_and(|k| obj_keywords.contains(&k))
+        {
+            return Some(InputFormat::Obj);
+        }
+
+        return None;
+    }
+
+    if gcode_comments { Some(InputFormat::Gcode) } else { None }
+}
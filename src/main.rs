@@ -1,16 +1,39 @@
 use clap::Parser;
-use image::{imageops::FilterType::Triangle, ImageReader};
+use image::{imageops::FilterType::Triangle, DynamicImage, ImageFormat, ImageReader, RgbaImage};
 use std::{ffi::OsString, io::Read, num::ParseIntError, path::PathBuf};
 use clap::ValueEnum;
 use std::path;
 use three_d::*;
 use three_d_asset::io::Serialize;
+use input_format::InputFormat;
 use std::fs::File;
 use zip::{result::ZipError, ZipArchive};
 use std::io::Cursor;
 
+mod archive;
+mod bgcode;
+mod contact_sheet;
+mod gcode;
+mod gcode_color;
+mod gcode_overlay;
+mod gcode_thumbnail;
+mod geometry;
+mod input_format;
+mod mesh_stats;
+mod msla;
+mod parse_3ds;
+mod parse_3mf;
+mod parse_amf;
+mod parse_dae;
+mod parse_gltf;
 mod parse_mesh;
+mod parse_obj;
+mod parse_off;
+mod parse_ply;
+mod parse_step;
+mod print_metadata;
 mod solid_material;
+mod threemf_thumbnail;
 
 #[derive(Parser, Debug)]
 #[command(name = "mesh-thumbnail", about = "3D file thumbnail generator", version = "0.1")]
@@ -53,6 +76,10 @@ struct Args {
     #[arg(required = true)]
     files: Vec<String>,
 
+    /// Input file format, detected from the file contents when not set
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormat>,
+
     /// Fallback on thumbnail inside 3mf files
     #[arg(long, default_value_t = false)]
     fallback_3mf_thumbnail: bool,
@@ -61,6 +88,22 @@ struct Args {
     #[arg(long, default_value_t = false)]
     prefer_3mf_thumbnail: bool,
 
+    /// Fallback on thumbnail embedded in gcode files
+    #[arg(long, default_value_t = false)]
+    fallback_gcode_thumbnail: bool,
+
+    /// Prefer thumbnail embedded in gcode files over rendering the toolpath
+    #[arg(long, default_value_t = false)]
+    prefer_gcode_thumbnail: bool,
+
+    /// Fallback on the preview image in resin slice files
+    #[arg(long, default_value_t = false)]
+    fallback_msla_thumbnail: bool,
+
+    /// Prefer the preview image in resin slice files over rendering the layers
+    #[arg(long, default_value_t = false)]
+    prefer_msla_thumbnail: bool,
+
     #[arg(long, default_value_t = 1)]
     /// Amount of images to generate per file
     images_per_file: u32,
@@ -68,12 +111,104 @@ struct Args {
     #[arg(long, default_value_t = 1.0)]
     /// Scale factor for the camera
     inverse_zoom: f32,
+
+    /// Only render the archive entry matching this glob, e.g. "*.stl" or "parts/**/base.3mf"
+    #[arg(long)]
+    archive_entry: Option<String>,
+
+    /// Only render the object or group with this name
+    #[arg(long)]
+    isolate_object: Option<String>,
+
+    /// Render the object or group with this name in the highlight color
+    #[arg(long)]
+    highlight_object: Option<String>,
+
+    /// Highlight color in hex format
+    #[arg(long, default_value = "FF8000")]
+    highlight_color: String,
+
+    /// Write rendered thumbnails into gcode files instead of saving images, files that already have thumbnails are only updated with --overwrite
+    #[arg(long, default_value_t = false, conflicts_with = "write_3mf_thumbnail")]
+    write_gcode_thumbnail: bool,
+
+    /// Thumbnail sizes to write into gcode files
+    #[arg(long, value_delimiter = ',', default_value = "32x32,220x124,300x300", value_parser = parse_thumbnail_size)]
+    gcode_thumbnail_sizes: Vec<ThumbnailSize>,
+
+    /// Image format of thumbnails written into gcode files
+    #[arg(long, default_value_t = Format::Png, value_enum)]
+    gcode_thumbnail_format: Format,
+
+    /// Write the rendered thumbnail into 3mf files instead of saving images, files that already have one are only updated with --overwrite
+    #[arg(long, default_value_t = false)]
+    write_3mf_thumbnail: bool,
+
+    /// What decides the color of the printed lines in gcode files
+    #[arg(long, default_value_t = gcode_color::ColorBy::Single, value_enum)]
+    gcode_color_by: gcode_color::ColorBy,
+
+    /// Draw these overlays over the toolpath of gcode files
+    #[arg(long, value_delimiter = ',', value_enum)]
+    gcode_overlays: Vec<gcode_overlay::Overlay>,
+
+    /// Only render gcode files up to this layer, counted from 1. The last layer is drawn in the highlight color.
+    #[arg(long)]
+    gcode_max_layer: Option<usize>,
+
+    /// Only render gcode files up to this height in mm. The last layer is drawn in the highlight color.
+    #[arg(long)]
+    gcode_max_z: Option<f32>,
+
+    /// Render gcode files as they look at these percentages of their layers, one image per percentage
+    #[arg(long, value_delimiter = ',')]
+    gcode_progress: Vec<u32>,
+
+    /// Write the print settings and estimates of gcode, bgcode and 3mf files to a .metadata.json file next to the image
+    #[arg(long, default_value_t = false)]
+    print_metadata: bool,
+
+    /// Write the bounding box, volume, surface area, triangle count and shell count of rendered meshes to a .stats.json file next to the image
+    #[arg(long, default_value_t = false)]
+    mesh_stats: bool,
+
+    /// Render every model of an archive, or every part of a multi part file, into one grid image
+    #[arg(long, default_value_t = false)]
+    contact_sheet: bool,
+
+    /// Write the name of each model or part under its cell of the contact sheet
+    #[arg(long, default_value_t = false)]
+    contact_sheet_captions: bool,
 }
 
 fn parse_hex_color(s: &str) -> Result<u32, ParseIntError> {
     u32::from_str_radix(s, 16)
 }
 
+fn hex_to_srgba(s: &str) -> Srgba {
+    let color = parse_hex_color(s).unwrap();
+    Srgba::new_opaque((color >> 16 & 0xFF) as u8, (color >> 8 & 0xFF) as u8, (color & 0xFF) as u8)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ThumbnailSize {
+    width: u32,
+    height: u32,
+}
+
+fn parse_thumbnail_size(s: &str) -> Result<ThumbnailSize, String> {
+    let (width, height) = s.split_once('x').ok_or(format!("Expected WIDTHxHEIGHT, got {}", s))?;
+    let width = width.trim().parse::<u32>().map_err(|e| e.to_string())?;
+    let height = height.trim().parse::<u32>().map_err(|e| e.to_string())?;
+
+    if width == 0 || height == 0
+    {
+        return Err(String::from("Thumbnail size must be larger than 0"));
+    }
+
+    Ok(ThumbnailSize { width, height })
+}
+
 #[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
 enum Format {
     Jpg,
@@ -97,6 +232,16 @@ fn main() {
         args.fallback_3mf_thumbnail = false;
     }
 
+    if args.prefer_gcode_thumbnail
+    {
+        args.fallback_gcode_thumbnail = false;
+    }
+
+    if args.prefer_msla_thumbnail
+    {
+        args.fallback_msla_thumbnail = false;
+    }
+
     if args.images_per_file < 1
     {
         args.images_per_file = 1;
@@ -114,115 +259,407 @@ fn main() {
     let context = HeadlessContext::new().unwrap();
     let alpha = if args.format == Format::Jpg { 0.8 } else { 0.0 };
 
-    // Create a color texture to render into
-    let mut texture = Texture2D::new_empty::<[u8; 4]>(
-        &context,
-        viewport.width,
-        viewport.height,
-        Interpolation::Nearest,
-        Interpolation::Nearest,
-        None,
-        Wrapping::ClampToEdge,
-        Wrapping::ClampToEdge,
-    );
-        
-    // Also create a depth texture to support depth testing
-    let mut depth_texture = DepthTexture2D::new::<f32>(
-        &context,
-        viewport.width,
-        viewport.height,
-        Wrapping::ClampToEdge,
-        Wrapping::ClampToEdge,
-    );
+    let (mut texture, mut depth_texture) = create_render_textures(&context, &viewport);
+    let gcode_options = parse_mesh::GcodeOptions { color_by: args.gcode_color_by, overlays: args.gcode_overlays.clone() };
 
-    for file in args.files
+    for file in &args.files
     {
-        let absolute_path = path::absolute(&file).unwrap();
-        let mut extension = absolute_path.extension().take().unwrap().to_str().take().unwrap();
+        let absolute_path = path::absolute(file).unwrap();
         let filename = absolute_path.file_name().take().unwrap().to_str().take().unwrap();
 
-        if filename.ends_with(".stl.zip")
+        let format = match args.input_format.map_or_else(|| input_format::detect(absolute_path.to_str().unwrap()), Ok)
         {
-            extension = "stl.zip";
-        }
+            Ok(format) => format,
+            Err(e) => {
+                println!("Error while converting {}: {}.", filename, e.to_string());
+                continue;
+            }
+        };
 
-        if filename.ends_with(".obj.zip")
+        if args.write_gcode_thumbnail
         {
-            extension = "obj.zip";
+            if format != InputFormat::Gcode
+            {
+                println!("Writing thumbnails is only supported for .gcode files, skipping {}...", filename);
+                continue;
+            }
+
+            if !args.overwrite && gcode_thumbnail::read_file(absolute_path.to_str().unwrap()).is_ok_and(|t| !t.is_empty())
+            {
+                println!("{} already contains thumbnails, skipping...", filename);
+                continue;
+            }
         }
 
-        if filename.ends_with(".gcode.zip")
+        if args.write_3mf_thumbnail
         {
-            extension = "gcode.zip";
+            if format != InputFormat::ThreeMf
+            {
+                println!("Writing thumbnails is only supported for .3mf files, skipping {}...", filename);
+                continue;
+            }
+
+            if !args.overwrite && threemf_thumbnail::has_thumbnail(absolute_path.to_str().unwrap()).unwrap_or(false)
+            {
+                println!("{} already contains a thumbnail, skipping...", filename);
+                continue;
+            }
         }
 
-        let filename_image = format!("{}{}", &filename[..filename.len() - extension.len()] ,args.format.to_string());
-        let image_path = PathBuf::from(args.outdir.clone()).join(filename_image);
-        let image_path_str = image_path.to_str().take().unwrap();
+        let stem = input_format::strip_extension(filename).to_string();
 
-        if !args.overwrite && path::Path::new(image_path_str).exists()
+        // Archives are rendered entry by entry, the extracted files live until all entries are done
+        let (_extracted, items) = if format == InputFormat::Archive
         {
-            println!("Path {} already exists, skipping {}...", image_path_str, filename);
-            continue;
+            match archive::extract(absolute_path.to_str().unwrap(), args.archive_entry.as_deref())
+            {
+                Ok(extracted) if args.contact_sheet && extracted.entries.len() > 1 => {
+                    let image_path = PathBuf::from(args.outdir.clone()).join(format!("{}.{}", stem, args.format.to_string()));
+
+                    if !args.overwrite && image_path.exists()
+                    {
+                        println!("Path {} already exists, skipping {}...", image_path.to_str().unwrap(), filename);
+                        continue;
+                    }
+
+                    let cells = extracted.entries
+                        .iter()
+                        .filter_map(|e| match parse_model(&e.path, e.format, args.isolate_object.as_deref(), &gcode_options) {
+                            Ok(model) => Some((input_format::strip_extension(&e.name).to_string(), model, e.format)),
+                            Err(err) => {
+                                println!("Error while converting {} in {}: {}.", e.name, filename, err.to_string());
+                                None
+                            }
+                        })
+                        .collect();
+
+                    if let Err(e) = render_contact_sheet(&context, cells, &args, alpha, &image_path)
+                    {
+                        println!("Error while rendering contact sheet for {}: {}.", filename, e.to_string());
+                    }
+
+                    continue;
+                },
+                Ok(extracted) => {
+                    let single = extracted.entries.len() == 1;
+                    let items : Vec<(PathBuf, String, InputFormat, String)> = extracted.entries
+                        .iter()
+                        .map(|e| {
+                            let image_stem = if single { stem.clone() } else { format!("{}-{}", stem, input_format::strip_extension(&e.name).replace('/', "_")) };
+                            (e.path.clone(), format!("{} in {}", e.name, filename), e.format, image_stem)
+                        })
+                        .collect();
+
+                    (Some(extracted), items)
+                },
+                Err(e) => {
+                    println!("Error while converting {}: {}.", filename, e.to_string());
+                    continue;
+                }
+            }
         }
+        else
+        {
+            (None, vec![(absolute_path.clone(), filename.to_string(), format, stem)])
+        };
 
-        if args.prefer_3mf_thumbnail && filename.ends_with(".3mf")
+        for (absolute_path, filename, format, image_stem) in items
         {
-            if extract_image_from_3mf(&absolute_path, args.width, args.height, &image_path).is_ok()
+            let writing_thumbnail = args.write_gcode_thumbnail || args.write_3mf_thumbnail;
+            let filename_image = format!("{}.{}", image_stem, args.format.to_string());
+            let image_path = PathBuf::from(args.outdir.clone()).join(filename_image);
+            let image_path_str = image_path.to_str().take().unwrap();
+
+            if !writing_thumbnail && !args.overwrite && path::Path::new(image_path_str).exists()
             {
+                println!("Path {} already exists, skipping {}...", image_path_str, filename);
                 continue;
             }
-        }
 
-        let possible_mesh = parse_mesh::parse_file((&absolute_path).to_str().take().unwrap());
+            if !writing_thumbnail && args.print_metadata
+            {
+                if let Err(e) = write_print_metadata(&absolute_path, format, &image_path)
+                {
+                    println!("Error while reading print metadata of {}: {}.", filename, e.to_string());
+                }
+            }
+
+            if !writing_thumbnail && args.prefer_3mf_thumbnail && format == InputFormat::ThreeMf
+            {
+                if extract_image_from_3mf(&absolute_path, args.width, args.height, &image_path).is_ok()
+                {
+                    continue;
+                }
+            }
+
+            let is_gcode = format.is_gcode();
+
+            if !writing_thumbnail && args.prefer_gcode_thumbnail && is_gcode
+            {
+                if extract_image_from_gcode(&absolute_path, args.width, args.height, &image_path).is_ok()
+                {
+                    continue;
+                }
+            }
 
-        if let Ok(mesh) = possible_mesh {
-            render_model(&context, &viewport, &mesh, alpha, &file, &image_path, &args.color, args.rotatex, args.rotatey, &mut texture, &mut depth_texture, args.images_per_file, args.inverse_zoom);
-        } else if let Err(e) = possible_mesh {
-            println!("Error while converting {}: {}.", filename, e.to_string());
+            let is_msla = format == InputFormat::Msla;
 
-            if args.fallback_3mf_thumbnail && filename.ends_with(".3mf") && !args.prefer_3mf_thumbnail
+            if !writing_thumbnail && args.prefer_msla_thumbnail && is_msla
             {
-                if extract_image_from_3mf(&absolute_path, args.width, args.height, &image_path).is_err()
+                if extract_image_from_msla(&absolute_path, args.width, args.height, &image_path).is_ok()
                 {
-                    println!("Fallback of extracting image also failed...");
+                    continue;
                 }
             }
+
+            let possible_model = parse_model(&absolute_path, format, args.isolate_object.as_deref(), &gcode_options);
+
+            if let Ok(mut model) = possible_model {
+                if !writing_thumbnail && args.mesh_stats
+                {
+                    if let Err(e) = write_mesh_stats(&model, &image_path)
+                    {
+                        println!("Error while writing mesh statistics of {}: {}.", filename, e.to_string());
+                    }
+                }
+
+                if !writing_thumbnail && args.contact_sheet && model.parts.len() > 1
+                {
+                    let toolpath = model.toolpath;
+                    let mut cells = part_cells(model.parts, format);
+
+                    if cells.len() > 1
+                    {
+                        if let Err(e) = render_contact_sheet(&context, cells, &args, alpha, &image_path)
+                        {
+                            println!("Error while rendering contact sheet for {}: {}.", filename, e.to_string());
+                        }
+
+                        continue;
+                    }
+
+                    // All parts belong to the same object, which is rendered as usual
+                    model = parse_mesh::Model { parts: cells.pop().unwrap().1.parts, toolpath };
+                }
+
+                if args.write_gcode_thumbnail
+                {
+                    let mut models = create_models(&context, &model, &args.color, args.highlight_object.as_deref(), &args.highlight_color);
+
+                    if let Err(e) = write_thumbnails_to_gcode(&context, &absolute_path, &mut models, &args.gcode_thumbnail_sizes, &args.gcode_thumbnail_format, args.rotatex, args.rotatey, args.inverse_zoom)
+                    {
+                        println!("Error while writing thumbnails to {}: {}.", filename, e.to_string());
+                    }
+                }
+                else if args.write_3mf_thumbnail
+                {
+                    let mut models = create_models(&context, &model, &args.color, args.highlight_object.as_deref(), &args.highlight_color);
+
+                    if let Err(e) = write_thumbnail_to_3mf(&viewport, &absolute_path, &mut models, args.rotatex, args.rotatey, &mut texture, &mut depth_texture, args.inverse_zoom)
+                    {
+                        println!("Error while writing thumbnail to {}: {}.", filename, e.to_string());
+                    }
+                }
+                else
+                {
+                    for (layer_image_path, last_layer) in layer_images(&model, &args, &image_path)
+                    {
+                        render_model(&context, &viewport, &model, last_layer, &args, alpha, format, &layer_image_path, &mut texture, &mut depth_texture);
+                    }
+                }
+            } else if let Err(e) = possible_model {
+                println!("Error while converting {}: {}.", filename, e.to_string());
+
+                if writing_thumbnail
+                {
+                    continue;
+                }
+
+                if args.fallback_3mf_thumbnail && format == InputFormat::ThreeMf && !args.prefer_3mf_thumbnail
+                {
+                    if extract_image_from_3mf(&absolute_path, args.width, args.height, &image_path).is_err()
+                    {
+                        println!("Fallback of extracting image also failed...");
+                    }
+                }
+
+                if args.fallback_gcode_thumbnail && is_gcode
+                {
+                    if extract_image_from_gcode(&absolute_path, args.width, args.height, &image_path).is_err()
+                    {
+                        println!("Fallback of extracting image also failed...");
+                    }
+                }
+
+                if args.fallback_msla_thumbnail && is_msla
+                {
+                    if extract_image_from_msla(&absolute_path, args.width, args.height, &image_path).is_err()
+                    {
+                        println!("Fallback of extracting image also failed...");
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_model(path : &PathBuf, format : InputFormat, isolate_object : Option<&str>, gcode_options : &parse_mesh::GcodeOptions) -> Result<parse_mesh::Model, parse_mesh::ParseError>
+{
+    parse_mesh::parse_file(path.to_str().unwrap(), format, gcode_options)
+        .and_then(|model| match isolate_object {
+            Some(name) => model.isolate(name),
+            None => Ok(model),
+        })
+}
+
+/// Saves the slicer metadata of a file as json next to its image, formats without slicer metadata are skipped
+fn write_print_metadata(path : &PathBuf, format : InputFormat, image_path : &PathBuf) -> Result<(), parse_mesh::ParseError>
+{
+    if let Some(metadata) = print_metadata::read_file(path.to_str().unwrap(), format)?
+    {
+        std::fs::write(image_path.with_extension("metadata.json"), metadata.to_json())?;
+    }
+
+    Ok(())
+}
+
+/// Saves the statistics of the meshes of a model as json next to its image, toolpaths without meshes are skipped
+fn write_mesh_stats(model : &parse_mesh::Model, image_path : &PathBuf) -> Result<(), std::io::Error>
+{
+    if let Some(stats) = mesh_stats::measure(model)
+    {
+        std::fs::write(image_path.with_extension("stats.json"), stats.to_json())?;
+    }
+
+    Ok(())
+}
+
+/// Renders every model into its own cell of a grid and saves the grid as one image
+fn render_contact_sheet(
+    context: &HeadlessContext,
+    cells: Vec<(String, parse_mesh::Model, InputFormat)>,
+    args: &Args,
+    alpha: f32,
+    image_path: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cells.is_empty()
+    {
+        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "No models to render")));
+    }
+
+    let layout = contact_sheet::Layout::new(cells.len(), args.width, args.height, args.contact_sheet_captions);
+    let (cell_width, cell_height) = layout.render_size();
+    let viewport = Viewport::new_at_origo(cell_width, cell_height);
+    let (mut texture, mut depth_texture) = create_render_textures(context, &viewport);
+
+    let rendered : Vec<(Vec<[u8; 4]>, String)> = cells
+        .into_iter()
+        .map(|(caption, model, format)| {
+            let mut models = create_models(context, &model, &args.color, args.highlight_object.as_deref(), &args.highlight_color);
+            let pixels = render_pixels(&viewport, &mut models, alpha, format, args.rotatex, args.rotatey, &mut texture, &mut depth_texture, args.inverse_zoom);
+            (pixels, caption)
+        })
+        .collect();
+
+    // Same color the renderer clears to, so cells blend into the sheet
+    let background = [51, 51, 51, (alpha * 255.0) as u8];
+    let pixels = contact_sheet::compose(&rendered, &layout, args.width, args.height, background);
+
+    std::fs::write(image_path, encode_image(pixels, args.width, args.height, &args.format)?)?;
+    Ok(())
+}
+
+/// Contact sheet cells of the parts of a model. Parts with the same name, such as the material sections of an obj
+/// object, share a cell.
+fn part_cells(parts : Vec<parse_mesh::ModelPart>, format : InputFormat) -> Vec<(String, parse_mesh::Model, InputFormat)>
+{
+    let mut cells : Vec<(String, parse_mesh::Model, InputFormat)> = Vec::new();
+
+    for (i, part) in parts.into_iter().enumerate() {
+        if let Some((_, model, _)) = cells.iter_mut().find(|(caption, _, _)| !part.name.is_empty() && *caption == part.name)
+        {
+            model.parts.push(part);
+            continue;
         }
+
+        let caption = if part.name.is_empty() { format!("Part {}", i + 1) } else { part.name.clone() };
+        cells.push((caption, parse_mesh::Model { parts: vec![part], toolpath: None }, format));
     }
+
+    cells
+}
+
+/// Images to render for a file with their last printed layer. Only G-code toolpaths have layers, other files get
+/// a single image of the whole model. A height below the first layer gives no images.
+fn layer_images(model : &parse_mesh::Model, args : &Args, image_path : &PathBuf) -> Vec<(PathBuf, Option<usize>)>
+{
+    let Some(toolpath) = model.toolpath.as_ref().filter(|t| !t.layers.is_empty()) else {
+        return vec![(image_path.clone(), None)];
+    };
+
+    if !args.gcode_progress.is_empty()
+    {
+        let stem = image_path.file_stem().unwrap().to_str().unwrap();
+
+        return args.gcode_progress
+            .iter()
+            .map(|percentage| {
+                let mut path = image_path.clone();
+                replace_file_stem(&mut path, &format!("{}-{:03}", stem, percentage));
+
+                let layers = (toolpath.layers.len() as f32 * (*percentage).min(100) as f32 / 100.0).ceil() as usize;
+                (path, Some(layers.max(1) - 1))
+            })
+            .filter(|(path, _)| args.overwrite || !path.exists())
+            .collect();
+    }
+
+    let by_number = args.gcode_max_layer.map(|layer| layer.max(1) - 1);
+    let by_height = match args.gcode_max_z
+    {
+        Some(z) => match model.layer_at_z(z)
+        {
+            Some(layer) => Some(layer),
+            // Nothing has been printed yet at this height
+            None => {
+                println!("No layer is printed at or below Z {}, skipping...", z);
+                return Vec::new();
+            },
+        },
+        None => None,
+    };
+
+    let last_layer = match (by_number, by_height)
+    {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+
+    vec![(image_path.clone(), last_layer)]
 }
 
 fn render_model(
     context: &HeadlessContext,
     viewport: &Viewport,
-    mesh: &CpuMesh,
+    model: &parse_mesh::Model,
+    last_layer: Option<usize>,
+    args: &Args,
     alpha: f32,
-    file: &str,
+    format: InputFormat,
     image_path: &PathBuf,
-    color : &str,
-    rotatex: f32,
-    rotatey: f32,
     texture: &mut Texture2D,
     depth_texture: &mut DepthTexture2D,
-    count : u32,
-    scale : f32,
 ) {
-    let color = parse_hex_color(color).unwrap();
-    let mut model = Gm::new(
-        Mesh::new(&context, &mesh),
-        solid_material::SolidMaterial::new_opaque(&context,
-            &CpuMaterial {
-                albedo: Srgba::new_opaque((color >> 16 & 0xFF) as u8, (color >> 8 & 0xFF) as u8, (color & 0xFF) as u8),
-                ..Default::default()
-            }),
-        );
+    let count = args.images_per_file;
+    // The layer that is being printed stands out from the layers below it
+    let cutaway = last_layer.map(|layer| model.until_layer(layer, Some(hex_to_srgba(&args.highlight_color))));
+    let mut models = create_models(context, cutaway.as_ref().unwrap_or(model), &args.color, args.highlight_object.as_deref(), &args.highlight_color);
 
     for iter in 0..count {
         let mut iter_file_path = PathBuf::clone(image_path);
-        let mut local_rotatex = rotatex;
+        let mut local_rotatex = args.rotatex;
 
         if count > 1 {
-            model.set_transformation(Mat4::one());
             let new_name = format!("{}-{:02}", iter_file_path.file_stem().unwrap().to_str().unwrap(), iter);
             replace_file_stem(&mut iter_file_path, &new_name);
         }
@@ -231,51 +668,7 @@ fn render_model(
             local_rotatex += (360.0 / count as f32) * iter as f32;
         }
 
-        let mut offset = Mat4::from_translation(model.aabb().min() * -1.0) * Mat4::from_translation((model.aabb().min() - model.aabb().max()) / 2f32);
-
-        if file.ends_with(".stl") 
-            || file.ends_with(".stl.zip")
-            || file.ends_with(".3mf")
-            || file.ends_with(".obj")
-            || file.ends_with(".obj.zip")
-        {
-            offset = Mat4::from_angle_x(Deg(270.0)) * offset;
-        }
-        else if file.ends_with("gcode")
-            || file.ends_with("gcode.zip")
-        {
-            offset = Mat4::from_angle_y(Deg(180.0)) * offset;
-        }
-        model.set_transformation(offset);
-
-        let magnitude = (model.aabb().min() - model.aabb().max()).magnitude() * scale;
-
-        let pitch = rotatey.clamp(-90.0, 90.0).to_radians();
-        let yaw = local_rotatex.to_radians();
-
-        let x = magnitude * pitch.cos() * yaw.sin();
-        let y = magnitude * pitch.sin();
-        let z = magnitude * pitch.cos() * yaw.cos();
-
-        let camera = Camera::new_perspective(
-            viewport.clone(),
-            vec3(x, y, z),
-            vec3(0.0, 0.0, 0.0),
-            vec3(0.0, 1.0, 0.0),
-            degrees(45.0),
-            magnitude * 0.01,
-            1000.0,
-        );
-
-        let pixels : Vec<[u8; 4]> = RenderTarget::new(
-            texture.as_color_target(None),
-            depth_texture.as_depth_target(),
-        )
-        // Clear color and depth of the render target
-        .clear(ClearState::color_and_depth(0.2, 0.2, 0.2, alpha, 1.0))
-        // Render the triangle with the per vertex colors defined at construction
-        .render(&camera, &model, &[])
-        .read_color();
+        let pixels = render_pixels(viewport, &mut models, alpha, format, local_rotatex, args.rotatey, texture, depth_texture, args.inverse_zoom);
 
         three_d_asset::io::save(
             &CpuTexture {
@@ -291,6 +684,299 @@ fn render_model(
     }
 }
 
+/// GPU objects of a model, the toolpath is drawn as instances of a single line shape
+struct RenderModels
+{
+    meshes: Vec<Gm<Mesh, solid_material::SolidMaterial>>,
+    toolpath: Option<Gm<InstancedMesh, solid_material::SolidMaterial>>,
+    /// Travel moves and markers drawn over the toolpath. They do not count for framing, a parking
+    /// move at the end of a print would otherwise shrink the part in the image.
+    overlays: Vec<Gm<InstancedMesh, solid_material::SolidMaterial>>,
+}
+
+impl RenderModels
+{
+    fn set_transformation(&mut self, transformation: Mat4) {
+        for mesh in self.meshes.iter_mut() {
+            mesh.set_transformation(transformation);
+        }
+
+        if let Some(toolpath) = self.toolpath.as_mut() {
+            toolpath.set_transformation(transformation);
+        }
+
+        for overlay in self.overlays.iter_mut() {
+            overlay.set_transformation(transformation);
+        }
+    }
+
+    fn aabb(&self) -> AxisAlignedBoundingBox {
+        let mut aabb = AxisAlignedBoundingBox::EMPTY;
+
+        for mesh in self.meshes.iter() {
+            aabb.expand_with_aabb(mesh.aabb());
+        }
+
+        if let Some(toolpath) = self.toolpath.as_ref() {
+            aabb.expand_with_aabb(toolpath.aabb());
+        }
+
+        aabb
+    }
+}
+
+fn create_models(
+    context: &HeadlessContext,
+    model: &parse_mesh::Model,
+    color : &str,
+    highlight_object: Option<&str>,
+    highlight_color : &str,
+) -> RenderModels {
+    let color = hex_to_srgba(color);
+    let highlight_color = hex_to_srgba(highlight_color);
+
+    let meshes = model.parts.iter().map(|part| {
+        let highlighted = highlight_object.is_some_and(|name| part.name == name);
+
+        let mut material = if highlighted {
+            solid_material::SolidMaterial::new_opaque(&context,
+                &CpuMaterial {
+                    albedo: highlight_color,
+                    ..Default::default()
+                })
+        } else {
+            solid_material::SolidMaterial::new_opaque(&context,
+                &CpuMaterial {
+                    albedo: part.color.unwrap_or(color),
+                    albedo_texture: part.texture.clone(),
+                    ..Default::default()
+                })
+        };
+        material.use_vertex_colors = part.mesh.colors.is_some() && !highlighted;
+
+        Gm::new(Mesh::new(&context, &part.mesh), material)
+    }).collect();
+
+    let toolpath = model.toolpath.as_ref().map(|toolpath| {
+        let instances = Instances {
+            transformations: toolpath.transformations(),
+            colors: toolpath.colors.clone(),
+            ..Default::default()
+        };
+
+        let mut material = solid_material::SolidMaterial::new_opaque(&context,
+            &CpuMaterial {
+                albedo: color,
+                ..Default::default()
+            });
+        material.use_vertex_colors = toolpath.colors.is_some();
+
+        Gm::new(InstancedMesh::new(&context, &instances, &toolpath.line_mesh()), material)
+    });
+
+    let mut overlays = Vec::new();
+
+    if let Some(toolpath) = model.toolpath.as_ref() {
+        if !toolpath.travels.is_empty() {
+            let instances = Instances {
+                transformations: toolpath.travel_transformations(),
+                ..Default::default()
+            };
+
+            let material = solid_material::SolidMaterial::new_opaque(&context,
+                &CpuMaterial {
+                    albedo: gcode_overlay::TRAVEL_COLOR,
+                    ..Default::default()
+                });
+
+            overlays.push(Gm::new(InstancedMesh::new(&context, &instances, &toolpath.line_mesh()), material));
+        }
+
+        if !toolpath.markers.is_empty() {
+            let instances = Instances {
+                transformations: toolpath.marker_transformations(),
+                colors: Some(toolpath.markers.iter().map(|m| m.color).collect()),
+                ..Default::default()
+            };
+
+            let mut material = solid_material::SolidMaterial::new_opaque(&context,
+                &CpuMaterial {
+                    albedo: color,
+                    ..Default::default()
+                });
+            material.use_vertex_colors = true;
+
+            overlays.push(Gm::new(InstancedMesh::new(&context, &instances, &toolpath.marker_mesh()), material));
+        }
+    }
+
+    RenderModels { meshes, toolpath, overlays }
+}
+
+fn create_render_textures(
+    context: &HeadlessContext,
+    viewport: &Viewport,
+) -> (Texture2D, DepthTexture2D) {
+    // Create a color texture to render into
+    let texture = Texture2D::new_empty::<[u8; 4]>(
+        &context,
+        viewport.width,
+        viewport.height,
+        Interpolation::Nearest,
+        Interpolation::Nearest,
+        None,
+        Wrapping::ClampToEdge,
+        Wrapping::ClampToEdge,
+    );
+        
+    // Also create a depth texture to support depth testing
+    let depth_texture = DepthTexture2D::new::<f32>(
+        &context,
+        viewport.width,
+        viewport.height,
+        Wrapping::ClampToEdge,
+        Wrapping::ClampToEdge,
+    );
+
+    (texture, depth_texture)
+}
+
+fn render_pixels(
+    viewport: &Viewport,
+    models: &mut RenderModels,
+    alpha: f32,
+    format: InputFormat,
+    rotatex: f32,
+    rotatey: f32,
+    texture: &mut Texture2D,
+    depth_texture: &mut DepthTexture2D,
+    scale : f32,
+) -> Vec<[u8; 4]> {
+    models.set_transformation(Mat4::one());
+    let aabb = models.aabb();
+
+    let mut offset = Mat4::from_translation(aabb.min() * -1.0) * Mat4::from_translation((aabb.min() - aabb.max()) / 2f32);
+
+    if format.is_z_up()
+    {
+        offset = Mat4::from_angle_x(Deg(270.0)) * offset;
+    }
+    else if format.is_gcode()
+    {
+        offset = Mat4::from_angle_y(Deg(180.0)) * offset;
+    }
+
+    models.set_transformation(offset);
+    let aabb = models.aabb();
+
+    let magnitude = (aabb.min() - aabb.max()).magnitude() * scale;
+
+    let pitch = rotatey.clamp(-90.0, 90.0).to_radians();
+    let yaw = rotatex.to_radians();
+
+    let x = magnitude * pitch.cos() * yaw.sin();
+    let y = magnitude * pitch.sin();
+    let z = magnitude * pitch.cos() * yaw.cos();
+
+    let camera = Camera::new_perspective(
+        viewport.clone(),
+        vec3(x, y, z),
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        degrees(45.0),
+        magnitude * 0.01,
+        1000.0,
+    );
+
+    RenderTarget::new(
+        texture.as_color_target(None),
+        depth_texture.as_depth_target(),
+    )
+    // Clear color and depth of the render target
+    .clear(ClearState::color_and_depth(0.2, 0.2, 0.2, alpha, 1.0))
+    // Render the triangle with the per vertex colors defined at construction
+    .render(&camera, models.meshes.iter(), &[])
+    .render(&camera, models.toolpath.iter(), &[])
+    .render(&camera, models.overlays.iter(), &[])
+    .read_color()
+}
+
+fn write_thumbnails_to_gcode(
+    context: &HeadlessContext,
+    gcode_path: &PathBuf,
+    models: &mut RenderModels,
+    sizes: &[ThumbnailSize],
+    format: &Format,
+    rotatex: f32,
+    rotatey: f32,
+    scale : f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = gcode_path.to_str().unwrap();
+    let alpha = if *format == Format::Jpg { 0.8 } else { 0.0 };
+    let mut thumbnails = Vec::with_capacity(sizes.len());
+
+    for size in sizes
+    {
+        let viewport = Viewport::new_at_origo(size.width, size.height);
+        let (mut texture, mut depth_texture) = create_render_textures(context, &viewport);
+        let pixels = render_pixels(&viewport, models, alpha, InputFormat::Gcode, rotatex, rotatey, &mut texture, &mut depth_texture, scale);
+
+        thumbnails.push(gcode_thumbnail::Thumbnail {
+            format: match format
+            {
+                Format::Png => gcode_thumbnail::ThumbnailFormat::Png,
+                Format::Jpg => gcode_thumbnail::ThumbnailFormat::Jpg,
+            },
+            width: size.width,
+            height: size.height,
+            data: encode_image(pixels, size.width, size.height, format)?,
+        });
+    }
+
+    gcode_thumbnail::write_file(path, &thumbnails).map_err(to_io_error)?;
+    Ok(())
+}
+
+fn write_thumbnail_to_3mf(
+    viewport: &Viewport,
+    threemf_path: &PathBuf,
+    models: &mut RenderModels,
+    rotatex: f32,
+    rotatey: f32,
+    texture: &mut Texture2D,
+    depth_texture: &mut DepthTexture2D,
+    scale : f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = threemf_path.to_str().unwrap();
+    let pixels = render_pixels(viewport, models, 0.0, InputFormat::ThreeMf, rotatex, rotatey, texture, depth_texture, scale);
+    let png = encode_image(pixels, viewport.width, viewport.height, &Format::Png)?;
+
+    threemf_thumbnail::write_file(path, &png).map_err(to_io_error)?;
+    Ok(())
+}
+
+fn encode_image(
+    pixels: Vec<[u8; 4]>,
+    width : u32,
+    height : u32,
+    format: &Format,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = DynamicImage::ImageRgba8(
+        RgbaImage::from_raw(width, height, pixels.into_iter().flatten().collect()).unwrap()
+    );
+
+    let mut data = Cursor::new(Vec::new());
+
+    match format
+    {
+        Format::Png => image.write_to(&mut data, ImageFormat::Png)?,
+        // The jpeg encoder does not support an alpha channel
+        Format::Jpg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut data, ImageFormat::Jpeg)?,
+    }
+
+    Ok(data.into_inner())
+}
+
 fn extract_image_from_3mf(
     threemf_path : &PathBuf,
     width : u32,
@@ -307,11 +993,7 @@ fn extract_image_from_3mf(
             let mut buffer = Vec::with_capacity(file.size() as usize);
             file.read_to_end(&mut buffer)?;
 
-            let step1 = ImageReader::new(Cursor::new(buffer)).with_guessed_format()?.decode()?;
-            let step2 = step1.resize_to_fill(width, height, Triangle);
-
-            step2.save(image_path)?;
-            return Ok(());
+            return save_thumbnail(buffer, width, height, image_path);
         }
     }
 
@@ -321,6 +1003,59 @@ fn extract_image_from_3mf(
     )))
 }
 
+fn extract_image_from_gcode(
+    gcode_path : &PathBuf,
+    width : u32,
+    height : u32,
+    image_path : &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let thumbnails = gcode_thumbnail::read_file(gcode_path.to_str().unwrap()).map_err(to_io_error)?;
+
+    match gcode_thumbnail::closest_thumbnail(&thumbnails, width, height)
+    {
+        Some(thumbnail) => save_thumbnail(thumbnail.data.clone(), width, height, image_path),
+        None => Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No thumbnail found in gcode file",
+        ))),
+    }
+}
+
+fn extract_image_from_msla(
+    msla_path : &PathBuf,
+    width : u32,
+    height : u32,
+    image_path : &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = msla::read_file(msla_path.to_str().unwrap()).map_err(to_io_error)?;
+
+    match gcode_thumbnail::closest_thumbnail(&file.thumbnails, width, height)
+    {
+        Some(thumbnail) => save_thumbnail(thumbnail.data.clone(), width, height, image_path),
+        None => Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No preview image found in resin file",
+        ))),
+    }
+}
+
+fn save_thumbnail(
+    buffer : Vec<u8>,
+    width : u32,
+    height : u32,
+    image_path : &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let step1 = ImageReader::new(Cursor::new(buffer)).with_guessed_format()?.decode()?;
+    let step2 = step1.resize_to_fill(width, height, Triangle);
+
+    step2.save(image_path)?;
+    Ok(())
+}
+
+fn to_io_error(e : parse_mesh::ParseError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
 fn replace_file_stem(path: &mut PathBuf, new_stem: &str) {
     if let Some(ext) = path.extension() {
         path.set_file_name(format!("{}.{}", new_stem, ext.to_string_lossy()));
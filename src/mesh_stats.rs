@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use serde_json::json;
+use three_d::*;
+use crate::parse_mesh::Model;
+
+/// Measurements of the triangles of a model, in the units of the file
+pub struct MeshStats
+{
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+    /// Negative when the triangles are wound inside out
+    pub volume: f64,
+    pub surface_area: f64,
+    pub triangles: usize,
+    /// Distinct vertex positions, corners that are stored more than once, e.g. for uv seams, count once
+    pub vertices: usize,
+    /// Groups of triangles connected by shared vertices
+    pub shells: usize,
+    /// Every edge is shared by exactly two triangles that wind in opposite directions along it,
+    /// so the mesh encloses a volume that can be printed
+    pub watertight: bool,
+}
+
+/// Measures every part of a model together. G-code toolpaths have no triangles and give no statistics.
+pub fn measure(model : &Model) -> Option<MeshStats>
+{
+    let mut positions : Vec<Vector3<f64>> = Vec::new();
+    let mut vertex_ids : HashMap<[u32; 3], u32> = HashMap::new();
+    let mut triangles : Vec<[u32; 3]> = Vec::new();
+
+    for part in &model.parts {
+        let part_positions = part.mesh.positions.to_f32();
+        let indices = part.mesh.indices.to_u32().unwrap_or_else(|| (0..part_positions.len() as u32).collect());
+
+        // Corners are welded by their exact position, triangle soups such as STL files store every corner separately.
+        // Adding 0 turns -0 into 0, which has different bits
+        let ids : Vec<u32> = part_positions
+            .iter()
+            .map(|p| *vertex_ids.entry([(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]).or_insert_with(|| {
+                positions.push(vec3(p.x as f64, p.y as f64, p.z as f64));
+                positions.len() as u32 - 1
+            }))
+            .collect();
+
+        triangles.extend(indices.chunks_exact(3).filter_map(|t| Some([*ids.get(t[0] as usize)?, *ids.get(t[1] as usize)?, *ids.get(t[2] as usize)?])));
+    }
+
+    if triangles.is_empty()
+    {
+        return None;
+    }
+
+    let mut stats = MeshStats {
+        min: vec3(f64::MAX, f64::MAX, f64::MAX),
+        max: vec3(f64::MIN, f64::MIN, f64::MIN),
+        volume: 0.0,
+        surface_area: 0.0,
+        triangles: triangles.len(),
+        vertices: 0,
+        shells: 0,
+        watertight: true,
+    };
+
+    let mut used = vec![false; positions.len()];
+    let mut shells = DisjointSet::new(positions.len());
+    // Times every edge is walked from its first to its second vertex
+    let mut edges : HashMap<(u32, u32), i32> = HashMap::new();
+
+    for triangle in &triangles {
+        let [a, b, c] = triangle.map(|i| positions[i as usize]);
+        stats.volume += a.dot(b.cross(c)) / 6.0;
+        stats.surface_area += (b - a).cross(c - a).magnitude() / 2.0;
+
+        // Triangles with two corners in the same place have no area and do not close any gap
+        let degenerate = triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[2] == triangle[0];
+
+        for i in 0..3 {
+            let (from, to) = (triangle[i], triangle[(i + 1) % 3]);
+            used[from as usize] = true;
+            shells.union(from, to);
+
+            if !degenerate
+            {
+                *edges.entry((from, to)).or_default() += 1;
+            }
+        }
+    }
+
+    for (index, position) in positions.iter().enumerate() {
+        if !used[index]
+        {
+            continue;
+        }
+
+        stats.vertices += 1;
+        stats.min = vec3(stats.min.x.min(position.x), stats.min.y.min(position.y), stats.min.z.min(position.z));
+        stats.max = vec3(stats.max.x.max(position.x), stats.max.y.max(position.y), stats.max.z.max(position.z));
+
+        if shells.find(index as u32) == index as u32
+        {
+            stats.shells += 1;
+        }
+    }
+
+    stats.watertight = edges.iter().all(|(&(from, to), &count)| count == 1 && edges.get(&(to, from)) == Some(&1));
+
+    Some(stats)
+}
+
+impl MeshStats
+{
+    pub fn to_json(&self) -> String
+    {
+        let size = self.max - self.min;
+        let value = json!({
+            "bounding_box": {
+                "min": [self.min.x, self.min.y, self.min.z],
+                "max": [self.max.x, self.max.y, self.max.z],
+                "size": [size.x, size.y, size.z],
+            },
+            "volume": self.volume,
+            "surface_area": self.surface_area,
+            "triangles": self.triangles,
+            "vertices": self.vertices,
+            "shells": self.shells,
+            "watertight": self.watertight,
+        });
+
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+/// Union find over vertex ids, used to count the connected shells
+struct DisjointSet
+{
+    parents: Vec<u32>,
+}
+
+impl DisjointSet
+{
+    fn new(size : usize) -> DisjointSet
+    {
+        DisjointSet { parents: (0..size as u32).collect() }
+    }
+
+    fn find(&mut self, mut id : u32) -> u32
+    {
+        while self.parents[id as usize] != id {
+            // Path halving keeps the trees flat
+            self.parents[id as usize] = self.parents[self.parents[id as usize] as usize];
+            id = self.parents[id as usize];
+        }
+
+        id
+    }
+
+    fn union(&mut self, a : u32, b : u32)
+    {
+        let (a, b) = (self.find(a), self.find(b));
+
+        if a != b
+        {
+            self.parents[a as usize] = b;
+        }
+    }
+}
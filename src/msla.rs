@@ -0,0 +1,788 @@
+use std::fs;
+use std::io::{Cursor, Read, Seek};
+use image::{ImageFormat, ImageReader, RgbImage};
+use three_d::*;
+use zip::ZipArchive;
+use crate::gcode_thumbnail::{Thumbnail, ThumbnailFormat};
+use crate::input_format;
+use crate::parse_mesh::ParseError;
+
+// Resin printer slice files, layer by layer bitmaps plus preview images
+// Chitubox: https://github.com/sn4k3/UVtools/blob/master/UVtools.Core/FileFormats/ChituboxFile.cs
+// Photon Workshop: https://github.com/sn4k3/UVtools/blob/master/UVtools.Core/FileFormats/PhotonWorkshopFile.cs
+// Elegoo goo: https://github.com/elegooofficial/GOO
+
+const CHITUBOX_MAGIC_CBDDLP : u32 = 0x12FD0019;
+const CHITUBOX_MAGIC_CTB : u32 = 0x12FD0086;
+const CHITUBOX_MAGIC_CTB_V4 : u32 = 0x12FD0106;
+const PHOTON_WORKSHOP_MAGIC : &[u8] = b"ANYCUBIC";
+const GOO_MAGIC : &[u8; 8] = b"\x07\x00\x00\x00DLP\x00";
+const GOO_HEADER_SIZE : usize = 195477;
+/// Settings of a layer up to and including the size of its data
+const GOO_LAYER_HEADER_SIZE : usize = 70;
+
+/// The reconstructed mesh uses at most this many voxels along every axis
+const MAX_VOXELS : usize = 256;
+/// Pixels at or above this brightness are cured resin
+const CURED_THRESHOLD : u8 = 128;
+/// Image sizes come from the file header, larger layers and previews are rejected before anything is allocated
+const MAX_PIXELS : usize = 256 * 1024 * 1024;
+/// Size of a layer table entry
+const CHITUBOX_LAYER_ENTRY_SIZE : usize = 36;
+const PHOTON_WORKSHOP_LAYER_ENTRY_SIZE : usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+enum LayerEncoding
+{
+    /// Every layer is a grayscale png, as used by sl1
+    Png,
+    /// 7 bit grey runs, xor encrypted when the key is not 0
+    ChituboxRle(u32),
+    /// 1 bit runs, as used by cbddlp and photon
+    ChituboxBits,
+    PhotonWorkshop,
+    Goo,
+}
+
+pub struct MslaFile
+{
+    pub thumbnails: Vec<Thumbnail>,
+    pub width: u32,
+    pub height: u32,
+    /// Size of a pixel on the build plate in mm
+    pub pixel_size: (f32, f32),
+    pub layer_height: f32,
+    /// Encoded bitmap of every layer, from the build plate up
+    layers: Vec<Vec<u8>>,
+    encoding: LayerEncoding,
+}
+
+/// Recognizes the binary formats by their header, sl1 files are plain zips
+pub fn is_msla(data : &[u8]) -> bool
+{
+    data.starts_with(PHOTON_WORKSHOP_MAGIC)
+        || (data.len() > 12 && &data[4..12] == GOO_MAGIC)
+        || u32_le(data, 0).is_ok_and(|m| [CHITUBOX_MAGIC_CBDDLP, CHITUBOX_MAGIC_CTB, CHITUBOX_MAGIC_CTB_V4].contains(&m))
+}
+
+pub fn read_file(path : &str) -> Result<MslaFile, ParseError>
+{
+    let data = fs::read(path)?;
+
+    if input_format::is_zip(&data)
+    {
+        return read_sl1(Cursor::new(data));
+    }
+
+    parse(&data)
+}
+
+/// Parses one of the binary formats
+pub fn parse(data : &[u8]) -> Result<MslaFile, ParseError>
+{
+    if data.starts_with(PHOTON_WORKSHOP_MAGIC)
+    {
+        return parse_photon_workshop(data);
+    }
+
+    if data.len() > 12 && &data[4..12] == GOO_MAGIC
+    {
+        return parse_goo(data);
+    }
+
+    match u32_le(data, 0)?
+    {
+        CHITUBOX_MAGIC_CBDDLP | CHITUBOX_MAGIC_CTB | CHITUBOX_MAGIC_CTB_V4 => parse_chitubox(data),
+        magic => Err(ParseError::ParseError(format!("Unknown resin file type {:#X}", magic))),
+    }
+}
+
+impl MslaFile
+{
+    /// Decodes a layer into one brightness byte per pixel, row by row from the top
+    pub fn decode_layer(&self, index : usize) -> Result<Vec<u8>, ParseError>
+    {
+        let data = &self.layers[index];
+        let size = pixel_count(self.width, self.height)?;
+
+        let mut pixels = match self.encoding
+        {
+            LayerEncoding::Png => {
+                let image = ImageReader::new(Cursor::new(data)).with_guessed_format()?.decode()
+                    .map_err(|e| ParseError::ParseError(format!("Invalid layer image: {}", e)))?;
+                image.to_luma8().into_raw()
+            },
+            LayerEncoding::ChituboxRle(key) => decode_chitubox_rle(&decrypt_chitubox_layer(data, key, index as u32), size),
+            LayerEncoding::ChituboxBits => decode_chitubox_bits(data, size),
+            LayerEncoding::PhotonWorkshop => decode_photon_workshop(data, size),
+            LayerEncoding::Goo => decode_goo(data, size)?,
+        };
+
+        // Truncated layers are padded with empty pixels
+        pixels.resize(size, 0);
+        Ok(pixels)
+    }
+
+    /// Rebuilds the printed object as a block of voxels. Large prints are sampled down to at most
+    /// `MAX_VOXELS` voxels per axis, a voxel is solid when any of its pixels in any of its layers is cured.
+    pub fn to_mesh(&self) -> Result<CpuMesh, ParseError>
+    {
+        if self.layers.is_empty() || self.width == 0 || self.height == 0
+        {
+            return Err(ParseError::MeshConvertError(String::from("Resin file contains no layers")));
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let step_x = width.div_ceil(MAX_VOXELS);
+        let step_y = height.div_ceil(MAX_VOXELS);
+        let step_z = self.layers.len().div_ceil(MAX_VOXELS);
+
+        let size_x = width.div_ceil(step_x);
+        let size_y = height.div_ceil(step_y);
+        let size_z = self.layers.len().div_ceil(step_z);
+        let mut voxels = vec![false; size_x * size_y * size_z];
+
+        for layer in 0..self.layers.len() {
+            let z = layer / step_z;
+            let pixels = self.decode_layer(layer)?;
+
+            for (i, pixel) in pixels.iter().enumerate() {
+                if *pixel >= CURED_THRESHOLD
+                {
+                    // Image rows start at the top, the mesh y axis points up the image
+                    let x = (i % width) / step_x;
+                    let y = size_y - 1 - (i / width) / step_y;
+                    voxels[(z * size_y + y) * size_x + x] = true;
+                }
+            }
+        }
+
+        let solid = |x : isize, y : isize, z : isize| {
+            x >= 0 && y >= 0 && z >= 0
+                && (x as usize) < size_x && (y as usize) < size_y && (z as usize) < size_z
+                && voxels[(z as usize * size_y + y as usize) * size_x + x as usize]
+        };
+
+        // Corners of every cube face, counter clockwise seen from outside
+        let faces : [([isize; 3], [[f32; 3]; 4]); 6] = [
+            ([1, 0, 0], [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0]]),
+            ([-1, 0, 0], [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]]),
+            ([0, 1, 0], [[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0]]),
+            ([0, -1, 0], [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]]),
+            ([0, 0, 1], [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]]),
+            ([0, 0, -1], [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]]),
+        ];
+
+        let voxel_size = vec3(
+            self.pixel_size.0 * step_x as f32,
+            self.pixel_size.1 * step_y as f32,
+            self.layer_height * step_z as f32,
+        );
+
+        let mut positions : Vec<Vec3> = Vec::new();
+        let mut indices : Vec<u32> = Vec::new();
+
+        for z in 0..size_z as isize {
+            for y in 0..size_y as isize {
+                for x in 0..size_x as isize {
+                    if !solid(x, y, z)
+                    {
+                        continue;
+                    }
+
+                    // Only faces between solid and empty voxels are visible
+                    for (normal, corners) in faces.iter() {
+                        if solid(x + normal[0], y + normal[1], z + normal[2])
+                        {
+                            continue;
+                        }
+
+                        let offset = positions.len() as u32;
+                        positions.extend(corners.iter().map(|c| vec3(
+                            (x as f32 + c[0]) * voxel_size.x,
+                            (y as f32 + c[1]) * voxel_size.y,
+                            (z as f32 + c[2]) * voxel_size.z,
+                        )));
+                        indices.extend([offset, offset + 1, offset + 2, offset, offset + 2, offset + 3]);
+                    }
+                }
+            }
+        }
+
+        if indices.is_empty()
+        {
+            return Err(ParseError::MeshConvertError(String::from("Resin file contains no cured pixels")));
+        }
+
+        Ok(CpuMesh {
+            positions: Positions::F32(positions),
+            indices: Indices::U32(indices),
+            ..Default::default()
+        })
+    }
+}
+
+/// Prusa sl1 and sl1s files are zips with a png per layer, thumbnails and the printer settings as ini files
+fn read_sl1<R>(handle : R) -> Result<MslaFile, ParseError>
+where
+    R: Read + Seek
+{
+    let mut zip = ZipArchive::new(handle)?;
+    let mut settings : Vec<(String, String)> = Vec::new();
+    let mut thumbnails = Vec::new();
+    let mut layers : Vec<(String, Vec<u8>)> = Vec::new();
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let name = file.name().to_string();
+        let mut buffer = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buffer)?;
+
+        if name.ends_with(".ini")
+        {
+            settings.extend(String::from_utf8_lossy(&buffer)
+                .lines()
+                .filter_map(|l| l.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string())));
+        }
+        else if name.ends_with(".png") && name.starts_with("thumbnail/")
+        {
+            if let Ok((width, height)) = ImageReader::new(Cursor::new(&buffer)).with_guessed_format()?.into_dimensions()
+            {
+                thumbnails.push(Thumbnail { format: ThumbnailFormat::Png, width, height, data: buffer });
+            }
+        }
+        else if name.ends_with(".png")
+        {
+            layers.push((name, buffer));
+        }
+    }
+
+    // Layer names are the job name followed by a zero padded layer number
+    layers.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let setting = |key : &str| settings.iter().find(|(k, _)| k == key).and_then(|(_, v)| v.parse::<f32>().ok());
+    let (width, height) = match layers.first() {
+        Some((_, png)) => ImageReader::new(Cursor::new(png)).with_guessed_format()?.into_dimensions()
+            .map_err(|e| ParseError::ParseError(format!("Invalid layer image: {}", e)))?,
+        None => (0, 0),
+    };
+
+    let pixel_size = |size : Option<f32>, pixels : Option<f32>| match (size, pixels) {
+        (Some(size), Some(pixels)) if pixels > 0.0 => size / pixels,
+        _ => 0.05,
+    };
+
+    Ok(MslaFile {
+        thumbnails,
+        width,
+        height,
+        pixel_size: (
+            pixel_size(setting("display_width"), setting("display_pixels_x")),
+            pixel_size(setting("display_height"), setting("display_pixels_y")),
+        ),
+        layer_height: setting("layerHeight").or(setting("layer_height")).unwrap_or(0.05),
+        layers: layers.into_iter().map(|(_, png)| png).collect(),
+        encoding: LayerEncoding::Png,
+    })
+}
+
+fn parse_chitubox(data : &[u8]) -> Result<MslaFile, ParseError>
+{
+    let magic = u32_le(data, 0)?;
+    let bed_x = f32_le(data, 8)?;
+    let bed_y = f32_le(data, 12)?;
+    let layer_height = f32_le(data, 32)?;
+    let width = u32_le(data, 52)?;
+    let height = u32_le(data, 56)?;
+    let preview_large = u32_le(data, 60)? as usize;
+    let layer_table = u32_le(data, 64)? as usize;
+    let layer_count = u32_le(data, 68)? as usize;
+    let preview_small = u32_le(data, 72)? as usize;
+    let key = if magic == CHITUBOX_MAGIC_CBDDLP { 0 } else { u32_le(data, 100)? };
+
+    let mut thumbnails = Vec::new();
+
+    for offset in [preview_large, preview_small] {
+        if offset == 0
+        {
+            continue;
+        }
+
+        let preview_width = u32_le(data, offset)?;
+        let preview_height = u32_le(data, offset + 4)?;
+        let start = u32_le(data, offset + 8)? as usize;
+        let length = u32_le(data, offset + 12)? as usize;
+        let rle = slice(data, start, length)?;
+        pixel_count(preview_width, preview_height)?;
+
+        thumbnails.push(encode_thumbnail(decode_chitubox_preview(rle, preview_width, preview_height), preview_width, preview_height)?);
+    }
+
+    // Anti aliased cbddlp files store a set of layers per level after each other, the first set is enough for a preview
+    slice(data, layer_table, layer_count * CHITUBOX_LAYER_ENTRY_SIZE)?;
+    let mut layers = Vec::with_capacity(layer_count);
+
+    for i in 0..layer_count {
+        let entry = layer_table + i * CHITUBOX_LAYER_ENTRY_SIZE;
+        let start = u32_le(data, entry + 12)? as usize;
+        let length = u32_le(data, entry + 16)? as usize;
+        layers.push(slice(data, start, length)?.to_vec());
+    }
+
+    Ok(MslaFile {
+        thumbnails,
+        width,
+        height,
+        pixel_size: (bed_x / width.max(1) as f32, bed_y / height.max(1) as f32),
+        layer_height,
+        layers,
+        encoding: if magic == CHITUBOX_MAGIC_CBDDLP { LayerEncoding::ChituboxBits } else { LayerEncoding::ChituboxRle(key) },
+    })
+}
+
+fn parse_photon_workshop(data : &[u8]) -> Result<MslaFile, ParseError>
+{
+    // Every section starts with a 12 byte name and its length
+    let header = u32_le(data, 20)? as usize + 16;
+    let preview = u32_le(data, 28)? as usize + 16;
+    let layer_table = u32_le(data, 36)? as usize + 16;
+
+    let pixel_size = f32_le(data, header)? / 1000.0;
+    let layer_height = f32_le(data, header + 4)?;
+    let width = u32_le(data, header + 44)?;
+    let height = u32_le(data, header + 48)?;
+
+    let mut thumbnails = Vec::new();
+
+    if preview > 16
+    {
+        let preview_width = u32_le(data, preview)?;
+        let preview_height = u32_le(data, preview + 8)?;
+        let pixels = slice(data, preview + 12, preview_width as usize * preview_height as usize * 2)?;
+
+        let rgb = pixels
+            .chunks_exact(2)
+            .flat_map(|p| rgb565(u16::from_le_bytes([p[0], p[1]])))
+            .collect();
+
+        thumbnails.push(encode_thumbnail(rgb, preview_width, preview_height)?);
+    }
+
+    let layer_count = u32_le(data, layer_table)? as usize;
+    slice(data, layer_table + 4, layer_count * PHOTON_WORKSHOP_LAYER_ENTRY_SIZE)?;
+    let mut layers = Vec::with_capacity(layer_count);
+
+    for i in 0..layer_count {
+        let entry = layer_table + 4 + i * PHOTON_WORKSHOP_LAYER_ENTRY_SIZE;
+        let start = u32_le(data, entry)? as usize;
+        let length = u32_le(data, entry + 4)? as usize;
+        layers.push(slice(data, start, length)?.to_vec());
+    }
+
+    Ok(MslaFile {
+        thumbnails,
+        width,
+        height,
+        pixel_size: (pixel_size, pixel_size),
+        layer_height,
+        layers,
+        encoding: LayerEncoding::PhotonWorkshop,
+    })
+}
+
+fn parse_goo(data : &[u8]) -> Result<MslaFile, ParseError>
+{
+    // Goo is big endian, with fixed size previews in the header
+    let small_preview = (194, 116);
+    let large_preview = (194 + 116 * 116 * 2 + 2, 290);
+    let settings = large_preview.0 + 290 * 290 * 2 + 2;
+
+    let layer_count = u32_be(data, settings)? as usize;
+    let width = u16_be(data, settings + 4)? as u32;
+    let height = u16_be(data, settings + 6)? as u32;
+    let bed_x = f32_be(data, settings + 10)?;
+    let bed_y = f32_be(data, settings + 14)?;
+    let layer_height = f32_be(data, settings + 22)?;
+
+    let mut thumbnails = Vec::new();
+
+    for (offset, size) in [large_preview, small_preview] {
+        let rgb = slice(data, offset, size * size * 2)?
+            .chunks_exact(2)
+            .flat_map(|p| rgb565(u16::from_be_bytes([p[0], p[1]])))
+            .collect();
+
+        thumbnails.push(encode_thumbnail(rgb, size as u32, size as u32)?);
+    }
+
+    // Layers follow the header back to back, every one with its settings in front of the data: a pause flag,
+    // 15 floats, the light pwm and a line break before the size of the data, and another line break after it
+    let mut layers = Vec::with_capacity(layer_count.min(data.len() / GOO_LAYER_HEADER_SIZE));
+    let mut position = GOO_HEADER_SIZE;
+
+    for _ in 0..layer_count {
+        let length = u32_be(data, position + GOO_LAYER_HEADER_SIZE - 4)? as usize;
+        layers.push(slice(data, position + GOO_LAYER_HEADER_SIZE, length)?.to_vec());
+        position += GOO_LAYER_HEADER_SIZE + length + 2;
+    }
+
+    Ok(MslaFile {
+        thumbnails,
+        width,
+        height,
+        pixel_size: (bed_x / width.max(1) as f32, bed_y / height.max(1) as f32),
+        layer_height,
+        layers,
+        encoding: LayerEncoding::Goo,
+    })
+}
+
+/// Preview images are rgb555 runs, a set bit 5 means a run length follows in the next 12 bits
+fn decode_chitubox_preview(data : &[u8], width : u32, height : u32) -> Vec<u8>
+{
+    let size = width as usize * height as usize * 3;
+    let mut rgb = Vec::with_capacity(size);
+    let mut i = 0;
+
+    while i + 1 < data.len() && rgb.len() < size {
+        let dot = u16::from_le_bytes([data[i], data[i + 1]]);
+        i += 2;
+
+        let mut repeat = 1;
+
+        if dot & 0x20 != 0 && i + 1 < data.len()
+        {
+            repeat += (u16::from_le_bytes([data[i], data[i + 1]]) & 0xFFF) as usize;
+            i += 2;
+        }
+
+        let pixel = [((dot >> 11) & 0x1F) as u8 * 8, ((dot >> 6) & 0x1F) as u8 * 8, (dot & 0x1F) as u8 * 8];
+
+        for _ in 0..repeat {
+            rgb.extend(pixel);
+        }
+    }
+
+    rgb.resize(size, 0);
+    rgb
+}
+
+fn decrypt_chitubox_layer(data : &[u8], key : u32, layer : u32) -> Vec<u8>
+{
+    if key == 0
+    {
+        return data.to_vec();
+    }
+
+    let init = key.wrapping_mul(0x2D83CDAC).wrapping_add(0xD8A83423);
+    let mut xor = layer.wrapping_mul(0x1E1530CD).wrapping_add(0xEC3D47CD).wrapping_mul(init);
+
+    data.iter().enumerate().map(|(i, b)| {
+        let result = b ^ (xor >> (8 * (i % 4))) as u8;
+
+        if i % 4 == 3
+        {
+            xor = xor.wrapping_add(init);
+        }
+
+        result
+    }).collect()
+}
+
+/// Runs of 7 bit grey values, a set top bit means a variable length run count follows
+fn decode_chitubox_rle(data : &[u8], size : usize) -> Vec<u8>
+{
+    let mut pixels = Vec::with_capacity(size);
+    let mut i = 0;
+
+    while i < data.len() && pixels.len() < size {
+        let code = data[i];
+        i += 1;
+
+        let mut run = 1;
+
+        if code & 0x80 != 0
+        {
+            let length = data.get(i).copied().unwrap_or(0) as usize;
+            let extra = match length
+            {
+                l if l & 0x80 == 0 => 0,
+                l if l & 0xC0 == 0x80 => 1,
+                l if l & 0xE0 == 0xC0 => 2,
+                _ => 3,
+            };
+
+            let mask = [0x7F, 0x3F, 0x1F, 0x0F][extra];
+            run = length & mask;
+
+            for j in 1..=extra {
+                run = (run << 8) | data.get(i + j).copied().unwrap_or(0) as usize;
+            }
+
+            i += extra + 1;
+        }
+
+        let grey = code & 0x7F;
+        let value = if grey == 0 { 0 } else { (grey << 1) | 1 };
+        pixels.extend(std::iter::repeat_n(value, run.min(size - pixels.len())));
+    }
+
+    pixels
+}
+
+/// Runs of black or white, the top bit is the color and the rest the run length
+fn decode_chitubox_bits(data : &[u8], size : usize) -> Vec<u8>
+{
+    let mut pixels = Vec::with_capacity(size);
+
+    for code in data {
+        let value = if code & 0x80 != 0 { 255 } else { 0 };
+        let run = (code & 0x7F) as usize;
+        pixels.extend(std::iter::repeat_n(value, run.min(size - pixels.len())));
+    }
+
+    pixels
+}
+
+/// The top nibble is a grey level, black and white runs borrow the next byte for a 12 bit length
+fn decode_photon_workshop(data : &[u8], size : usize) -> Vec<u8>
+{
+    let mut pixels = Vec::with_capacity(size);
+    let mut i = 0;
+
+    while i < data.len() && pixels.len() < size {
+        let code = data[i] >> 4;
+        let mut run = (data[i] & 0x0F) as usize;
+        i += 1;
+
+        let value = match code
+        {
+            0x0 | 0xF => {
+                run = (run << 8) | data.get(i).copied().unwrap_or(0) as usize;
+                i += 1;
+                if code == 0xF { 255 } else { 0 }
+            },
+            _ => (code << 4) | code,
+        };
+
+        pixels.extend(std::iter::repeat_n(value, (run + 1).min(size - pixels.len())));
+    }
+
+    pixels
+}
+
+/// Chunks of black, white or grey pixels, or a small difference to the previous grey value.
+/// The layer data starts with a magic byte and ends with a checksum.
+fn decode_goo(data : &[u8], size : usize) -> Result<Vec<u8>, ParseError>
+{
+    if data.first() != Some(&0x55)
+    {
+        return Err(ParseError::ParseError(String::from("Invalid goo layer data")));
+    }
+
+    let data = &data[1..data.len().saturating_sub(1).max(1)];
+    let mut pixels : Vec<u8> = Vec::with_capacity(size);
+    let mut i = 0;
+    let byte = |i : usize| data.get(i).copied().unwrap_or(0) as usize;
+
+    while i < data.len() && pixels.len() < size {
+        let header = data[i];
+        i += 1;
+
+        let (value, run) = match header >> 6
+        {
+            0b10 => {
+                let previous = pixels.last().copied().unwrap_or(0);
+                let difference = header & 0x0F;
+                let value = if header & 0x20 != 0 { previous.wrapping_sub(difference) } else { previous.wrapping_add(difference) };
+                let run = if header & 0x10 != 0 { i += 1; byte(i - 1) } else { 1 };
+                (value, run)
+            },
+            kind => {
+                let value = match kind
+                {
+                    0b00 => 0,
+                    0b11 => 255,
+                    _ => {
+                        i += 1;
+                        byte(i - 1) as u8
+                    },
+                };
+
+                let low = (header & 0x0F) as usize;
+                let run = match (header >> 4) & 0x3
+                {
+                    0 => low,
+                    1 => low | byte(i) << 4,
+                    2 => low | byte(i) << 12 | byte(i + 1) << 4,
+                    _ => low | byte(i) << 20 | byte(i + 1) << 12 | byte(i + 2) << 4,
+                };
+
+                i += ((header >> 4) & 0x3) as usize;
+                (value, run)
+            },
+        };
+
+        pixels.extend(std::iter::repeat_n(value, run.min(size - pixels.len())));
+    }
+
+    Ok(pixels)
+}
+
+fn rgb565(value : u16) -> [u8; 3]
+{
+    [((value >> 11) & 0x1F) as u8 * 8, ((value >> 5) & 0x3F) as u8 * 4, (value & 0x1F) as u8 * 8]
+}
+
+fn encode_thumbnail(rgb : Vec<u8>, width : u32, height : u32) -> Result<Thumbnail, ParseError>
+{
+    let image = RgbImage::from_raw(width, height, rgb)
+        .ok_or_else(|| ParseError::ParseError(String::from("Invalid preview image size")))?;
+
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, ImageFormat::Png)
+        .map_err(|e| ParseError::ParseError(format!("Failed to encode preview image: {}", e)))?;
+
+    Ok(Thumbnail { format: ThumbnailFormat::Png, width, height, data: data.into_inner() })
+}
+
+/// Pixels of an image, checked against `MAX_PIXELS`
+fn pixel_count(width : u32, height : u32) -> Result<usize, ParseError>
+{
+    let count = width as usize * height as usize;
+
+    if count > MAX_PIXELS
+    {
+        return Err(ParseError::ParseError(format!("Resin image size {}x{} is too large", width, height)));
+    }
+
+    Ok(count)
+}
+
+fn slice(data : &[u8], start : usize, length : usize) -> Result<&[u8], ParseError>
+{
+    data.get(start..start.saturating_add(length))
+        .ok_or_else(|| ParseError::ReadError(String::from("Unexpected end of resin file")))
+}
+
+fn u32_le(data : &[u8], offset : usize) -> Result<u32, ParseError>
+{
+    slice(data, offset, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn f32_le(data : &[u8], offset : usize) -> Result<f32, ParseError>
+{
+    u32_le(data, offset).map(f32::from_bits)
+}
+
+fn u16_be(data : &[u8], offset : usize) -> Result<u16, ParseError>
+{
+    slice(data, offset, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_be(data : &[u8], offset : usize) -> Result<u32, ParseError>
+{
+    slice(data, offset, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn f32_be(data : &[u8], offset : usize) -> Result<f32, ParseError>
+{
+    u32_be(data, offset).map(f32::from_bits)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn chitubox_rle_runs()
+    {
+        // A single white pixel, a short grey run and a black run with a two byte length
+        let data = [0x7F, 0x90, 0x03, 0x80, 0x81, 0x02];
+        let pixels = decode_chitubox_rle(&data, 262);
+
+        assert_eq!(&pixels[..4], &[0xFF, 0x21, 0x21, 0x21]);
+        assert_eq!(pixels.len(), 262);
+        assert!(pixels[4..].iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn chitubox_bits_runs()
+    {
+        assert_eq!(decode_chitubox_bits(&[0x83, 0x02], 5), vec![255, 255, 255, 0, 0]);
+        assert_eq!(decode_chitubox_bits(&[0x83, 0x02], 4), vec![255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn chitubox_layer_decryption()
+    {
+        assert_eq!(decrypt_chitubox_layer(&[1, 2, 3], 0, 7), vec![1, 2, 3]);
+        assert_eq!(decrypt_chitubox_layer(&[0; 5], 1, 0), vec![0xC3, 0xDB, 0x10, 0x68, 0x92]);
+
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+        assert_eq!(decrypt_chitubox_layer(&decrypt_chitubox_layer(&data, 0x1234, 3), 0x1234, 3), data);
+    }
+
+    #[test]
+    fn photon_workshop_runs()
+    {
+        // White with a 12 bit length, a grey nibble run and black with a 12 bit length
+        let data = [0xF0, 0x02, 0x81, 0x00, 0x01];
+        assert_eq!(decode_photon_workshop(&data, 7), vec![255, 255, 255, 0x88, 0x88, 0, 0]);
+    }
+
+    #[test]
+    fn goo_runs()
+    {
+        // Magic, white run with a 2 byte length, grey run, difference to the previous grey, black run and checksum
+        let data = [0x55, 0xD2, 0x01, 0x42, 0x80, 0xA3, 0x02, 0x00];
+        let pixels = decode_goo(&data, 23).unwrap();
+
+        assert_eq!(&pixels[..18], &[255; 18]);
+        assert_eq!(&pixels[18..], &[0x80, 0x80, 0x7D, 0, 0]);
+        assert!(decode_goo(&[0x00, 0x02], 2).is_err());
+    }
+
+    #[test]
+    fn goo_layers()
+    {
+        let settings = 194 + 116 * 116 * 2 + 2 + 290 * 290 * 2 + 2;
+        let mut data = vec![0; GOO_HEADER_SIZE];
+        data[4..12].copy_from_slice(GOO_MAGIC);
+        data[settings..settings + 4].copy_from_slice(&2u32.to_be_bytes());
+        data[settings + 4..settings + 6].copy_from_slice(&4u16.to_be_bytes());
+        data[settings + 6..settings + 8].copy_from_slice(&2u16.to_be_bytes());
+
+        for layer in [[0x55, 0xC4, 0x04, 0x00], [0x55, 0x04, 0xC4, 0x00]] {
+            let mut layer_settings = vec![0; GOO_LAYER_HEADER_SIZE - 4];
+            layer_settings[64..66].copy_from_slice(b"\r\n");
+            data.extend(layer_settings);
+            data.extend((layer.len() as u32).to_be_bytes());
+            data.extend(layer);
+            data.extend(b"\r\n");
+        }
+
+        let file = parse(&data).unwrap();
+
+        assert_eq!((file.width, file.height), (4, 2));
+        assert_eq!(file.thumbnails.len(), 2);
+        assert_eq!(file.decode_layer(0).unwrap(), vec![255, 255, 255, 255, 0, 0, 0, 0]);
+        assert_eq!(file.decode_layer(1).unwrap(), vec![0, 0, 0, 0, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn header_sizes_are_checked()
+    {
+        // A layer count far beyond what the file holds fails instead of reserving memory for it
+        let settings = 194 + 116 * 116 * 2 + 2 + 290 * 290 * 2 + 2;
+        let mut data = vec![0; GOO_HEADER_SIZE];
+        data[4..12].copy_from_slice(GOO_MAGIC);
+        data[settings..settings + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse(&data).is_err());
+
+        assert!(pixel_count(u32::MAX, u32::MAX).is_err());
+        assert_eq!(pixel_count(4, 2).unwrap(), 8);
+    }
+}
@@ -0,0 +1,307 @@
+use three_d::*;
+use crate::parse_mesh::ParseError;
+
+// https://web.archive.org/web/20090404091233/http://www.jalix.org/ressources/graphics/3DS/_unofficials/3ds-info.txt
+
+const MAIN : u16 = 0x4D4D;
+const EDITOR : u16 = 0x3D3D;
+const OBJECT : u16 = 0x4000;
+const TRIANGLE_MESH : u16 = 0x4100;
+const VERTICES : u16 = 0x4110;
+const FACES : u16 = 0x4120;
+const MESH_MATRIX : u16 = 0x4160;
+const KEYFRAMER : u16 = 0xB000;
+const OBJECT_NODE : u16 = 0xB002;
+const NODE_HEADER : u16 = 0xB010;
+const PIVOT : u16 = 0xB013;
+const POSITION_TRACK : u16 = 0xB020;
+const ROTATION_TRACK : u16 = 0xB021;
+const SCALE_TRACK : u16 = 0xB022;
+const NODE_ID : u16 = 0xB030;
+
+/// Keyframer nodes can reference each other in a loop in broken files
+const MAX_NODE_DEPTH : usize = 64;
+
+struct Mesh
+{
+    name: String,
+    vertices: Vec<Vector3<f32>>,
+    faces: Vec<[u32; 3]>,
+    /// Local coordinate system the vertices were exported in
+    matrix: Matrix4<f32>,
+}
+
+struct Node
+{
+    id: u16,
+    name: String,
+    parent: Option<u16>,
+    pivot: Vector3<f32>,
+    /// Transform of the first keyframe, relative to the parent
+    transform: Matrix4<f32>,
+}
+
+/// Splits a chunk body into its child chunks. Truncated chunks are cut off at the end of the data.
+fn chunks(data : &[u8]) -> Vec<(u16, &[u8])>
+{
+    let mut chunks = Vec::new();
+    let mut position = 0;
+
+    while position + 6 <= data.len() {
+        let id = u16::from_le_bytes([data[position], data[position + 1]]);
+        let length = u32::from_le_bytes([data[position + 2], data[position + 3], data[position + 4], data[position + 5]]) as usize;
+
+        if length < 6
+        {
+            break;
+        }
+
+        let end = (position + length).min(data.len());
+        chunks.push((id, &data[position + 6..end]));
+        position = end;
+    }
+
+    chunks
+}
+
+fn read_u16(data : &[u8], position : usize) -> Result<u16, ParseError>
+{
+    data.get(position..position + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| ParseError::ParseError(String::from("Unexpected end of 3ds chunk")))
+}
+
+fn read_u32(data : &[u8], position : usize) -> Result<u32, ParseError>
+{
+    data.get(position..position + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| ParseError::ParseError(String::from("Unexpected end of 3ds chunk")))
+}
+
+fn read_f32(data : &[u8], position : usize) -> Result<f32, ParseError>
+{
+    read_u32(data, position).map(f32::from_bits)
+}
+
+fn read_vector(data : &[u8], position : usize) -> Result<Vector3<f32>, ParseError>
+{
+    Ok(Vector3::new(read_f32(data, position)?, read_f32(data, position + 4)?, read_f32(data, position + 8)?))
+}
+
+/// Reads a zero terminated string, returning it with the offset right after it
+fn read_string(data : &[u8]) -> (String, usize)
+{
+    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    (String::from_utf8_lossy(&data[..end]).to_string(), (end + 1).min(data.len()))
+}
+
+/// Parses a 3ds file into a single mesh. Objects are placed by the first frame of the keyframer when it is present.
+pub fn parse(data : &[u8]) -> Result<CpuMesh, ParseError>
+{
+    let main = chunks(data);
+
+    if main.first().map(|c| c.0) != Some(MAIN)
+    {
+        return Err(ParseError::ParseError(String::from("Not a 3ds file")));
+    }
+
+    let mut meshes : Vec<Mesh> = Vec::new();
+    let mut nodes : Vec<Node> = Vec::new();
+
+    for (id, body) in chunks(main[0].1) {
+        match id
+        {
+            EDITOR => {
+                for (id, body) in chunks(body) {
+                    if id == OBJECT
+                    {
+                        parse_object(body, &mut meshes)?;
+                    }
+                }
+            },
+            KEYFRAMER => {
+                for (id, body) in chunks(body) {
+                    if id == OBJECT_NODE
+                    {
+                        nodes.push(parse_node(body, nodes.len() as u16)?);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let mut positions : Vec<Vec3> = Vec::new();
+    let mut indices : Vec<u32> = Vec::new();
+
+    for mesh in meshes.iter() {
+        let instances : Vec<&Node> = nodes.iter().filter(|n| n.name == mesh.name).collect();
+
+        // Vertices are stored in world space, the keyframer moves them away from the mesh matrix
+        let transforms = if instances.is_empty()
+        {
+            vec![Matrix4::identity()]
+        }
+        else
+        {
+            let inverse = mesh.matrix.invert().unwrap_or(Matrix4::identity());
+
+            instances
+                .iter()
+                .map(|n| node_transform(&nodes, n, 0) * Matrix4::from_translation(-n.pivot) * inverse)
+                .collect()
+        };
+
+        for transform in transforms {
+            let offset = positions.len() as u32;
+
+            positions.extend(mesh.vertices.iter().map(|v| (transform * v.extend(1.0)).truncate()));
+            indices.extend(mesh.faces.iter().flatten().map(|i| i + offset));
+        }
+    }
+
+    if indices.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("3ds file contains no meshes")));
+    }
+
+    Ok(CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(indices),
+        ..Default::default()
+    })
+}
+
+fn parse_object(data : &[u8], meshes : &mut Vec<Mesh>) -> Result<(), ParseError>
+{
+    let (name, start) = read_string(data);
+
+    // Objects can also be lights or cameras
+    for (id, body) in chunks(&data[start..]) {
+        if id != TRIANGLE_MESH
+        {
+            continue;
+        }
+
+        let mut mesh = Mesh { name: name.clone(), vertices: Vec::new(), faces: Vec::new(), matrix: Matrix4::identity() };
+
+        for (id, body) in chunks(body) {
+            match id
+            {
+                VERTICES => {
+                    let count = read_u16(body, 0)? as usize;
+
+                    for i in 0..count {
+                        mesh.vertices.push(read_vector(body, 2 + i * 12)?);
+                    }
+                },
+                FACES => {
+                    let count = read_u16(body, 0)? as usize;
+
+                    // Every face is followed by a flags value, material groups follow the faces as sub chunks
+                    for i in 0..count {
+                        let position = 2 + i * 8;
+                        mesh.faces.push([
+                            read_u16(body, position)? as u32,
+                            read_u16(body, position + 2)? as u32,
+                            read_u16(body, position + 4)? as u32,
+                        ]);
+                    }
+                },
+                MESH_MATRIX => {
+                    let values = (0..12).map(|i| read_f32(body, i * 4)).collect::<Result<Vec<f32>, _>>()?;
+                    mesh.matrix = Matrix4::new(
+                        values[0], values[1], values[2], 0.0,
+                        values[3], values[4], values[5], 0.0,
+                        values[6], values[7], values[8], 0.0,
+                        values[9], values[10], values[11], 1.0,
+                    );
+                },
+                _ => {},
+            }
+        }
+
+        if mesh.faces.iter().flatten().any(|i| *i as usize >= mesh.vertices.len())
+        {
+            return Err(ParseError::ParseError(format!("3ds object {} references a vertex that does not exist", mesh.name)));
+        }
+
+        meshes.push(mesh);
+    }
+
+    Ok(())
+}
+
+fn parse_node(data : &[u8], index : u16) -> Result<Node, ParseError>
+{
+    let mut node = Node { id: index, name: String::new(), parent: None, pivot: Vector3::new(0.0, 0.0, 0.0), transform: Matrix4::identity() };
+    let mut translation = Matrix4::identity();
+    let mut rotation = Matrix4::identity();
+    let mut scale = Matrix4::identity();
+
+    for (id, body) in chunks(data) {
+        match id
+        {
+            NODE_ID => node.id = read_u16(body, 0)?,
+            NODE_HEADER => {
+                let (name, position) = read_string(body);
+                let parent = read_u16(body, position + 4)?;
+
+                node.name = name;
+                node.parent = if parent == 0xFFFF { None } else { Some(parent) };
+            },
+            PIVOT => node.pivot = read_vector(body, 0)?,
+            POSITION_TRACK => {
+                if let Some(position) = first_key(body)?
+                {
+                    translation = Matrix4::from_translation(read_vector(body, position)?);
+                }
+            },
+            ROTATION_TRACK => {
+                if let Some(position) = first_key(body)?
+                {
+                    let angle = read_f32(body, position)?;
+                    let axis = read_vector(body, position + 4)?;
+
+                    if axis.magnitude2() > 0.0
+                    {
+                        rotation = Matrix4::from_axis_angle(axis.normalize(), Rad(angle));
+                    }
+                }
+            },
+            SCALE_TRACK => {
+                if let Some(position) = first_key(body)?
+                {
+                    let value = read_vector(body, position)?;
+                    scale = Matrix4::from_nonuniform_scale(value.x, value.y, value.z);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    node.transform = translation * rotation * scale;
+    Ok(node)
+}
+
+/// Finds the value of the first key in a track, skipping the spline parameters in front of it
+fn first_key(data : &[u8]) -> Result<Option<usize>, ParseError>
+{
+    if read_u32(data, 10)? == 0
+    {
+        return Ok(None);
+    }
+
+    let flags = read_u16(data, 18)?;
+    Ok(Some(20 + (flags & 0x1F).count_ones() as usize * 4))
+}
+
+fn node_transform(nodes : &[Node], node : &Node, depth : usize) -> Matrix4<f32>
+{
+    let parent = match node.parent.and_then(|p| nodes.iter().find(|n| n.id == p)) {
+        Some(parent) if depth < MAX_NODE_DEPTH => node_transform(nodes, parent, depth + 1),
+        _ => Matrix4::identity(),
+    };
+
+    parent * node.transform
+}
@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use regex::Regex;
+use three_d::*;
+use zip::ZipArchive;
+use crate::parse_mesh::{Model, ModelPart, ParseError, UNSPECIFIED_COLOR};
+
+// https://github.com/3MFConsortium/spec_core/blob/master/3MF%20Core%20Specification.md
+// https://github.com/3MFConsortium/spec_materials/blob/master/3MF%20Materials%20Extension.md
+
+const MODEL_RELATIONSHIP_TYPE : &str = "http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel";
+const DEFAULT_MODEL_PATH : &str = "3D/3dmodel.model";
+/// Components can reference each other in a loop in broken files
+const MAX_COMPONENT_DEPTH : usize = 32;
+
+const BAMBU_MODEL_SETTINGS_PATH : &str = "Metadata/model_settings.config";
+pub const BAMBU_PROJECT_SETTINGS_PATH : &str = "Metadata/project_settings.config";
+const PRUSA_MODEL_CONFIG_PATH : &str = "Metadata/Slic3r_PE_model.config";
+pub const PRUSA_CONFIG_PATH : &str = "Metadata/Slic3r_PE.config";
+
+struct ObjectMesh
+{
+    vertices: Vec<Vec3>,
+    triangles: Vec<Triangle>,
+}
+
+struct Triangle
+{
+    vertices: [u32; 3],
+    /// Property group and the per vertex indices into it
+    properties: Option<(u32, [u32; 3])>,
+    /// Extruder painted on by a slicer, 0 when not painted
+    painted_extruder: u32,
+}
+
+struct Component
+{
+    /// Model file the referenced object lives in, for the production extension
+    path: Option<String>,
+    object_id: u32,
+    transform: Mat4,
+}
+
+#[derive(Default)]
+struct Object
+{
+    name: String,
+    mesh: Option<ObjectMesh>,
+    components: Vec<Component>,
+    /// Default property for triangles without their own
+    property: Option<(u32, u32)>,
+}
+
+struct Item
+{
+    path: Option<String>,
+    object_id: u32,
+    transform: Mat4,
+}
+
+#[derive(Default)]
+struct ModelFile
+{
+    objects: HashMap<u32, Object>,
+    build: Vec<Item>,
+    /// Colors of every basematerials and colorgroup resource, by resource id
+    property_groups: HashMap<u32, Vec<Srgba>>,
+}
+
+/// Per object extruder assignments and extruder colors stored by slicers next to the model
+#[derive(Default)]
+struct SlicerSettings
+{
+    extruder_colors: Vec<Srgba>,
+    object_extruders: HashMap<u32, u32>,
+    /// Bambu Studio assigns extruders to the component objects of an object, as (object id, part id).
+    /// Part ids are only unique within their object.
+    part_extruders: HashMap<(u32, u32), u32>,
+    /// PrusaSlicer assigns extruders to triangle ranges of an object, as (first, last, extruder)
+    volume_extruders: HashMap<u32, Vec<(u32, u32, u32)>>,
+}
+
+struct Package
+{
+    zip: ZipArchive<File>,
+    models: HashMap<String, ModelFile>,
+    settings: SlicerSettings,
+}
+
+struct MeshBuilder
+{
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
+    colors: Vec<Srgba>,
+}
+
+/// Every build item becomes a part, named after its object
+pub fn parse(path : &str) -> Result<Model, ParseError>
+{
+    let handle = File::open(path)?;
+    let mut zip = ZipArchive::new(handle)?;
+    let settings = read_slicer_settings(&mut zip)?;
+    let mut package = Package { zip, models: HashMap::new(), settings };
+
+    let root_path = find_root_model_path(&mut package.zip)?;
+    package.load(&root_path)?;
+
+    let root = &package.models[&root_path];
+    let mut items : Vec<Item> = root.build
+        .iter()
+        .map(|item| Item { path: item.path.clone(), object_id: item.object_id, transform: item.transform })
+        .collect();
+
+    // Files without a build section still contain printable objects
+    if items.is_empty()
+    {
+        let mut object_ids : Vec<u32> = root.objects.keys().copied().collect();
+        object_ids.sort();
+        items.extend(object_ids.into_iter().map(|object_id| Item { path: None, object_id, transform: Mat4::identity() }));
+    }
+
+    let mut parts = Vec::new();
+
+    for item in items
+    {
+        let path = item.path.map(|p| normalize_path(&p)).unwrap_or_else(|| root_path.clone());
+        let extruder = package.settings.object_extruders.get(&item.object_id).copied();
+        let mut builder = MeshBuilder { positions: Vec::new(), indices: Vec::new(), colors: Vec::new() };
+        package.append_object(&path, item.object_id, item.transform, extruder, 0, &mut builder)?;
+
+        if builder.indices.is_empty()
+        {
+            continue;
+        }
+
+        let has_colors = builder.colors.iter().any(|c| c.a != 0);
+
+        parts.push(ModelPart {
+            name: package.models[&path].objects[&item.object_id].name.clone(),
+            mesh: CpuMesh {
+                positions: Positions::F32(builder.positions),
+                indices: Indices::U32(builder.indices),
+                colors: if has_colors { Some(builder.colors) } else { None },
+                ..Default::default()
+            },
+            color: None,
+            texture: None,
+        });
+    }
+
+    if parts.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("No meshes found in 3mf model")));
+    }
+
+    Ok(Model { parts, toolpath: None })
+}
+
+impl Package
+{
+    fn load(&mut self, path : &str) -> Result<(), ParseError>
+    {
+        if self.models.contains_key(path)
+        {
+            return Ok(());
+        }
+
+        let mut file = self.zip.by_name(path)?;
+        let mut buffer = String::with_capacity(file.size() as usize);
+        file.read_to_string(&mut buffer)?;
+
+        let model = parse_model_file(&buffer)?;
+        self.models.insert(String::from(path), model);
+        Ok(())
+    }
+
+    fn append_object(
+        &mut self,
+        path : &str,
+        object_id : u32,
+        transform : Mat4,
+        extruder : Option<u32>,
+        depth : usize,
+        builder : &mut MeshBuilder,
+    ) -> Result<(), ParseError>
+    {
+        if depth > MAX_COMPONENT_DEPTH
+        {
+            return Err(ParseError::ParseError(String::from("3mf components are nested too deep")));
+        }
+
+        self.load(path)?;
+
+        let model = &self.models[path];
+        let object = model.objects.get(&object_id)
+            .ok_or(ParseError::ParseError(format!("3mf object {} not found in {}", object_id, path)))?;
+
+        if let Some(mesh) = &object.mesh
+        {
+            let volumes = self.settings.volume_extruders.get(&object_id);
+
+            let colors : Vec<Option<[Srgba; 3]>> = mesh.triangles
+                .iter()
+                .enumerate()
+                .map(|(index, triangle)| {
+                    let extruder = volumes
+                        .and_then(|v| v.iter().find(|(first, last, _)| (*first..=*last).contains(&(index as u32))))
+                        .map(|(_, _, e)| *e)
+                        .or(extruder);
+
+                    self.triangle_colors(model, object, triangle, extruder)
+                })
+                .collect();
+
+            builder.append(mesh, &colors, transform);
+        }
+
+        let components : Vec<(String, u32, Mat4)> = object.components
+            .iter()
+            .map(|c| (c.path.as_deref().map(normalize_path).unwrap_or_else(|| String::from(path)), c.object_id, c.transform))
+            .collect();
+
+        for (component_path, component_id, component_transform) in components
+        {
+            let component_extruder = self.settings.part_extruders.get(&(object_id, component_id)).copied().or(extruder);
+            self.append_object(&component_path, component_id, transform * component_transform, component_extruder, depth + 1, builder)?;
+        }
+
+        Ok(())
+    }
+
+    fn triangle_colors(&self, model : &ModelFile, object : &Object, triangle : &Triangle, extruder : Option<u32>) -> Option<[Srgba; 3]>
+    {
+        if let Some(color) = self.extruder_color(triangle.painted_extruder)
+        {
+            return Some([color; 3]);
+        }
+
+        if let Some((pid, [p1, p2, p3])) = triangle.properties
+            && let Some(group) = model.property_groups.get(&pid)
+            && let (Some(c1), Some(c2), Some(c3)) = (group.get(p1 as usize), group.get(p2 as usize), group.get(p3 as usize))
+        {
+            return Some([*c1, *c2, *c3]);
+        }
+
+        if let Some((pid, pindex)) = object.property
+            && let Some(color) = model.property_groups.get(&pid).and_then(|g| g.get(pindex as usize))
+        {
+            return Some([*color; 3]);
+        }
+
+        extruder.and_then(|e| self.extruder_color(e)).map(|color| [color; 3])
+    }
+
+    fn extruder_color(&self, extruder : u32) -> Option<Srgba>
+    {
+        if extruder == 0
+        {
+            return None;
+        }
+
+        self.settings.extruder_colors.get(extruder as usize - 1).copied()
+    }
+}
+
+impl MeshBuilder
+{
+    fn append(&mut self, mesh : &ObjectMesh, colors : &[Option<[Srgba; 3]>], transform : Mat4)
+    {
+        if colors.iter().all(|c| c.is_none())
+        {
+            let offset = self.positions.len() as u32;
+
+            self.positions.extend(mesh.vertices.iter().map(|v| (transform * v.extend(1.0)).truncate()));
+            self.colors.extend(mesh.vertices.iter().map(|_| UNSPECIFIED_COLOR));
+            self.indices.extend(mesh.triangles.iter().flat_map(|t| t.vertices.map(|i| i + offset)));
+            return;
+        }
+
+        // Neighbouring triangles can have different colors, so vertices are not shared
+        for (triangle, colors) in mesh.triangles.iter().zip(colors)
+        {
+            let colors = colors.unwrap_or([UNSPECIFIED_COLOR; 3]);
+
+            for (vertex, color) in triangle.vertices.iter().zip(colors)
+            {
+                self.indices.push(self.positions.len() as u32);
+                self.positions.push((transform * mesh.vertices[*vertex as usize].extend(1.0)).truncate());
+                self.colors.push(color);
+            }
+        }
+    }
+}
+
+fn find_root_model_path(zip : &mut ZipArchive<File>) -> Result<String, ParseError>
+{
+    if let Ok(mut rels) = zip.by_name("_rels/.rels")
+    {
+        let mut buffer = String::new();
+        rels.read_to_string(&mut buffer)?;
+
+        let mut reader = Reader::from_str(&buffer);
+
+        loop
+        {
+            match reader.read_event()?
+            {
+                Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                    if attribute(&e, b"Type")?.as_deref() == Some(MODEL_RELATIONSHIP_TYPE)
+                        && let Some(target) = attribute(&e, b"Target")?
+                    {
+                        return Ok(normalize_path(&target));
+                    }
+                },
+                Event::Eof => break,
+                _ => {},
+            }
+        }
+    }
+
+    if zip.index_for_name(DEFAULT_MODEL_PATH).is_some()
+    {
+        return Ok(String::from(DEFAULT_MODEL_PATH));
+    }
+
+    zip.file_names()
+        .find(|name| name.ends_with(".model"))
+        .map(String::from)
+        .ok_or(ParseError::MeshConvertError(String::from("No model file found in 3mf archive")))
+}
+
+fn parse_model_file(xml : &str) -> Result<ModelFile, ParseError>
+{
+    let mut reader = Reader::from_str(xml);
+    let mut model = ModelFile::default();
+    let mut current : Option<(u32, Object)> = None;
+    let mut current_group : Option<(u32, Vec<Srgba>)> = None;
+
+    loop
+    {
+        match reader.read_event()?
+        {
+            Event::Start(e) | Event::Empty(e) => {
+                match e.local_name().as_ref()
+                {
+                    b"object" => {
+                        let id = required_u32(&e, b"id")?;
+                        let property = match (optional_u32(&e, b"pid")?, optional_u32(&e, b"pindex")?)
+                        {
+                            (Some(pid), pindex) => Some((pid, pindex.unwrap_or(0))),
+                            _ => None,
+                        };
+
+                        let name = attribute(&e, b"name")?.unwrap_or_default();
+
+                        current = Some((id, Object { name, property, ..Default::default() }));
+                    },
+                    b"mesh" => {
+                        if let Some((_, object)) = current.as_mut()
+                        {
+                            object.mesh = Some(ObjectMesh { vertices: Vec::new(), triangles: Vec::new() });
+                        }
+                    },
+                    b"vertex" => {
+                        if let Some(mesh) = current.as_mut().and_then(|(_, o)| o.mesh.as_mut())
+                        {
+                            mesh.vertices.push(vec3(required_f32(&e, b"x")?, required_f32(&e, b"y")?, required_f32(&e, b"z")?));
+                        }
+                    },
+                    b"triangle" => {
+                        if let Some(mesh) = current.as_mut().and_then(|(_, o)| o.mesh.as_mut())
+                        {
+                            mesh.triangles.push(parse_triangle(&e, mesh.vertices.len())?);
+                        }
+                    },
+                    b"component" => {
+                        if let Some((_, object)) = current.as_mut()
+                        {
+                            object.components.push(Component {
+                                path: attribute(&e, b"path")?,
+                                object_id: required_u32(&e, b"objectid")?,
+                                transform: transform_attribute(&e)?,
+                            });
+                        }
+                    },
+                    b"item" => {
+                        model.build.push(Item {
+                            path: attribute(&e, b"path")?,
+                            object_id: required_u32(&e, b"objectid")?,
+                            transform: transform_attribute(&e)?,
+                        });
+                    },
+                    b"basematerials" | b"colorgroup" => {
+                        current_group = Some((required_u32(&e, b"id")?, Vec::new()));
+                    },
+                    b"base" => {
+                        if let Some((_, colors)) = current_group.as_mut()
+                        {
+                            colors.push(attribute(&e, b"displaycolor")?.and_then(|c| parse_color(&c)).unwrap_or(UNSPECIFIED_COLOR));
+                        }
+                    },
+                    b"color" => {
+                        if let Some((_, colors)) = current_group.as_mut()
+                        {
+                            colors.push(attribute(&e, b"color")?.and_then(|c| parse_color(&c)).unwrap_or(UNSPECIFIED_COLOR));
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Event::End(e) => {
+                match e.local_name().as_ref()
+                {
+                    b"object" => {
+                        if let Some((id, object)) = current.take()
+                        {
+                            model.objects.insert(id, object);
+                        }
+                    },
+                    b"basematerials" | b"colorgroup" => {
+                        if let Some((id, colors)) = current_group.take()
+                        {
+                            model.property_groups.insert(id, colors);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    Ok(model)
+}
+
+fn parse_triangle(e : &BytesStart, vertex_count : usize) -> Result<Triangle, ParseError>
+{
+    let vertices = [required_u32(e, b"v1")?, required_u32(e, b"v2")?, required_u32(e, b"v3")?];
+
+    if vertices.iter().any(|i| *i as usize >= vertex_count)
+    {
+        return Err(ParseError::ParseError(String::from("3mf triangle references a missing vertex")));
+    }
+
+    let properties = match (optional_u32(e, b"pid")?, optional_u32(e, b"p1")?)
+    {
+        (Some(pid), Some(p1)) => Some((pid, [p1, optional_u32(e, b"p2")?.unwrap_or(p1), optional_u32(e, b"p3")?.unwrap_or(p1)])),
+        _ => None,
+    };
+
+    // Bambu Studio and PrusaSlicer store multi material painting per triangle
+    let painted_extruder = match attribute(e, b"paint_color")?.or(attribute(e, b"mmu_segmentation")?)
+    {
+        Some(paint) => decode_painted_extruder(&paint),
+        None => 0,
+    };
+
+    Ok(Triangle { vertices, properties, painted_extruder })
+}
+
+/// Decodes the triangle selector state written by PrusaSlicer and Bambu Studio.
+/// The string is a reversed stream of hex nibbles describing a tree of subdivided triangles.
+/// Subdivisions are not rendered, so the extruder used by most leaf triangles is returned.
+// https://github.com/prusa3d/PrusaSlicer/blob/master/src/libslic3r/TriangleSelector.cpp
+fn decode_painted_extruder(paint : &str) -> u32
+{
+    let mut nibbles = paint.chars().rev().filter_map(|c| c.to_digit(16));
+    let mut counts : HashMap<u32, u32> = HashMap::new();
+
+    fn read_node(nibbles : &mut impl Iterator<Item = u32>, counts : &mut HashMap<u32, u32>, depth : usize)
+    {
+        let Some(code) = nibbles.next() else { return; };
+        let split_sides = code & 0b11;
+
+        if split_sides == 0
+        {
+            let mut state = code >> 2;
+
+            if state == 0b11
+            {
+                state = 3 + nibbles.next().unwrap_or(0);
+            }
+
+            *counts.entry(state).or_insert(0) += 1;
+        }
+        else if depth < 32
+        {
+            for _ in 0..=split_sides
+            {
+                read_node(nibbles, counts, depth + 1);
+            }
+        }
+    }
+
+    read_node(&mut nibbles, &mut counts, 0);
+
+    counts
+        .into_iter()
+        .max_by_key(|(state, count)| (*count, *state))
+        .map(|(state, _)| state)
+        .unwrap_or(0)
+}
+
+fn read_slicer_settings(zip : &mut ZipArchive<File>) -> Result<SlicerSettings, ParseError>
+{
+    let mut settings = SlicerSettings::default();
+
+    if let Some(project_settings) = read_optional_file(zip, BAMBU_PROJECT_SETTINGS_PATH)?
+    {
+        // Json, only the filament colors are of interest
+        let regex_colors = Regex::new(r#""filament_colour"\s*:\s*\[([^\]]*)\]"#).unwrap();
+
+        if let Some(caps) = regex_colors.captures(&project_settings)
+        {
+            settings.extruder_colors = caps.get(1).unwrap().as_str()
+                .split(',')
+                .map(|c| parse_color(c.trim().trim_matches('"')).unwrap_or(UNSPECIFIED_COLOR))
+                .collect();
+        }
+    }
+
+    if let Some(config) = read_optional_file(zip, PRUSA_CONFIG_PATH)?
+    {
+        let regex_setting = Regex::new(r"(?m)^;\s*(extruder_colour|filament_colour)\s*=\s*(.*)$").unwrap();
+        let mut extruder_colors : Vec<Option<Srgba>> = Vec::new();
+        let mut filament_colors : Vec<Option<Srgba>> = Vec::new();
+
+        for caps in regex_setting.captures_iter(&config)
+        {
+            let colors = caps.get(2).unwrap().as_str()
+                .split(';')
+                .map(|c| parse_color(c.trim().trim_matches('"')))
+                .collect();
+
+            match caps.get(1).unwrap().as_str()
+            {
+                "extruder_colour" => extruder_colors = colors,
+                _ => filament_colors = colors,
+            }
+        }
+
+        // An empty extruder color means the filament color is shown
+        let count = extruder_colors.len().max(filament_colors.len());
+        settings.extruder_colors = (0..count)
+            .map(|i| extruder_colors.get(i).copied().flatten()
+                .or(filament_colors.get(i).copied().flatten())
+                .unwrap_or(UNSPECIFIED_COLOR))
+            .collect();
+    }
+
+    for path in [BAMBU_MODEL_SETTINGS_PATH, PRUSA_MODEL_CONFIG_PATH]
+    {
+        if let Some(model_settings) = read_optional_file(zip, path)?
+        {
+            read_extruder_assignments(&model_settings, &mut settings)?;
+        }
+    }
+
+    Ok(settings)
+}
+
+fn read_extruder_assignments(xml : &str, settings : &mut SlicerSettings) -> Result<(), ParseError>
+{
+    let mut reader = Reader::from_str(xml);
+    let mut object_id : Option<u32> = None;
+    let mut part_id : Option<u32> = None;
+    let mut volume : Option<(u32, u32)> = None;
+
+    loop
+    {
+        match reader.read_event()?
+        {
+            Event::Start(e) | Event::Empty(e) => {
+                match e.local_name().as_ref()
+                {
+                    b"object" => object_id = optional_u32(&e, b"id")?,
+                    b"part" => part_id = optional_u32(&e, b"id")?,
+                    b"volume" => volume = optional_u32(&e, b"firstid")?.zip(optional_u32(&e, b"lastid")?),
+                    b"metadata" if attribute(&e, b"key")?.as_deref() == Some("extruder") => {
+                        let Some(extruder) = optional_u32(&e, b"value")? else { continue; };
+
+                        if let (Some(object_id), Some(part_id)) = (object_id, part_id)
+                        {
+                            settings.part_extruders.insert((object_id, part_id), extruder);
+                        }
+                        else if let (Some(object_id), Some((first, last))) = (object_id, volume)
+                        {
+                            // Volume extruder 0 means the extruder of the object is used
+                            if extruder != 0
+                            {
+                                settings.volume_extruders.entry(object_id).or_default().push((first, last, extruder));
+                            }
+                        }
+                        else if let Some(object_id) = object_id
+                        {
+                            settings.object_extruders.insert(object_id, extruder);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Event::End(e) => {
+                match e.local_name().as_ref()
+                {
+                    b"object" => object_id = None,
+                    b"part" => part_id = None,
+                    b"volume" => volume = None,
+                    _ => {},
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+fn read_optional_file(zip : &mut ZipArchive<File>, path : &str) -> Result<Option<String>, ParseError>
+{
+    let Ok(mut file) = zip.by_name(path) else {
+        return Ok(None);
+    };
+
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer)?;
+    Ok(Some(buffer))
+}
+
+/// Parses `#RRGGBB` and `#RRGGBBAA` colors. Transparency is ignored, thumbnails are rendered opaque.
+fn parse_color(color : &str) -> Option<Srgba>
+{
+    let hex = color.trim().strip_prefix('#')?;
+
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii()
+    {
+        return None;
+    }
+
+    let channel = |i : usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Srgba::new_opaque(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Reads an attribute by its local name, so namespaced attributes such as `p:path` are found as well
+pub fn attribute(e : &BytesStart, name : &[u8]) -> Result<Option<String>, ParseError>
+{
+    for attr in e.attributes()
+    {
+        let attr = attr.map_err(|e| ParseError::ParseError(e.to_string()))?;
+
+        if attr.key.local_name().as_ref() == name
+        {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn required_attribute(e : &BytesStart, name : &[u8]) -> Result<String, ParseError>
+{
+    attribute(e, name)?.ok_or(ParseError::ParseError(format!(
+        "Missing attribute {} on 3mf element {}",
+        String::from_utf8_lossy(name),
+        String::from_utf8_lossy(e.local_name().as_ref())
+    )))
+}
+
+fn optional_u32(e : &BytesStart, name : &[u8]) -> Result<Option<u32>, ParseError>
+{
+    attribute(e, name)?
+        .map(|v| v.trim().parse::<u32>().map_err(|e| ParseError::ParseError(e.to_string())))
+        .transpose()
+}
+
+fn required_u32(e : &BytesStart, name : &[u8]) -> Result<u32, ParseError>
+{
+    required_attribute(e, name)?.trim().parse::<u32>().map_err(|e| ParseError::ParseError(e.to_string()))
+}
+
+fn required_f32(e : &BytesStart, name : &[u8]) -> Result<f32, ParseError>
+{
+    Ok(required_attribute(e, name)?.trim().parse::<f32>()?)
+}
+
+/// 3mf transforms are 4x3 matrices applied to row vectors, stored row by row
+fn transform_attribute(e : &BytesStart) -> Result<Mat4, ParseError>
+{
+    let Some(transform) = attribute(e, b"transform")? else {
+        return Ok(Mat4::identity());
+    };
+
+    let m = transform
+        .split_whitespace()
+        .map(|v| v.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()?;
+
+    if m.len() != 12
+    {
+        return Err(ParseError::ParseError(format!("Invalid 3mf transform \"{}\"", transform)));
+    }
+
+    Ok(Mat4::new(
+        m[0], m[1], m[2], 0.0,
+        m[3], m[4], m[5], 0.0,
+        m[6], m[7], m[8], 0.0,
+        m[9], m[10], m[11], 1.0,
+    ))
+}
+
+fn normalize_path(path : &str) -> String
+{
+    String::from(path.trim_start_matches('/'))
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use three_d::*;
+use zip::ZipArchive;
+use crate::parse_3mf::attribute;
+use crate::parse_mesh::{Model, ModelPart, ParseError, UNSPECIFIED_COLOR};
+
+// ISO/ASTM 52915, https://en.wikipedia.org/wiki/Additive_manufacturing_file_format
+
+/// Curved triangles are split into this many parts along every edge
+const CURVE_SUBDIVISIONS : usize = 4;
+/// Constellations can reference each other in a loop in broken files
+const MAX_INSTANCE_DEPTH : usize = 32;
+
+#[derive(Default)]
+struct Vertex
+{
+    position: [f64; 3],
+    color: Option<Srgba>,
+}
+
+#[derive(Default)]
+struct Edge
+{
+    vertices: [usize; 2],
+    /// Tangent directions at both vertices
+    tangents: [[f64; 3]; 2],
+}
+
+#[derive(Default)]
+struct Triangle
+{
+    vertices: [usize; 3],
+    color: Option<Srgba>,
+}
+
+#[derive(Default)]
+struct Volume
+{
+    material_id: Option<String>,
+    color: Option<Srgba>,
+    triangles: Vec<Triangle>,
+}
+
+#[derive(Default)]
+struct Object
+{
+    name: String,
+    color: Option<Srgba>,
+    vertices: Vec<Vertex>,
+    /// Tangents of curved edges, by the vertices of the edge in both directions
+    edges: HashMap<(usize, usize), (Vector3<f64>, Vector3<f64>)>,
+    volumes: Vec<Volume>,
+}
+
+#[derive(Default)]
+struct Instance
+{
+    object_id: String,
+    delta: [f64; 3],
+    rotation: [f64; 3],
+}
+
+#[derive(Default)]
+struct AmfFile
+{
+    /// Object ids in file order
+    object_order: Vec<String>,
+    objects: HashMap<String, Object>,
+    materials: HashMap<String, Option<Srgba>>,
+    constellations: HashMap<String, Vec<Instance>>,
+}
+
+/// Parses a plain or zip compressed amf file. Every volume of every object becomes a part, named after its object.
+/// Objects are placed by the constellations in the file, or rendered as they are when there are none.
+pub fn parse(data : &[u8]) -> Result<Model, ParseError>
+{
+    let xml = if data.starts_with(b"PK")
+    {
+        let mut zip = ZipArchive::new(Cursor::new(data))?;
+        let index = (0..zip.len())
+            .find(|i| zip.name_for_index(*i).is_some_and(|n| n.to_lowercase().ends_with(".amf")))
+            .unwrap_or(0);
+
+        let mut file = zip.by_index(index)?;
+        let mut buffer = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buffer)?;
+        buffer
+    }
+    else
+    {
+        data.to_vec()
+    };
+
+    let amf = parse_xml(&String::from_utf8_lossy(&xml))?;
+    let mut parts = Vec::new();
+
+    if amf.constellations.is_empty()
+    {
+        for id in amf.object_order.iter() {
+            add_object(&amf, id, Matrix4::identity(), &mut parts);
+        }
+    }
+    else
+    {
+        let referenced : Vec<&String> = amf.constellations.values().flatten().map(|i| &i.object_id).collect();
+        let mut roots : Vec<&String> = amf.constellations.keys().filter(|id| !referenced.contains(id)).collect();
+        roots.sort();
+
+        for id in roots {
+            add_instance(&amf, id, Matrix4::identity(), 0, &mut parts)?;
+        }
+    }
+
+    if parts.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("No meshes found in amf model")));
+    }
+
+    Ok(Model { parts, toolpath: None })
+}
+
+fn parse_xml(xml : &str) -> Result<AmfFile, ParseError>
+{
+    let mut reader = Reader::from_str(xml);
+    let mut amf = AmfFile::default();
+
+    let mut stack : Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut metadata_type = String::new();
+    let mut color = [1f64; 4];
+    let mut color_valid = true;
+
+    let mut object : Option<(String, Object)> = None;
+    let mut volume : Option<Volume> = None;
+    let mut vertex = Vertex::default();
+    let mut edge = Edge::default();
+    let mut triangle = Triangle::default();
+    let mut material : Option<(String, Option<Srgba>)> = None;
+    let mut constellation : Option<(String, Vec<Instance>)> = None;
+    let mut instance = Instance::default();
+
+    loop
+    {
+        let event = reader.read_event()?;
+        let (start, end) = match &event
+        {
+            Event::Start(e) => (Some(e.clone()), None),
+            Event::Empty(e) => (Some(e.clone()), Some(e.local_name().as_ref().to_vec())),
+            Event::End(e) => (None, Some(e.local_name().as_ref().to_vec())),
+            Event::Text(t) => {
+                text.push_str(&t.unescape()?);
+                continue;
+            },
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        if let Some(e) = start
+        {
+            let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+            text.clear();
+
+            match name.as_str()
+            {
+                "object" => object = Some((attribute(&e, b"id")?.unwrap_or_default(), Object::default())),
+                "volume" => volume = Some(Volume { material_id: attribute(&e, b"materialid")?, ..Default::default() }),
+                "vertex" => vertex = Vertex::default(),
+                "edge" => edge = Edge::default(),
+                "triangle" => triangle = Triangle::default(),
+                "material" => material = Some((attribute(&e, b"id")?.unwrap_or_default(), None)),
+                "constellation" => constellation = Some((attribute(&e, b"id")?.unwrap_or_default(), Vec::new())),
+                "instance" => instance = Instance { object_id: attribute(&e, b"objectid")?.unwrap_or_default(), ..Default::default() },
+                "metadata" => metadata_type = attribute(&e, b"type")?.unwrap_or_default(),
+                "color" => {
+                    color = [1.0; 4];
+                    color_valid = true;
+                },
+                _ => {},
+            }
+
+            stack.push(name);
+        }
+
+        let Some(end) = end else { continue };
+        let name = String::from_utf8_lossy(&end).to_string();
+        stack.pop();
+
+        let parent = stack.last().map(|p| p.as_str()).unwrap_or("");
+        let number = || text.trim().parse::<f64>();
+        let index = || text.trim().parse::<usize>().map_err(|e| ParseError::ParseError(format!("Invalid amf index {}: {}", text.trim(), e)));
+
+        match (parent, name.as_str())
+        {
+            ("coordinates", axis @ ("x" | "y" | "z")) => {
+                let value = number()?;
+                match axis
+                {
+                    "x" => vertex.position[0] = value,
+                    "y" => vertex.position[1] = value,
+                    _ => vertex.position[2] = value,
+                }
+            },
+            ("color", channel @ ("r" | "g" | "b" | "a")) => {
+                // Channels can also be formulas, which are not supported
+                match number()
+                {
+                    Ok(value) => color[["r", "g", "b", "a"].iter().position(|c| *c == channel).unwrap()] = value,
+                    Err(_) => color_valid = false,
+                }
+            },
+            ("triangle", "v1") => triangle.vertices[0] = index()?,
+            ("triangle", "v2") => triangle.vertices[1] = index()?,
+            ("triangle", "v3") => triangle.vertices[2] = index()?,
+            ("edge", "v1") => edge.vertices[0] = index()?,
+            ("edge", "v2") => edge.vertices[1] = index()?,
+            ("edge", "dx1") => edge.tangents[0][0] = number()?,
+            ("edge", "dy1") => edge.tangents[0][1] = number()?,
+            ("edge", "dz1") => edge.tangents[0][2] = number()?,
+            ("edge", "dx2") => edge.tangents[1][0] = number()?,
+            ("edge", "dy2") => edge.tangents[1][1] = number()?,
+            ("edge", "dz2") => edge.tangents[1][2] = number()?,
+            ("instance", "deltax") => instance.delta[0] = number()?,
+            ("instance", "deltay") => instance.delta[1] = number()?,
+            ("instance", "deltaz") => instance.delta[2] = number()?,
+            ("instance", "rx") => instance.rotation[0] = number()?,
+            ("instance", "ry") => instance.rotation[1] = number()?,
+            ("instance", "rz") => instance.rotation[2] = number()?,
+            (_, "metadata") if metadata_type == "name" => {
+                if let Some((_, object)) = object.as_mut().filter(|_| volume.is_none())
+                {
+                    object.name = text.trim().to_string();
+                }
+            },
+            (owner, "color") if color_valid => {
+                let channel = |c : f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+                // Alpha is ignored, fully transparent vertices would keep the default color
+                let value = Some(Srgba::new_opaque(channel(color[0]), channel(color[1]), channel(color[2])));
+
+                match owner
+                {
+                    "triangle" => triangle.color = value,
+                    "vertex" => vertex.color = value,
+                    "volume" => if let Some(volume) = volume.as_mut() { volume.color = value },
+                    "object" => if let Some((_, object)) = object.as_mut() { object.color = value },
+                    "material" => if let Some((_, material)) = material.as_mut() { *material = value },
+                    _ => {},
+                }
+            },
+            (_, "vertex") => {
+                if let Some((_, object)) = object.as_mut()
+                {
+                    object.vertices.push(std::mem::take(&mut vertex));
+                }
+            },
+            (_, "edge") => {
+                if let Some((_, object)) = object.as_mut()
+                {
+                    let [v1, v2] = edge.vertices;
+                    let [d1, d2] = edge.tangents.map(Vector3::from);
+                    object.edges.insert((v1, v2), (d1, d2));
+                    // Both tangents point along the direction of travel, so they turn around with it
+                    object.edges.insert((v2, v1), (-d2, -d1));
+                }
+            },
+            (_, "triangle") => {
+                if let Some(volume) = volume.as_mut()
+                {
+                    volume.triangles.push(std::mem::take(&mut triangle));
+                }
+            },
+            (_, "volume") => {
+                if let (Some((_, object)), Some(volume)) = (object.as_mut(), volume.take())
+                {
+                    object.volumes.push(volume);
+                }
+            },
+            (_, "object") => {
+                if let Some((id, object)) = object.take()
+                {
+                    if object.volumes.iter().flat_map(|v| v.triangles.iter()).flat_map(|t| t.vertices).any(|v| v >= object.vertices.len())
+                    {
+                        return Err(ParseError::ParseError(format!("Amf object {} references a missing vertex", id)));
+                    }
+
+                    amf.object_order.push(id.clone());
+                    amf.objects.insert(id, object);
+                }
+            },
+            (_, "material") => {
+                if let Some((id, color)) = material.take()
+                {
+                    amf.materials.insert(id, color);
+                }
+            },
+            (_, "instance") => {
+                if let Some((_, instances)) = constellation.as_mut()
+                {
+                    instances.push(std::mem::take(&mut instance));
+                }
+            },
+            (_, "constellation") => {
+                if let Some((id, instances)) = constellation.take()
+                {
+                    amf.constellations.insert(id, instances);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok(amf)
+}
+
+fn add_instance(amf : &AmfFile, id : &str, transform : Matrix4<f64>, depth : usize, parts : &mut Vec<ModelPart>) -> Result<(), ParseError>
+{
+    if depth > MAX_INSTANCE_DEPTH
+    {
+        return Err(ParseError::ParseError(String::from("Amf constellations are nested too deep")));
+    }
+
+    if amf.objects.contains_key(id)
+    {
+        add_object(amf, id, transform, parts);
+        return Ok(());
+    }
+
+    for instance in amf.constellations.get(id).map(|c| c.as_slice()).unwrap_or(&[]) {
+        // Rotations are applied around x, then y, then z, before the translation
+        let local = Matrix4::from_translation(Vector3::new(instance.delta[0], instance.delta[1], instance.delta[2]))
+            * Matrix4::from_angle_z(Deg(instance.rotation[2]))
+            * Matrix4::from_angle_y(Deg(instance.rotation[1]))
+            * Matrix4::from_angle_x(Deg(instance.rotation[0]));
+
+        add_instance(amf, &instance.object_id, transform * local, depth + 1, parts)?;
+    }
+
+    Ok(())
+}
+
+fn add_object(amf : &AmfFile, id : &str, transform : Matrix4<f64>, parts : &mut Vec<ModelPart>)
+{
+    let object = match amf.objects.get(id) {
+        Some(object) => object,
+        None => return,
+    };
+
+    for volume in object.volumes.iter() {
+        if volume.triangles.is_empty()
+        {
+            continue;
+        }
+
+        let material_color = volume.material_id.as_ref().and_then(|m| amf.materials.get(m)).copied().flatten();
+        let base_color = volume.color.or(material_color).or(object.color);
+
+        // Vertex colors are only needed when the color changes within the volume
+        let vertex_colors = volume.triangles
+            .iter()
+            .any(|t| t.color.is_some() || t.vertices.iter().any(|v| object.vertices[*v].color.is_some()));
+
+        let mut positions : Vec<Vector3<f64>> = Vec::new();
+        let mut colors : Vec<Srgba> = Vec::new();
+        let mut indices : Vec<u32> = Vec::new();
+
+        for triangle in volume.triangles.iter() {
+            let corner_colors = triangle.vertices.map(|v| triangle.color
+                .or(object.vertices[v].color)
+                .or(base_color)
+                .unwrap_or(UNSPECIFIED_COLOR));
+
+            for (position, weights) in tessellate_triangle(object, triangle) {
+                indices.push(positions.len() as u32);
+                positions.push((transform * position.extend(1.0)).truncate());
+
+                if vertex_colors
+                {
+                    colors.push(blend_colors(&corner_colors, weights));
+                }
+            }
+        }
+
+        parts.push(ModelPart {
+            name: object.name.clone(),
+            mesh: CpuMesh {
+                positions: Positions::F64(positions),
+                indices: Indices::U32(indices),
+                colors: if vertex_colors { Some(colors) } else { None },
+                ..Default::default()
+            },
+            color: base_color,
+            texture: None,
+        });
+    }
+}
+
+/// Side of a triangle along a curved edge, as corner indices of the triangle with the tangents at both ends
+struct CurvedSide
+{
+    a: usize,
+    b: usize,
+    opposite: usize,
+    da: Vector3<f64>,
+    db: Vector3<f64>,
+}
+
+/// Corners of the triangles a triangle is rendered as, with their barycentric weights.
+/// Triangles with curved edges are subdivided, edges without tangents stay straight.
+fn tessellate_triangle(object : &Object, triangle : &Triangle) -> Vec<(Vector3<f64>, [f64; 3])>
+{
+    let corners = triangle.vertices.map(|v| Vector3::from(object.vertices[v].position));
+    let sides = [(0, 1, 2), (1, 2, 0), (2, 0, 1)];
+    let curves : Vec<CurvedSide> = sides
+        .iter()
+        .filter_map(|(a, b, opposite)| object.edges
+            .get(&(triangle.vertices[*a], triangle.vertices[*b]))
+            .map(|(da, db)| CurvedSide { a: *a, b: *b, opposite: *opposite, da: *da, db: *db }))
+        .collect();
+
+    if curves.is_empty()
+    {
+        return vec![(corners[0], [1.0, 0.0, 0.0]), (corners[1], [0.0, 1.0, 0.0]), (corners[2], [0.0, 0.0, 1.0])];
+    }
+
+    // Every curved edge bends the triangle, fading out towards the opposite corner
+    let point = |weights : [f64; 3]| {
+        let mut position = corners[0] * weights[0] + corners[1] * weights[1] + corners[2] * weights[2];
+
+        for CurvedSide { a, b, opposite, da, db } in curves.iter() {
+            let along = weights[*a] + weights[*b];
+
+            if along <= 0.0
+            {
+                continue;
+            }
+
+            position += (hermite(corners[*a], *da, corners[*b], *db, weights[*b] / along) - (corners[*a] * (weights[*a] / along) + corners[*b] * (weights[*b] / along)))
+                * (1.0 - weights[*opposite]);
+        }
+
+        (position, weights)
+    };
+
+    let n = CURVE_SUBDIVISIONS;
+    let weights = |i : usize, j : usize| {
+        let (u, v) = (i as f64 / n as f64, j as f64 / n as f64);
+        [1.0 - u - v, u, v]
+    };
+
+    let mut corners_out = Vec::with_capacity(n * n * 3);
+
+    for j in 0..n {
+        for i in 0..n - j {
+            corners_out.extend([point(weights(i, j)), point(weights(i + 1, j)), point(weights(i, j + 1))]);
+
+            if i + j + 1 < n
+            {
+                corners_out.extend([point(weights(i + 1, j)), point(weights(i + 1, j + 1)), point(weights(i, j + 1))]);
+            }
+        }
+    }
+
+    corners_out
+}
+
+/// Cubic Hermite curve between two points, with unit tangents scaled by the length of the edge
+fn hermite(p0 : Vector3<f64>, d0 : Vector3<f64>, p1 : Vector3<f64>, d1 : Vector3<f64>, t : f64) -> Vector3<f64>
+{
+    let length = (p1 - p0).magnitude();
+    let (t2, t3) = (t * t, t * t * t);
+
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + d0 * (length * (t3 - 2.0 * t2 + t))
+        + p1 * (-2.0 * t3 + 3.0 * t2)
+        + d1 * (length * (t3 - t2))
+}
+
+fn blend_colors(colors : &[Srgba; 3], weights : [f64; 3]) -> Srgba
+{
+    // Mixing in unspecified corners would darken the color
+    if colors.iter().any(|c| c.a == 0)
+    {
+        let index = (0..3).max_by(|a, b| weights[*a].total_cmp(&weights[*b])).unwrap();
+        return colors[index];
+    }
+
+    let channel = |f : fn(&Srgba) -> u8| (0..3).map(|i| f(&colors[i]) as f64 * weights[i]).sum::<f64>().round() as u8;
+    Srgba::new_opaque(channel(|c| c.r), channel(|c| c.g), channel(|c| c.b))
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use three_d::*;
+use crate::geometry::triangulate;
+use crate::parse_mesh::ParseError;
+
+// https://www.khronos.org/files/collada_spec_1_5.pdf
+
+/// Node instances can reference each other in a loop in broken files
+const MAX_NODE_DEPTH : usize = 64;
+
+/// Collada files reference elements by id all over the document, so it is read into a tree first
+struct Element
+{
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element
+{
+    fn child(&self, name : &str) -> Option<&Element>
+    {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name : &'a str) -> impl Iterator<Item = &'a Element>
+    {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn attribute(&self, name : &str) -> Option<&str>
+    {
+        self.attributes.get(name).map(|a| a.as_str())
+    }
+
+    fn numbers(&self) -> Result<Vec<f64>, ParseError>
+    {
+        Ok(self.text.split_whitespace().map(|v| v.parse::<f64>()).collect::<Result<Vec<f64>, _>>()?)
+    }
+
+    fn indices(&self) -> Result<Vec<usize>, ParseError>
+    {
+        self.text
+            .split_whitespace()
+            .map(|v| v.parse::<usize>().map_err(|_| ParseError::ParseError(format!("Invalid dae index {}", v))))
+            .collect()
+    }
+}
+
+struct Document<'a>
+{
+    ids: HashMap<&'a str, &'a Element>,
+}
+
+impl<'a> Document<'a>
+{
+    /// Resolves a `#id` url, or a plain id as used by some input sources
+    fn get(&self, url : &str) -> Option<&'a Element>
+    {
+        self.ids.get(url.trim_start_matches('#')).copied()
+    }
+}
+
+/// Parses a collada file into a single mesh. Geometry is placed by the node hierarchy of the visual scene,
+/// and rotated so the up axis of the file is y.
+pub fn parse(data : &str) -> Result<CpuMesh, ParseError>
+{
+    let root = parse_tree(data)?;
+
+    if root.name != "COLLADA"
+    {
+        return Err(ParseError::ParseError(String::from("Not a collada file")));
+    }
+
+    let mut document = Document { ids: HashMap::new() };
+    collect_ids(&root, &mut document.ids);
+
+    let up_axis = root.child("asset").and_then(|a| a.child("up_axis")).map(|u| u.text.trim()).unwrap_or("Y_UP");
+    let up = match up_axis
+    {
+        "Z_UP" => Matrix4::from_angle_x(Deg(-90.0)),
+        "X_UP" => Matrix4::from_angle_z(Deg(90.0)),
+        _ => Matrix4::identity(),
+    };
+
+    let mut positions : Vec<Vector3<f64>> = Vec::new();
+    let mut indices : Vec<u32> = Vec::new();
+
+    let scene = root
+        .child("scene")
+        .and_then(|s| s.child("instance_visual_scene"))
+        .and_then(|i| i.attribute("url"))
+        .and_then(|url| document.get(url))
+        .or_else(|| root.child("library_visual_scenes").and_then(|l| l.child("visual_scene")));
+
+    match scene
+    {
+        Some(scene) => {
+            for node in scene.children("node") {
+                add_node(&document, node, up, 0, &mut positions, &mut indices)?;
+            }
+        },
+        None => {
+            // Without a scene, every geometry is shown where it is
+            for geometry in root.children("library_geometries").flat_map(|l| l.children("geometry")) {
+                add_geometry(&document, geometry, up, &mut positions, &mut indices)?;
+            }
+        },
+    }
+
+    if indices.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("Dae file contains no meshes")));
+    }
+
+    Ok(CpuMesh {
+        positions: Positions::F64(positions),
+        indices: Indices::U32(indices),
+        ..Default::default()
+    })
+}
+
+fn parse_tree(data : &str) -> Result<Element, ParseError>
+{
+    let mut reader = Reader::from_str(data);
+    let mut stack : Vec<Element> = vec![Element { name: String::new(), attributes: HashMap::new(), children: Vec::new(), text: String::new() }];
+
+    let new_element = |e : &BytesStart| -> Result<Element, ParseError> {
+        let mut attributes = HashMap::new();
+
+        for attr in e.attributes() {
+            let attr = attr.map_err(|e| ParseError::ParseError(format!("Invalid dae attribute: {}", e)))?;
+            attributes.insert(
+                String::from_utf8_lossy(attr.key.local_name().as_ref()).to_string(),
+                attr.unescape_value()?.into_owned());
+        }
+
+        Ok(Element {
+            name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+            attributes,
+            children: Vec::new(),
+            text: String::new(),
+        })
+    };
+
+    loop
+    {
+        match reader.read_event()?
+        {
+            Event::Start(e) => stack.push(new_element(&e)?),
+            Event::Empty(e) => {
+                let element = new_element(&e)?;
+                stack.last_mut().unwrap().children.push(element);
+            },
+            Event::Text(t) => stack.last_mut().unwrap().text.push_str(&t.unescape()?),
+            Event::End(_) => {
+                if stack.len() > 1
+                {
+                    let element = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(element);
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    stack
+        .swap_remove(0)
+        .children
+        .pop()
+        .ok_or_else(|| ParseError::ParseError(String::from("Dae file is empty")))
+}
+
+fn collect_ids<'a>(element : &'a Element, ids : &mut HashMap<&'a str, &'a Element>)
+{
+    if let Some(id) = element.attribute("id")
+    {
+        ids.insert(id, element);
+    }
+
+    for child in element.children.iter() {
+        collect_ids(child, ids);
+    }
+}
+
+fn add_node(document : &Document, node : &Element, parent : Matrix4<f64>, depth : usize, positions : &mut Vec<Vector3<f64>>, indices : &mut Vec<u32>) -> Result<(), ParseError>
+{
+    if depth > MAX_NODE_DEPTH
+    {
+        return Err(ParseError::ParseError(String::from("Dae nodes are nested too deep")));
+    }
+
+    let mut transform = parent;
+
+    // Transforms are applied in document order, each one in the space of the ones before it
+    for child in node.children.iter() {
+        let values = match child.name.as_str() {
+            "matrix" | "translate" | "rotate" | "scale" => child.numbers()?,
+            _ => continue,
+        };
+
+        let local = match (child.name.as_str(), values.as_slice())
+        {
+            ("matrix", m) if m.len() == 16 => row_major(m),
+            ("translate", [x, y, z]) => Matrix4::from_translation(Vector3::new(*x, *y, *z)),
+            ("rotate", [x, y, z, angle]) if x * x + y * y + z * z > 0.0 => Matrix4::from_axis_angle(Vector3::new(*x, *y, *z).normalize(), Deg(*angle)),
+            ("scale", [x, y, z]) => Matrix4::from_nonuniform_scale(*x, *y, *z),
+            _ => continue,
+        };
+
+        transform = transform * local;
+    }
+
+    for child in node.children.iter() {
+        let target = child.attribute("url").and_then(|url| document.get(url));
+
+        match (child.name.as_str(), target)
+        {
+            ("node", _) => add_node(document, child, transform, depth + 1, positions, indices)?,
+            ("instance_node", Some(target)) => add_node(document, target, transform, depth + 1, positions, indices)?,
+            ("instance_geometry", Some(target)) => add_geometry(document, target, transform, positions, indices)?,
+            ("instance_controller", Some(controller)) => {
+                // Skinned meshes are shown in their bind pose
+                if let Some(skin) = controller.child("skin")
+                {
+                    let bind_shape = match skin.child("bind_shape_matrix").map(|b| b.numbers()).transpose()? {
+                        Some(m) if m.len() == 16 => row_major(&m),
+                        _ => Matrix4::identity(),
+                    };
+
+                    if let Some(geometry) = skin.attribute("source").and_then(|s| document.get(s))
+                    {
+                        add_geometry(document, geometry, transform * bind_shape, positions, indices)?;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+fn add_geometry(document : &Document, geometry : &Element, transform : Matrix4<f64>, positions : &mut Vec<Vector3<f64>>, indices : &mut Vec<u32>) -> Result<(), ParseError>
+{
+    // Splines and other geometry types are not supported
+    let mesh = match geometry.child("mesh") {
+        Some(mesh) => mesh,
+        None => return Ok(()),
+    };
+
+    for primitive in mesh.children.iter() {
+        if !["triangles", "polylist", "polygons", "trifans", "tristrips"].contains(&primitive.name.as_str())
+        {
+            continue;
+        }
+
+        let inputs : Vec<&Element> = primitive.children("input").collect();
+        let stride = inputs
+            .iter()
+            .filter_map(|i| i.attribute("offset").and_then(|o| o.parse::<usize>().ok()))
+            .max()
+            .unwrap_or(0) + 1;
+
+        let vertex_input = match inputs.iter().find(|i| i.attribute("semantic") == Some("VERTEX")) {
+            Some(input) => input,
+            None => continue,
+        };
+
+        let offset = vertex_input.attribute("offset").and_then(|o| o.parse::<usize>().ok()).unwrap_or(0);
+        let vertices = vertex_input
+            .attribute("source")
+            .and_then(|s| document.get(s))
+            .ok_or_else(|| ParseError::ParseError(String::from("Dae primitive references missing vertices")))?;
+
+        let source = vertices
+            .children("input")
+            .find(|i| i.attribute("semantic") == Some("POSITION"))
+            .and_then(|i| i.attribute("source"))
+            .and_then(|s| document.get(s))
+            .ok_or_else(|| ParseError::ParseError(String::from("Dae vertices have no positions")))?;
+
+        let points = read_source(source)?;
+        let start = positions.len() as u32;
+        positions.extend(points.iter().map(|p| (transform * p.extend(1.0)).truncate()));
+
+        // Every <p> holds the indices of all inputs, interleaved
+        let lists = primitive
+            .children("p")
+            .chain(primitive.children("ph").filter_map(|ph| ph.child("p")))
+            .map(|p| p.indices().map(|i| i.into_iter().skip(offset).step_by(stride).collect::<Vec<usize>>()))
+            .collect::<Result<Vec<Vec<usize>>, _>>()?;
+
+        if lists.iter().flatten().any(|i| *i >= points.len())
+        {
+            return Err(ParseError::ParseError(String::from("Dae primitive references a vertex that does not exist")));
+        }
+
+        let mut triangles : Vec<[usize; 3]> = Vec::new();
+
+        match primitive.name.as_str()
+        {
+            "triangles" => {
+                for list in lists.iter() {
+                    triangles.extend(list.chunks_exact(3).map(|t| [t[0], t[1], t[2]]));
+                }
+            },
+            "polylist" => {
+                let counts = primitive.child("vcount").map(|v| v.indices()).transpose()?.unwrap_or_default();
+                let list = lists.concat();
+                let mut position = 0;
+
+                for count in counts {
+                    if position + count > list.len()
+                    {
+                        break;
+                    }
+
+                    let polygon = &list[position..position + count];
+                    triangles.extend(triangulate(&points, polygon).iter().map(|t| t.map(|i| polygon[i])));
+                    position += count;
+                }
+            },
+            "polygons" => {
+                for polygon in lists.iter() {
+                    triangles.extend(triangulate(&points, polygon).iter().map(|t| t.map(|i| polygon[i])));
+                }
+            },
+            "trifans" => {
+                for fan in lists.iter().filter(|l| l.len() >= 3) {
+                    triangles.extend((1..fan.len() - 1).map(|i| [fan[0], fan[i], fan[i + 1]]));
+                }
+            },
+            _ => {
+                // Every other triangle of a strip is flipped to keep the winding consistent
+                for strip in lists.iter().filter(|l| l.len() >= 3) {
+                    triangles.extend((0..strip.len() - 2).map(|i| if i % 2 == 0 {
+                        [strip[i], strip[i + 1], strip[i + 2]]
+                    } else {
+                        [strip[i + 1], strip[i], strip[i + 2]]
+                    }));
+                }
+            },
+        }
+
+        indices.extend(triangles.iter().flatten().map(|i| start + *i as u32));
+    }
+
+    Ok(())
+}
+
+/// Reads the points of a source, using the stride and offset of its accessor
+fn read_source(source : &Element) -> Result<Vec<Vector3<f64>>, ParseError>
+{
+    let values = source
+        .child("float_array")
+        .ok_or_else(|| ParseError::ParseError(String::from("Dae source has no float array")))?
+        .numbers()?;
+
+    let accessor = source.child("technique_common").and_then(|t| t.child("accessor"));
+    let number = |name : &str, default : usize| accessor
+        .and_then(|a| a.attribute(name))
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(default);
+
+    let stride = number("stride", 3).max(3);
+    let offset = number("offset", 0);
+    let count = number("count", values.len().saturating_sub(offset) / stride);
+
+    Ok((0..count)
+        .map(|i| offset + i * stride)
+        .take_while(|i| i + 3 <= values.len())
+        .map(|i| Vector3::new(values[i], values[i + 1], values[i + 2]))
+        .collect())
+}
+
+/// Collada stores matrices row by row
+fn row_major(m : &[f64]) -> Matrix4<f64>
+{
+    Matrix4::new(
+        m[0], m[4], m[8], m[12],
+        m[1], m[5], m[9], m[13],
+        m[2], m[6], m[10], m[14],
+        m[3], m[7], m[11], m[15],
+    )
+}
@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::Value;
+use three_d::*;
+use crate::parse_mesh::{Model, ModelPart, ParseError};
+
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+const GLB_MAGIC : &[u8] = b"glTF";
+const GLB_CHUNK_JSON : u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN : u32 = 0x004E4942;
+/// Nodes can reference each other in a loop in broken files
+const MAX_NODE_DEPTH : usize = 64;
+
+const MODE_TRIANGLES : u64 = 4;
+const MODE_TRIANGLE_STRIP : u64 = 5;
+const MODE_TRIANGLE_FAN : u64 = 6;
+
+struct Document
+{
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+    directory: PathBuf,
+    /// Decoded images by image index, None when the image could not be loaded
+    images: HashMap<usize, Option<CpuTexture>>,
+}
+
+/// Parses a .gltf file with embedded or external buffers, or a binary .glb file.
+/// Every triangle primitive of every mesh in the scene becomes a part, named after its node.
+/// Point and line primitives, and sparse accessors, are not supported.
+pub fn parse(path : &str) -> Result<Model, ParseError>
+{
+    let data = fs::read(path)?;
+    let directory = Path::new(path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+    let (json, bin) = if data.starts_with(GLB_MAGIC)
+    {
+        parse_glb(&data)?
+    }
+    else
+    {
+        (parse_json(&data)?, None)
+    };
+
+    let mut document = Document { json, buffers: Vec::new(), directory, images: HashMap::new() };
+    document.buffers = load_buffers(&document, bin)?;
+
+    let nodes = scene_nodes(&document.json);
+    let mut parts = Vec::new();
+
+    for node in nodes {
+        add_node(&mut document, node, Mat4::identity(), 0, &mut parts)?;
+    }
+
+    if parts.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("No meshes found in gltf model")));
+    }
+
+    Ok(Model { parts, toolpath: None })
+}
+
+fn parse_json(data : &[u8]) -> Result<Value, ParseError>
+{
+    serde_json::from_slice(data).map_err(|e| ParseError::ParseError(format!("Invalid gltf json: {}", e)))
+}
+
+fn parse_glb(data : &[u8]) -> Result<(Value, Option<Vec<u8>>), ParseError>
+{
+    if data.len() < 12 || read_u32(data, 4) != 2
+    {
+        return Err(ParseError::ParseError(String::from("Unsupported glb version")));
+    }
+
+    let length = (read_u32(data, 8) as usize).min(data.len());
+    let mut position = 12;
+    let mut json = None;
+    let mut bin = None;
+
+    while position + 8 <= length {
+        let chunk_length = read_u32(data, position) as usize;
+        let chunk_type = read_u32(data, position + 4);
+        let start = position + 8;
+
+        if start + chunk_length > length
+        {
+            return Err(ParseError::ParseError(String::from("Glb chunk is larger than the file")));
+        }
+
+        let chunk = &data[start..start + chunk_length];
+
+        match chunk_type
+        {
+            GLB_CHUNK_JSON => json = Some(parse_json(chunk)?),
+            GLB_CHUNK_BIN if bin.is_none() => bin = Some(chunk.to_vec()),
+            _ => {}
+        }
+
+        position = start + chunk_length;
+    }
+
+    match json
+    {
+        Some(json) => Ok((json, bin)),
+        None => Err(ParseError::ParseError(String::from("Glb file has no json chunk"))),
+    }
+}
+
+fn read_u32(data : &[u8], position : usize) -> u32
+{
+    u32::from_le_bytes([data[position], data[position + 1], data[position + 2], data[position + 3]])
+}
+
+fn load_buffers(document : &Document, bin : Option<Vec<u8>>) -> Result<Vec<Vec<u8>>, ParseError>
+{
+    let mut bin = bin;
+    let mut buffers = Vec::new();
+
+    for buffer in array(&document.json, "buffers") {
+        let data = match buffer.get("uri").and_then(|u| u.as_str())
+        {
+            Some(uri) => read_uri(&document.directory, uri)?,
+            // Only the first buffer of a glb can point at the binary chunk
+            None => bin.take().ok_or_else(|| ParseError::ParseError(String::from("Gltf buffer has no data")))?,
+        };
+
+        buffers.push(data);
+    }
+
+    Ok(buffers)
+}
+
+/// Reads a data uri or a file relative to the gltf file
+fn read_uri(directory : &Path, uri : &str) -> Result<Vec<u8>, ParseError>
+{
+    if uri.starts_with("data:")
+    {
+        let (_, data) = uri.split_once(',').ok_or_else(|| ParseError::ParseError(String::from("Invalid gltf data uri")))?;
+
+        return STANDARD.decode(data).map_err(|e| ParseError::ParseError(format!("Invalid gltf data uri: {}", e)));
+    }
+
+    Ok(fs::read(directory.join(percent_decode(uri)))?)
+}
+
+fn percent_decode(uri : &str) -> String
+{
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (bytes[i], hex)
+        {
+            (b'%', Some(value)) => {
+                decoded.push(value);
+                i += 3;
+            }
+            (c, _) => {
+                decoded.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Root nodes of the default scene. Files without scenes render every node that is not a child of another node.
+fn scene_nodes(json : &Value) -> Vec<usize>
+{
+    let scene_index = json.get("scene").and_then(|s| s.as_u64()).unwrap_or(0) as usize;
+
+    if let Some(scene) = array(json, "scenes").get(scene_index)
+    {
+        return indices(scene, "nodes");
+    }
+
+    let nodes = array(json, "nodes");
+    let children : Vec<usize> = nodes.iter().flat_map(|n| indices(n, "children")).collect();
+
+    (0..nodes.len()).filter(|n| !children.contains(n)).collect()
+}
+
+fn add_node(document : &mut Document, index : usize, parent : Mat4, depth : usize, parts : &mut Vec<ModelPart>) -> Result<(), ParseError>
+{
+    if depth > MAX_NODE_DEPTH
+    {
+        return Err(ParseError::ParseError(String::from("Gltf node hierarchy is too deep")));
+    }
+
+    let node = array(&document.json, "nodes")
+        .get(index)
+        .cloned()
+        .ok_or_else(|| ParseError::ParseError(format!("Gltf node {} does not exist", index)))?;
+
+    let transform = parent * node_transform(&node);
+
+    if let Some(mesh_index) = node.get("mesh").and_then(|m| m.as_u64())
+    {
+        let mesh = array(&document.json, "meshes")
+            .get(mesh_index as usize)
+            .cloned()
+            .ok_or_else(|| ParseError::ParseError(format!("Gltf mesh {} does not exist", mesh_index)))?;
+
+        let name = node.get("name")
+            .or_else(|| mesh.get("name"))
+            .and_then(|n| n.as_str())
+            .unwrap_or("")
+            .to_string();
+
+        for primitive in array(&mesh, "primitives") {
+            if let Some(part) = parse_primitive(document, primitive, &name, transform)?
+            {
+                parts.push(part);
+            }
+        }
+    }
+
+    for child in indices(&node, "children") {
+        add_node(document, child, transform, depth + 1, parts)?;
+    }
+
+    Ok(())
+}
+
+fn node_transform(node : &Value) -> Mat4
+{
+    let numbers = |name : &str| -> Option<Vec<f32>> {
+        node.get(name)?.as_array()?.iter().map(|v| v.as_f64().map(|v| v as f32)).collect()
+    };
+
+    if let Some(m) = numbers("matrix").filter(|m| m.len() == 16)
+    {
+        // Column major, same as cgmath
+        return Mat4::new(
+            m[0], m[1], m[2], m[3],
+            m[4], m[5], m[6], m[7],
+            m[8], m[9], m[10], m[11],
+            m[12], m[13], m[14], m[15],
+        );
+    }
+
+    let mut transform = Mat4::identity();
+
+    if let Some(t) = numbers("translation").filter(|t| t.len() == 3)
+    {
+        transform = transform * Mat4::from_translation(vec3(t[0], t[1], t[2]));
+    }
+
+    if let Some(r) = numbers("rotation").filter(|r| r.len() == 4)
+    {
+        transform = transform * Mat4::from(Quat::new(r[3], r[0], r[1], r[2]));
+    }
+
+    if let Some(s) = numbers("scale").filter(|s| s.len() == 3)
+    {
+        transform = transform * Mat4::from_nonuniform_scale(s[0], s[1], s[2]);
+    }
+
+    transform
+}
+
+fn parse_primitive(document : &mut Document, primitive : &Value, name : &str, transform : Mat4) -> Result<Option<ModelPart>, ParseError>
+{
+    let mode = primitive.get("mode").and_then(|m| m.as_u64()).unwrap_or(MODE_TRIANGLES);
+
+    if mode != MODE_TRIANGLES && mode != MODE_TRIANGLE_STRIP && mode != MODE_TRIANGLE_FAN
+    {
+        return Ok(None);
+    }
+
+    let attributes = primitive.get("attributes");
+    let attribute = |name : &str| attributes.and_then(|a| a.get(name)).and_then(|a| a.as_u64()).map(|a| a as usize);
+
+    let position_accessor = match attribute("POSITION") {
+        Some(a) => a,
+        None => return Ok(None),
+    };
+
+    let (values, components) = read_accessor(document, position_accessor)?;
+
+    if components != 3
+    {
+        return Err(ParseError::ParseError(String::from("Gltf positions are not 3 dimensional")));
+    }
+
+    let positions : Vec<Vec3> = values
+        .chunks_exact(3)
+        .map(|p| (transform * vec4(p[0] as f32, p[1] as f32, p[2] as f32, 1.0)).truncate())
+        .collect();
+
+    let vertex_indices : Vec<u32> = match primitive.get("indices").and_then(|i| i.as_u64())
+    {
+        Some(accessor) => read_accessor(document, accessor as usize)?.0.iter().map(|i| *i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    if vertex_indices.iter().any(|i| *i as usize >= positions.len())
+    {
+        return Err(ParseError::ParseError(String::from("Gltf primitive references a vertex that does not exist")));
+    }
+
+    let indices = match mode
+    {
+        MODE_TRIANGLE_STRIP => (2..vertex_indices.len())
+            .flat_map(|i| if i % 2 == 0 {
+                [vertex_indices[i - 2], vertex_indices[i - 1], vertex_indices[i]]
+            } else {
+                [vertex_indices[i - 1], vertex_indices[i - 2], vertex_indices[i]]
+            })
+            .collect(),
+        MODE_TRIANGLE_FAN => (2..vertex_indices.len())
+            .flat_map(|i| [vertex_indices[0], vertex_indices[i - 1], vertex_indices[i]])
+            .collect(),
+        _ => vertex_indices.chunks_exact(3).flatten().copied().collect::<Vec<u32>>(),
+    };
+
+    if indices.is_empty()
+    {
+        return Ok(None);
+    }
+
+    let colors = match attribute("COLOR_0")
+    {
+        Some(accessor) => {
+            let (values, components) = read_accessor(document, accessor)?;
+
+            // Alpha is ignored, fully transparent vertices would keep the default color
+            Some(values
+                .chunks_exact(components.max(3))
+                .map(|c| Srgba::new_opaque(linear_to_srgb(c[0]), linear_to_srgb(c[1]), linear_to_srgb(c[2])))
+                .collect::<Vec<Srgba>>())
+                .filter(|c| c.len() == positions.len())
+        }
+        None => None,
+    };
+
+    let material = primitive.get("material")
+        .and_then(|m| m.as_u64())
+        .and_then(|m| array(&document.json, "materials").get(m as usize).cloned());
+    let pbr = material.as_ref().and_then(|m| m.get("pbrMetallicRoughness"));
+
+    let color = pbr
+        .and_then(|p| p.get("baseColorFactor"))
+        .and_then(|f| f.as_array())
+        .filter(|f| f.len() >= 3)
+        .map(|f| {
+            let channel = |i : usize| linear_to_srgb(f[i].as_f64().unwrap_or(1.0));
+            Srgba::new_opaque(channel(0), channel(1), channel(2))
+        });
+
+    let mut texture = None;
+    let mut uvs = None;
+
+    if let Some(texture_info) = pbr.and_then(|p| p.get("baseColorTexture"))
+    {
+        let texture_coordinate = texture_info.get("texCoord").and_then(|t| t.as_u64()).unwrap_or(0);
+        let image = texture_info.get("index")
+            .and_then(|i| i.as_u64())
+            .and_then(|i| array(&document.json, "textures").get(i as usize))
+            .and_then(|t| t.get("source"))
+            .and_then(|s| s.as_u64());
+
+        if let (Some(image), Some(accessor)) = (image, attribute(&format!("TEXCOORD_{}", texture_coordinate)))
+        {
+            let (values, _) = read_accessor(document, accessor)?;
+            let coordinates : Vec<Vec2> = values.chunks_exact(2).map(|uv| vec2(uv[0] as f32, uv[1] as f32)).collect();
+
+            texture = load_image(document, image as usize);
+
+            if texture.is_some() && coordinates.len() == positions.len()
+            {
+                uvs = Some(coordinates);
+            }
+            else
+            {
+                texture = None;
+            }
+        }
+    }
+
+    Ok(Some(ModelPart {
+        name: name.to_string(),
+        mesh: CpuMesh {
+            positions: Positions::F32(positions),
+            indices: Indices::U32(indices),
+            colors,
+            uvs,
+            ..Default::default()
+        },
+        color,
+        texture,
+    }))
+}
+
+/// Reads every element of an accessor, returned as a flat list together with the amount of components per element
+fn read_accessor(document : &Document, index : usize) -> Result<(Vec<f64>, usize), ParseError>
+{
+    let accessor = array(&document.json, "accessors")
+        .get(index)
+        .ok_or_else(|| ParseError::ParseError(format!("Gltf accessor {} does not exist", index)))?;
+
+    let count = accessor.get("count").and_then(|c| c.as_u64()).unwrap_or(0) as usize;
+    let normalized = accessor.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false);
+    let component_type = accessor.get("componentType").and_then(|c| c.as_u64()).unwrap_or(0);
+    let components = match accessor.get("type").and_then(|t| t.as_str()).unwrap_or("")
+    {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" => 4,
+        "MAT2" => 4,
+        "MAT3" => 9,
+        "MAT4" => 16,
+        t => return Err(ParseError::ParseError(format!("Unknown gltf accessor type {}", t))),
+    };
+
+    let size = match component_type
+    {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        t => return Err(ParseError::ParseError(format!("Unknown gltf component type {}", t))),
+    };
+
+    let view = match accessor.get("bufferView").and_then(|v| v.as_u64())
+    {
+        Some(view) => array(&document.json, "bufferViews")
+            .get(view as usize)
+            .ok_or_else(|| ParseError::ParseError(format!("Gltf buffer view {} does not exist", view)))?,
+        // Accessors without a buffer view are all zeros
+        None => return Ok((vec![0f64; count * components], components)),
+    };
+
+    let buffer = view.get("buffer")
+        .and_then(|b| b.as_u64())
+        .and_then(|b| document.buffers.get(b as usize))
+        .ok_or_else(|| ParseError::ParseError(String::from("Gltf buffer view references a buffer that does not exist")))?;
+
+    let offset = view.get("byteOffset").and_then(|o| o.as_u64()).unwrap_or(0) as usize
+        + accessor.get("byteOffset").and_then(|o| o.as_u64()).unwrap_or(0) as usize;
+    let stride = view.get("byteStride").and_then(|s| s.as_u64()).map(|s| s as usize).unwrap_or(size * components);
+
+    if count > 0 && offset + (count - 1) * stride + size * components > buffer.len()
+    {
+        return Err(ParseError::ParseError(String::from("Gltf accessor is larger than its buffer")));
+    }
+
+    let mut values = Vec::with_capacity(count * components);
+
+    for i in 0..count {
+        for c in 0..components {
+            let p = offset + i * stride + c * size;
+            let b = &buffer[p..p + size];
+
+            let value = match component_type
+            {
+                5120 => b[0] as i8 as f64,
+                5121 => b[0] as f64,
+                5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            };
+
+            values.push(if normalized
+            {
+                match component_type
+                {
+                    5120 => (value / 127.0).max(-1.0),
+                    5121 => value / 255.0,
+                    5122 => (value / 32767.0).max(-1.0),
+                    5123 => value / 65535.0,
+                    _ => value,
+                }
+            }
+            else
+            {
+                value
+            });
+        }
+    }
+
+    Ok((values, components))
+}
+
+fn load_image(document : &mut Document, index : usize) -> Option<CpuTexture>
+{
+    if let Some(texture) = document.images.get(&index)
+    {
+        return texture.clone();
+    }
+
+    let image = array(&document.json, "images").get(index);
+    let data = image.and_then(|image| match image.get("uri").and_then(|u| u.as_str())
+    {
+        Some(uri) => read_uri(&document.directory, uri).ok(),
+        None => {
+            let view = array(&document.json, "bufferViews").get(image.get("bufferView")?.as_u64()? as usize)?;
+            let buffer = document.buffers.get(view.get("buffer")?.as_u64()? as usize)?;
+            let offset = view.get("byteOffset").and_then(|o| o.as_u64()).unwrap_or(0) as usize;
+            let length = view.get("byteLength")?.as_u64()? as usize;
+
+            buffer.get(offset..offset + length).map(|d| d.to_vec())
+        }
+    });
+
+    let texture = data
+        .and_then(|data| image::load_from_memory(&data).ok())
+        .map(|image| {
+            let image = image.to_rgba8();
+
+            CpuTexture {
+                width: image.width(),
+                height: image.height(),
+                data: TextureData::RgbaU8(image.pixels().map(|p| p.0).collect()),
+                ..Default::default()
+            }
+        });
+
+    document.images.insert(index, texture.clone());
+    texture
+}
+
+fn linear_to_srgb(value : f64) -> u8
+{
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+
+    (srgb * 255.0).round() as u8
+}
+
+fn array<'a>(json : &'a Value, name : &str) -> &'a [Value]
+{
+    json.get(name).and_then(|a| a.as_array()).map(|a| a.as_slice()).unwrap_or(&[])
+}
+
+fn indices(json : &Value, name : &str) -> Vec<usize>
+{
+    array(json, name).iter().filter_map(|i| i.as_u64()).map(|i| i as usize).collect()
+}
@@ -1,23 +1,210 @@
-use regex::Regex;
 use stl_io::IndexedMesh;
 use three_d::*;
-use threemf;
 use std::num::ParseFloatError;
-use std::{collections::HashMap, fs::File};
+use std::fs::{self, File};
+use std::path::Path;
 use std::io;
 use std::io::Read;
-use std::io::BufRead;
 use stl_io;
-use zip::ZipArchive;
 use zip::result::ZipError;
-use wavefront_obj::obj::{self, ObjSet};
+use crate::bgcode;
+use crate::gcode;
+use crate::gcode_color::{ColorBy, Colorizer};
+use crate::gcode_overlay::{Marker, Overlay, OverlayTracer};
+use crate::input_format::InputFormat;
+use crate::msla;
+use crate::parse_3ds;
+use crate::parse_3mf;
+use crate::parse_amf;
+use crate::parse_dae;
+use crate::parse_gltf;
+use crate::parse_obj;
+use crate::parse_off;
+use crate::parse_ply;
+use crate::parse_step;
+
+/// Vertex color for parts of a mesh without a color of their own, these are rendered in the default model color
+pub const UNSPECIFIED_COLOR : Srgba = Srgba { r: 0, g: 0, b: 0, a: 0 };
+
+/// Size of a printed G-code line in mm, across and up
+const LINE_WIDTH : f32 = 0.4;
+const LINE_HEIGHT : f32 = 0.2;
+/// Size of a travel move line in mm
+const TRAVEL_WIDTH : f32 = 0.05;
+/// Radius of overlay markers as a part of the size of the print
+const MARKER_SIZE : f32 = 0.005;
+
+/// A parsed file, made up of one or more named parts that are rendered together
+pub struct Model
+{
+    pub parts: Vec<ModelPart>,
+    /// Printed lines of a G-code file, these are drawn next to the parts
+    pub toolpath: Option<Toolpath>,
+}
+
+#[derive(Clone)]
+pub struct ModelPart
+{
+    /// Object or group name from the file, empty if the format has no names
+    pub name: String,
+    pub mesh: CpuMesh,
+    /// Color from the file's material, rendered instead of the default model color
+    pub color: Option<Srgba>,
+    /// Texture from the file's material, sampled with the uvs of the mesh
+    pub texture: Option<CpuTexture>,
+}
+
+/// Printed lines of a G-code file. Every line is drawn as an instance of the same shape, stretched from its start to
+/// its end, so memory grows with the number of moves instead of with the triangles drawn for them.
+#[derive(Clone, Default)]
+pub struct Toolpath
+{
+    /// Start and end of every printed line, in render coordinates
+    pub lines: Vec<(Vec3, Vec3)>,
+    /// Color of every line, lines keep the model color without them
+    pub colors: Option<Vec<Srgba>>,
+    /// Start and end of every travel move, only filled when travel moves are drawn
+    pub travels: Vec<(Vec3, Vec3)>,
+    /// Retractions, z hops and seams, only filled for the overlays that are drawn
+    pub markers: Vec<Marker>,
+    /// Printed layers in print order
+    pub layers: Vec<Layer>,
+}
+
+/// The lines of a layer are those before `end`, after the ones of the layer before.
+/// Travel moves and markers up to the last line of the layer belong to it in the same way.
+#[derive(Clone, Copy)]
+pub struct Layer
+{
+    /// Highest point printed in the layer
+    pub z: f32,
+    pub end: usize,
+    pub travel_end: usize,
+    pub marker_end: usize,
+}
+
+impl Toolpath
+{
+    /// The shape every line is drawn with, a cylinder from 0 to 1 along the x axis
+    pub fn line_mesh(&self) -> CpuMesh
+    {
+        let angle_subdivisions = if self.lines.len() < 1000000 { 3 } else { 2 };
+        CpuMesh::cylinder(angle_subdivisions)
+    }
+
+    /// Transformations that place the line shape on every line, lines without length are hidden
+    pub fn transformations(&self) -> Vec<Mat4>
+    {
+        self.lines
+            .iter()
+            .map(|(start, end)| if start == end { Mat4::from_translation(*start) * Mat4::from_scale(0.0) } else { edge_transform(*start, *end, LINE_WIDTH, LINE_HEIGHT) })
+            .collect()
+    }
+
+    /// Transformations that place the line shape on every travel move, thinner than the printed lines
+    pub fn travel_transformations(&self) -> Vec<Mat4>
+    {
+        self.travels
+            .iter()
+            .map(|(start, end)| edge_transform(*start, *end, TRAVEL_WIDTH, TRAVEL_WIDTH))
+            .collect()
+    }
+
+    /// The shape every marker is drawn with
+    pub fn marker_mesh(&self) -> CpuMesh
+    {
+        CpuMesh::sphere(8)
+    }
+
+    /// Transformations that place the marker shape on every marker, scaled with the size of the print
+    /// so markers stay visible in thumbnails of large prints
+    pub fn marker_transformations(&self) -> Vec<Mat4>
+    {
+        let mut aabb = AxisAlignedBoundingBox::EMPTY;
+
+        for (start, end) in &self.lines {
+            aabb.expand(&[*start, *end]);
+        }
+
+        let radius = (aabb.size().magnitude() * MARKER_SIZE).max(LINE_WIDTH * 2.0);
+
+        self.markers
+            .iter()
+            .map(|marker| Mat4::from_translation(marker.position) * Mat4::from_scale(radius))
+            .collect()
+    }
+}
+
+impl Model
+{
+    pub fn from_mesh(mesh : CpuMesh) -> Model
+    {
+        Model {
+            parts: vec![ModelPart { name: String::new(), mesh, color: None, texture: None }],
+            toolpath: None,
+        }
+    }
+
+    /// Index of the last layer printed at or below the given height
+    pub fn layer_at_z(&self, z : f32) -> Option<usize>
+    {
+        self.toolpath.as_ref()?.layers.iter().rposition(|l| l.z <= z + 1e-4)
+    }
+
+    /// The toolpath printed up to and including the given layer, with that layer in the highlight color.
+    /// Later lines are hidden rather than dropped, so the camera frames the part the same way at every layer.
+    pub fn until_layer(&self, last : usize, highlight : Option<Srgba>) -> Model
+    {
+        let Some(toolpath) = &self.toolpath else {
+            return Model { parts: self.parts.clone(), toolpath: None };
+        };
 
+        let last = last.min(toolpath.layers.len().saturating_sub(1));
+        let start = if last > 0 { toolpath.layers[last - 1].end } else { 0 };
+        let end = toolpath.layers.get(last).map_or(0, |l| l.end);
+        let mut cutaway = toolpath.clone();
+
+        for line in &mut cutaway.lines[end..] {
+            line.1 = line.0;
+        }
 
+        if let Some(layer) = toolpath.layers.get(last)
+        {
+            cutaway.travels.truncate(layer.travel_end);
+            cutaway.markers.truncate(layer.marker_end);
+        }
+
+        if let Some(highlight) = highlight
+        {
+            let colors = cutaway.colors.get_or_insert_with(|| vec![UNSPECIFIED_COLOR; toolpath.lines.len()]);
+            colors[start..end].fill(highlight);
+        }
+
+        Model { parts: self.parts.clone(), toolpath: Some(cutaway) }
+    }
+
+    /// Drops every part that does not have the given name
+    pub fn isolate(self, name : &str) -> Result<Model, ParseError>
+    {
+        let parts : Vec<ModelPart> = self.parts.into_iter().filter(|p| p.name == name).collect();
+
+        if parts.is_empty()
+        {
+            return Err(ParseError::MeshConvertError(format!("No object named {} found", name)));
+        }
+
+        Ok(Model { parts, toolpath: None })
+    }
+}
+
+#[derive(Debug)]
 pub enum ParseError
 {
     ReadError(String),
     ParseError(String),
     MeshConvertError(String),
+    /// The file only describes geometry that can not be turned into triangles
+    TessellationError(String),
 }
 
 impl ToString for ParseError {
@@ -26,6 +213,7 @@ impl ToString for ParseError {
             ParseError::ReadError(str) => String::from(format!("Failed to read file: {}", str)),
             ParseError::ParseError(str) => String::from(format!("Failed to interpret model: {}", str)),
             ParseError::MeshConvertError(str) => String::from(format!("Failed to convert mesh from model: {}", str)),
+            ParseError::TessellationError(str) => String::from(format!("Failed to tessellate model: {}", str)),
         }
     }
 }
@@ -38,9 +226,9 @@ impl From<io::Error> for ParseError
     }
 }
 
-impl From<threemf::Error> for ParseError
+impl From<quick_xml::Error> for ParseError
 {
-    fn from(e: threemf::Error) -> ParseError
+    fn from(e: quick_xml::Error) -> ParseError
     {
         ParseError::ParseError(e.to_string())
     }
@@ -62,163 +250,107 @@ impl From<three_d_asset::Error> for ParseError
     }
 }
 
-impl From<wavefront_obj::ParseError> for ParseError
+impl From<ParseFloatError> for ParseError
 {
-    fn from(e: wavefront_obj::ParseError) -> ParseError
+    fn from(e: ParseFloatError) -> ParseError
     {
         ParseError::ParseError(e.to_string())
     }
 }
 
-impl From<ParseFloatError> for ParseError
+/// Settings that change how G-code toolpaths are turned into a mesh
+#[derive(Default)]
+pub struct GcodeOptions
 {
-    fn from(e: ParseFloatError) -> ParseError
-    {
-        ParseError::ParseError(e.to_string())
-    }
+    pub color_by: ColorBy,
+    pub overlays: Vec<Overlay>,
 }
 
-pub fn parse_file(path : &str) -> Result<CpuMesh, ParseError>
+pub fn parse_file(path : &str, format : InputFormat, gcode_options : &GcodeOptions) -> Result<Model, ParseError>
 {
-    if path.ends_with(".stl")
-    {
-        return parse_stl(path);
-    }
-    else if path.ends_with(".3mf")
-    {
-        return parse_3mf(path);   
-    }
-    else if path.ends_with(".stl.zip")
+    match format
     {
-        return parse_stl_zip(path);
-    }
-    else if path.ends_with(".obj")
-    {
-        return parse_obj(path);
-    }
-    else if path.ends_with(".obj.zip")
-    {
-        return parse_obj_zip(path);
-    }
-    else if path.ends_with(".gcode")
-    {
-        return parse_gcode(path);
-    }
-    else if path.ends_with(".gcode.zip")
-    {
-        return parse_gcode_zip(path);
+        InputFormat::Stl => parse_stl(path).map(Model::from_mesh),
+        InputFormat::ThreeMf => parse_3mf::parse(path),
+        InputFormat::Obj => parse_obj(path),
+        InputFormat::Gcode => parse_gcode(path, gcode_options),
+        InputFormat::Bgcode => parse_bgcode(path, gcode_options),
+        InputFormat::Ply => parse_ply(path).map(Model::from_mesh),
+        InputFormat::Gltf => parse_gltf::parse(path),
+        InputFormat::Step => parse_step(path).map(Model::from_mesh),
+        InputFormat::Amf => parse_amf::parse(&fs::read(path)?),
+        InputFormat::Off => parse_off(path).map(Model::from_mesh),
+        InputFormat::ThreeDs => parse_3ds::parse(&fs::read(path)?).map(Model::from_mesh),
+        InputFormat::Dae => parse_dae(path).map(Model::from_mesh),
+        InputFormat::Msla => msla::read_file(path)?.to_mesh().map(Model::from_mesh),
+        InputFormat::Archive => Err(ParseError::ParseError(String::from("Archives are extracted before parsing"))),
     }
+}
 
-    return Err(ParseError::ParseError(String::from("Unknown file type")));
+fn parse_stl(path : &str) -> Result<CpuMesh, ParseError>
+{
+    let mut handle = File::open(path)?;
+    let stl = stl_io::read_stl(&mut handle)?;
+
+    parse_stl_inner(&stl)
 }
 
-fn parse_3mf(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_obj(path : &str) -> Result<Model, ParseError>
 {
-    /*
-    let mut data = fs::read("model.3mf").unwrap();
-    let mut file = io::Cursor::new(data);
-     */
-    let handle = File::open(path)?;
-    let mfmodel = threemf::read(handle)?;
-
-    let mut positions : Vec<Vec3> = Vec::new();
-    let mut indices : Vec<u32> = Vec::new();
-    
-    let mut all_meshes : Vec<&threemf::Mesh> = mfmodel
-        .iter()
-        .map(|f| f.resources.object.iter())
-        .flat_map(|f| f)
-        .filter(|predicate| predicate.mesh.is_some())
-        .map(|f| f.mesh.as_ref().unwrap())
-        .collect();
+    let mut handle = File::open(path)?;
+    let mut buffer = Vec::new();
+    handle.read_to_end(&mut buffer)?;
+
+    let directory = Path::new(path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
 
-    all_meshes.sort_by(|a, b| a.triangles.triangle.len().cmp(&b.triangles.triangle.len()).reverse());
+    parse_obj::parse(&String::from_utf8_lossy(&buffer), |name| read_relative_file(&directory, name))
+}
+
+/// Reads a file next to a model, falling back on a case insensitive match as models often come from Windows
+fn read_relative_file(directory : &Path, name : &str) -> Option<Vec<u8>>
+{
+    let path = directory.join(name);
 
-    if all_meshes.len() <= 0
+    if let Ok(buffer) = fs::read(&path)
     {
-        return Err(ParseError::MeshConvertError(String::from("No meshes found in 3mf model")));
+        return Some(buffer);
     }
 
-    let mesh = all_meshes[0];
+    let parent = path.parent()?;
+    let lowercase = path.file_name()?.to_string_lossy().to_lowercase();
+    let found = fs::read_dir(parent).ok()?
+        .filter_map(|e| e.ok())
+        .find(|e| e.file_name().to_string_lossy().to_lowercase() == lowercase)?;
 
-    positions.extend(mesh.vertices
-        .vertex
-            .iter()
-            .map(|a| Vec3 {
-                x: a.x as f32,
-                y: a.y as f32,
-                z: a.z as f32
-            }));
-
-    indices.extend(
-        mesh.triangles.triangle
-        .iter()
-        .flat_map(|a| [a.v1 as u32, a.v2 as u32, a.v3 as u32].into_iter()));
-
-    Ok(
-        CpuMesh {
-            positions: Positions::F32(positions),
-            indices: Indices::U32(indices),
-            ..Default::default()
-        }
-    )
+    fs::read(found.path()).ok()
 }
 
-
-fn parse_stl(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_ply(path : &str) -> Result<CpuMesh, ParseError>
 {
-    let mut handle = File::open(path)?;
-    let stl = stl_io::read_stl(&mut handle)?;
+    let buffer = fs::read(path)?;
 
-    parse_stl_inner(&stl)
+    parse_ply::parse(&buffer)
 }
 
-fn parse_stl_zip(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_step(path : &str) -> Result<CpuMesh, ParseError>
 {
-    let handle = File::open(path)?;
-    let mut zip = ZipArchive::new(handle)?;
-
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        if file.name().ends_with(".stl") {
-            let mut buffer = Vec::with_capacity(file.size() as usize);
-            file.read_to_end(&mut buffer)?;
-            let mut cursor = io::Cursor::new(buffer);
-
-            let stl = stl_io::read_stl(&mut cursor)?;
-            return parse_stl_inner(&stl);
-        }
-    }
-    
-    return Err(ParseError::MeshConvertError(String::from("Failed to find .stl model in zip")));
+    let buffer = fs::read(path)?;
+
+    parse_step::parse(&String::from_utf8_lossy(&buffer))
 }
 
-fn parse_obj(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_off(path : &str) -> Result<CpuMesh, ParseError>
 {
-    let mut handle = File::open(path)?;
-    let mut buffer = Vec::new();
-    handle.read_to_end(&mut buffer)?;
+    let buffer = fs::read(path)?;
 
-    let obj = obj::parse(std::str::from_utf8(&buffer).unwrap())?;
-    parse_obj_inner(&obj)
+    parse_off::parse(&String::from_utf8_lossy(&buffer))
 }
 
-fn parse_obj_zip(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_dae(path : &str) -> Result<CpuMesh, ParseError>
 {
-    let handle = File::open(path)?;
-    let mut zip = ZipArchive::new(handle)?;
+    let buffer = fs::read(path)?;
 
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        if file.name().ends_with(".obj") {
-            let mut buffer = Vec::with_capacity(file.size() as usize);
-            file.read_to_end(&mut buffer)?;
-
-            return Ok(parse_obj_inner(&obj::parse(std::str::from_utf8(&buffer).unwrap())?)?);
-        }
-    }
-    
-    return Err(ParseError::MeshConvertError(String::from("Failed to find .obj model in zip")));
+    parse_dae::parse(&String::from_utf8_lossy(&buffer))
 }
 
 // https://github.com/asny/three-d-asset/blob/main/src/io/stl.rs#L9
@@ -249,192 +381,82 @@ fn parse_stl_inner(stl : &IndexedMesh) -> Result<CpuMesh, ParseError>
     )
 }
 
-// https://github.com/asny/three-d-asset/blob/main/src/io/obj.rs#L54
-fn parse_obj_inner(obj : &ObjSet) -> Result<CpuMesh, ParseError>
+fn parse_gcode(path : &str, options : &GcodeOptions) -> Result<Model, ParseError>
 {
-    let mut all_meshes : Vec<CpuMesh> = obj.objects.iter().map(|object| {
-        let mut positions = Vec::new();
-        let mut indices = Vec::new();
-         for mesh in object.geometry.iter() { 
-             let mut map: HashMap<usize, usize> = HashMap::new();
- 
-             let mut process = |i: wavefront_obj::obj::VTNIndex| {
-                 let mut index = map.get(&i.0).map(|v| *v);
- 
-                 if index.is_none() {
-                     index = Some(positions.len());
-                     map.insert(i.0, index.unwrap());
-                     let position = object.vertices[i.0];
-                     positions.push(Vector3::new(position.x, position.y, position.z));
-                 }
- 
-                 indices.push(index.unwrap() as u32);
-             };
-             for shape in mesh.shapes.iter() {
-                 // All triangles with same material
-                 match shape.primitive {
-                     wavefront_obj::obj::Primitive::Triangle(i0, i1, i2) => {
-                         process(i0);
-                         process(i1);
-                         process(i2);
-                     }
-                     _ => {}
-                 }
-             }
-         }
-
-         CpuMesh {
-            positions: Positions::F64(positions),
-            indices: Indices::U32(indices),
-            ..Default::default()
-        }
-     }).collect();
-
-     all_meshes.sort_by(|a, b| a.indices.len().cmp(&b.indices.len()).reverse());
-
-     if all_meshes.len() <= 0
-     {
-         return Err(ParseError::MeshConvertError(String::from("No meshes found in 3mf model")));
-     }
- 
-     let mesh = &all_meshes[0];
-
-     return Ok(CpuMesh {
-        positions: mesh.positions.clone(),
-        indices: mesh.indices.clone(),
-        ..Default::default()
-     });
-}
+    let mut handle = File::open(path)?;
 
-struct Point 
-{
-    v: Vec3,
-    use_line: bool,
+    parse_gcode_inner(&mut handle, options)
 }
 
-fn parse_gcode(path : &str) -> Result<CpuMesh, ParseError>
+fn parse_bgcode(path : &str, options : &GcodeOptions) -> Result<Model, ParseError>
 {
     let mut handle = File::open(path)?;
+    let bgcode = bgcode::read(&mut handle)?;
+    let mut cursor = io::Cursor::new(bgcode.gcode.into_bytes());
 
-    parse_gcode_inner(&mut handle)
+    parse_gcode_inner(&mut cursor, options)
 }
 
-fn parse_gcode_zip(path : &str) -> Result<CpuMesh, ParseError>
-{
-    let handle = File::open(path)?;
-    let mut zip = ZipArchive::new(handle)?;
-
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        if file.name().ends_with(".gcode") {
-            let mut buffer = Vec::with_capacity(file.size() as usize);
-            file.read_to_end(&mut buffer)?;
-            let mut cursor = io::Cursor::new(buffer);
-
-            return parse_gcode_inner(&mut cursor);
-        }
-    }
-    
-    return Err(ParseError::MeshConvertError(String::from("Failed to find .stl model in zip")));
-}
-fn parse_gcode_inner<W>(reader: &mut W) -> Result<CpuMesh, ParseError>
+fn parse_gcode_inner<W>(reader: &mut W, options : &GcodeOptions) -> Result<Model, ParseError>
 where
     W: Read
 {
     let reader = io::BufReader::new(reader);
-    let mut entries = Vec::with_capacity(0x10000);
-    let mut last_x = 0f32;
-    let mut last_y = 0f32;
-    let mut last_z = 0f32;
-    let regex_xy = Regex::new(r"X([\d.]+)\s+Y([\d.]+)\s+E").unwrap();
-    let regex_xy_no_extrusion = Regex::new(r"X([\d.]+)\s+Y([\d.]+)").unwrap();
-    let regex_z = Regex::new(r"Z([\d.]+)").unwrap();
-    let mut position_unsafe = false;
-
-    for line in reader.lines() {
-        let line = line?;
-        if line.starts_with("G1") || line.starts_with("G0") {
-            if let Some(caps) = regex_z.captures(&line)
-            {
-                last_z = caps.get(1).unwrap().as_str().parse::<f32>()?;
-            }
-
-            if let Some(caps) = regex_xy.captures(&line) 
-            {
-                if position_unsafe
-                {
-                    entries.push(Point { v: vec3(-last_x, last_z, last_y), use_line: false});
-                    position_unsafe = false;
-                }
-
-                last_x = caps.get(1).unwrap().as_str().parse::<f32>()?;
-                last_y = caps.get(2).unwrap().as_str().parse::<f32>()?;
-
-                entries.push(Point { v: vec3(-last_x, last_z, last_y), use_line: true});
-            }
-            else if let Some(caps) = regex_xy_no_extrusion.captures(&line)
-            {
-                last_x = caps.get(1).unwrap().as_str().parse::<f32>()?;
-                last_y = caps.get(2).unwrap().as_str().parse::<f32>()?;
-                position_unsafe = true;
-            }
-        }
-    }
+    let mut toolpath = Toolpath::default();
+    let mut colorizer = Colorizer::new(options.color_by);
+    let mut tracer = OverlayTracer::new(&options.overlays);
+    let mut layer = None;
 
-    if entries.len() <= 2
-    {
-        return Err(ParseError::ParseError(String::from("Gcode file contains no move instructions")));
-    }
+    gcode::interpret(reader, |m| {
+        if tracer.is_enabled()
+        {
+            tracer.add(m, &mut toolpath);
+        }
 
-    let angle_subdivisions = if entries.len() < 1000000 { 3 } else { 2 };
-    let mut test_cylinder = CpuMesh::cylinder(angle_subdivisions);
-    test_cylinder
-        .transform(edge_transform(entries[0].v, entries[1].v))
-        .unwrap();
+        if !m.is_extrusion()
+        {
+            return;
+        }
 
-    let estimated_entries = entries.iter().filter(|x| x.use_line).count();
-    let mut positions = Vec::with_capacity(test_cylinder.positions.len() * estimated_entries);
-    let mut indices = Vec::with_capacity(test_cylinder.indices.len().unwrap() * estimated_entries);
+        let start = gcode::render_coordinates(m.from);
+        let end = gcode::render_coordinates(m.to);
 
-    for i in 0..entries.len() - 1 {
-        if !entries[i + 1].use_line
+        if layer != Some(m.layer)
         {
-            continue;
+            layer = Some(m.layer);
+            toolpath.layers.push(Layer { z: f32::MIN, end: 0, travel_end: 0, marker_end: 0 });
         }
 
-        let mut cylinder = CpuMesh::cylinder(angle_subdivisions);
-        cylinder
-            .transform(edge_transform(entries[i].v, entries[i + 1].v))
-            .unwrap();
+        let current = toolpath.layers.last_mut().unwrap();
+        current.z = current.z.max(start.y).max(end.y);
+        current.end = toolpath.lines.len() + 1;
+        current.travel_end = toolpath.travels.len();
+        current.marker_end = toolpath.markers.len();
 
-        let l = positions.len() as u32;
+        toolpath.lines.push((start, end));
+        colorizer.add(m);
+    })?;
 
-        positions.extend(
-            cylinder.positions.into_f32()
-        );
+    if toolpath.lines.is_empty()
+    {
+        return Err(ParseError::ParseError(String::from("Gcode file contains no move instructions")));
+    }
 
-        indices.extend(
-            cylinder.indices.into_u32()
-                .unwrap()
-                .iter()
-                .map(|i| *i + l)
-        );
+    if colorizer.is_enabled()
+    {
+        toolpath.colors = Some(colorizer.colors());
     }
-    
-    return Ok(CpuMesh {
-        positions: Positions::F32(positions.clone()),
-        indices: Indices::U32(indices.clone()),
-        ..Default::default()
-     });
+
+    Ok(Model { parts: Vec::new(), toolpath: Some(toolpath) })
 }
 
 // Smart code from https://github.com/asny/three-d/blob/master/examples/wireframe/src/main.rs
-fn edge_transform(p1: Vec3, p2: Vec3) -> Mat4 {
+fn edge_transform(p1: Vec3, p2: Vec3, width: f32, height: f32) -> Mat4 {
     Mat4::from_translation(p1)
         * Into::<Mat4>::into(Quat::from_arc(
             vec3(1.0, 0.0, 0.0),
             (p2 - p1).normalize(),
             None,
         ))
-        * Mat4::from_nonuniform_scale((p1 - p2).magnitude(), 0.2, 0.4)
+        * Mat4::from_nonuniform_scale((p1 - p2).magnitude(), height, width)
 }
\ No newline at end of file
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use three_d::*;
+use crate::geometry::triangulate;
+use crate::parse_mesh::{Model, ModelPart, ParseError};
+
+// http://paulbourke.net/dataformats/obj/
+
+/// Line elements are rendered as tubes with this radius, relative to the size of the model
+const LINE_RADIUS : f64 = 0.002;
+
+#[derive(Default)]
+struct Part
+{
+    name: String,
+    /// Material from the last `usemtl`, parts are split per material
+    material: String,
+    positions: Vec<Vector3<f64>>,
+    uvs: Vec<Vec2>,
+    indices: Vec<u32>,
+    /// Polylines from `l` elements, as indices into the file's vertices
+    lines: Vec<Vec<usize>>,
+    /// File vertex and uv index to index into positions
+    map: HashMap<(usize, Option<usize>), u32>,
+}
+
+impl Part
+{
+    fn vertex(&mut self, vertices : &[Vector3<f64>], uvs : &[Vec2], reference : (usize, Option<usize>)) -> u32
+    {
+        if let Some(i) = self.map.get(&reference)
+        {
+            return *i;
+        }
+
+        let i = self.positions.len() as u32;
+        self.positions.push(vertices[reference.0]);
+        // Images are stored top row first, obj uvs start at the bottom
+        self.uvs.push(reference.1.map(|uv| vec2(uvs[uv].x, 1.0 - uvs[uv].y)).unwrap_or(vec2(0.0, 0.0)));
+        self.map.insert(reference, i);
+        i
+    }
+}
+
+#[derive(Default)]
+struct Material
+{
+    color: Option<Srgba>,
+    /// Path of the diffuse texture, relative to the obj file
+    texture: Option<String>,
+}
+
+/// Parses the geometry of an obj file. Every object and group becomes its own part.
+///
+/// Polygons are triangulated by ear clipping, so concave faces keep their shape.
+/// Line elements are only rendered, as thin tubes, for parts without any faces. Point elements are never rendered.
+///
+/// Material libraries and textures are loaded through `read_file`, with paths relative to the obj file.
+/// Missing or broken ones are skipped, so the model still renders in the default color.
+pub fn parse<F>(obj : &str, mut read_file : F) -> Result<Model, ParseError>
+where
+    F: FnMut(&str) -> Option<Vec<u8>>
+{
+    let mut vertices : Vec<Vector3<f64>> = Vec::new();
+    let mut uvs : Vec<Vec2> = Vec::new();
+    let mut materials : HashMap<String, Material> = HashMap::new();
+    let mut parts : Vec<Part> = Vec::new();
+    let mut object_name = String::new();
+    let mut group_name = String::new();
+    let mut material_name = String::new();
+    let mut current : Option<usize> = None;
+
+    for (line_number, line) in logical_lines(obj).iter().enumerate() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line.as_str(),
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match keyword
+        {
+            "v" => {
+                let mut coordinate = || -> Result<f64, ParseError> {
+                    let token = tokens.next()
+                        .ok_or_else(|| ParseError::ParseError(format!("Vertex on line {} has less than 3 coordinates", line_number + 1)))?;
+
+                    token.parse::<f64>()
+                        .map_err(|_| ParseError::ParseError(format!("Invalid vertex coordinate {} on line {}", token, line_number + 1)))
+                };
+
+                let x = coordinate()?;
+                let y = coordinate()?;
+                let z = coordinate()?;
+                vertices.push(Vector3::new(x, y, z));
+            }
+            "vt" => {
+                let u = tokens.next().and_then(|t| t.parse::<f32>().ok()).unwrap_or(0.0);
+                let v = tokens.next().and_then(|t| t.parse::<f32>().ok()).unwrap_or(0.0);
+                uvs.push(vec2(u, v));
+            }
+            "mtllib" => {
+                let names : Vec<&str> = tokens.collect();
+
+                // Either one file name with spaces in it, or a list of files
+                let joined = resolve_path("", &names.join(" "));
+
+                match read_file(&joined)
+                {
+                    Some(data) => parse_mtl(&String::from_utf8_lossy(&data), &joined, &mut materials),
+                    None => {
+                        for name in names {
+                            let path = resolve_path("", name);
+
+                            if let Some(data) = read_file(&path)
+                            {
+                                parse_mtl(&String::from_utf8_lossy(&data), &path, &mut materials);
+                            }
+                        }
+                    }
+                }
+            }
+            "usemtl" => {
+                material_name = tokens.collect::<Vec<_>>().join(" ");
+                current = None;
+            }
+            "o" => {
+                object_name = tokens.collect::<Vec<_>>().join(" ");
+                group_name = String::new();
+                current = None;
+            }
+            "g" => {
+                group_name = tokens.find(|g| *g != "default").unwrap_or("").to_string();
+                current = None;
+            }
+            "f" | "l" => {
+                let mut polygon = Vec::new();
+
+                for token in tokens {
+                    polygon.push(reference(token, vertices.len(), uvs.len(), line_number)?);
+                }
+
+                let name = if group_name.is_empty() { &object_name } else { &group_name };
+                let part_index = *current.get_or_insert_with(|| match parts.iter().position(|p| &p.name == name && p.material == material_name) {
+                    Some(i) => i,
+                    None => {
+                        parts.push(Part { name: name.clone(), material: material_name.clone(), ..Default::default() });
+                        parts.len() - 1
+                    }
+                });
+
+                let part = &mut parts[part_index];
+
+                if keyword == "l"
+                {
+                    part.lines.push(polygon.iter().map(|r| r.0).collect());
+                    continue;
+                }
+
+                let polygon_vertices : Vec<usize> = polygon.iter().map(|r| r.0).collect();
+
+                for [a, b, c] in triangulate(&vertices, &polygon_vertices) {
+                    let a = part.vertex(&vertices, &uvs, polygon[a]);
+                    let b = part.vertex(&vertices, &uvs, polygon[b]);
+                    let c = part.vertex(&vertices, &uvs, polygon[c]);
+                    part.indices.extend([a, b, c]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let line_radius = model_size(&vertices) * LINE_RADIUS;
+    let mut textures : HashMap<String, Option<CpuTexture>> = HashMap::new();
+
+    let parts : Vec<ModelPart> = parts
+        .into_iter()
+        .filter_map(|mut part| {
+            if part.indices.is_empty()
+            {
+                for line in part.lines.iter() {
+                    for segment in line.windows(2) {
+                        add_tube(&mut part.positions, &mut part.indices, vertices[segment[0]], vertices[segment[1]], line_radius);
+                    }
+                }
+
+                part.uvs.resize(part.positions.len(), vec2(0.0, 0.0));
+            }
+
+            if part.indices.is_empty()
+            {
+                return None;
+            }
+
+            let material = materials.get(&part.material);
+            let texture = material
+                .and_then(|m| m.texture.as_ref())
+                .and_then(|path| textures
+                    .entry(path.clone())
+                    .or_insert_with(|| read_file(path).and_then(|data| load_texture(&data)))
+                    .clone());
+
+            Some(ModelPart {
+                name: part.name,
+                mesh: CpuMesh {
+                    positions: Positions::F64(part.positions),
+                    indices: Indices::U32(part.indices),
+                    uvs: if texture.is_some() { Some(part.uvs) } else { None },
+                    ..Default::default()
+                },
+                color: material.and_then(|m| m.color),
+                texture,
+            })
+        })
+        .collect();
+
+    if parts.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("No meshes found in obj model")));
+    }
+
+    Ok(Model { parts, toolpath: None })
+}
+
+/// Adds the `Kd` color and `map_Kd` texture of every material in a .mtl file
+fn parse_mtl(mtl : &str, mtl_path : &str, materials : &mut HashMap<String, Material>)
+{
+    let directory = match mtl_path.rfind('/') {
+        Some(i) => &mtl_path[..i],
+        None => "",
+    };
+
+    let mut current : Option<String> = None;
+
+    for line in logical_lines(mtl) {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line.as_str(),
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        if keyword == "newmtl"
+        {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            materials.insert(name.clone(), Material::default());
+            current = Some(name);
+            continue;
+        }
+
+        let material = match current.as_ref().and_then(|name| materials.get_mut(name)) {
+            Some(m) => m,
+            None => continue,
+        };
+
+        match keyword
+        {
+            "Kd" => {
+                let channels : Vec<f32> = tokens.filter_map(|t| t.parse::<f32>().ok()).collect();
+
+                if channels.len() >= 3
+                {
+                    let channel = |c : f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    material.color = Some(Srgba::new_opaque(channel(channels[0]), channel(channels[1]), channel(channels[2])));
+                }
+            }
+            "map_Kd" => {
+                // Options like `-s 1 1 1` come before the file name
+                if let Some(name) = tokens.last()
+                {
+                    material.texture = Some(resolve_path(directory, name));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn load_texture(data : &[u8]) -> Option<CpuTexture>
+{
+    let image = image::load_from_memory(data).ok()?.to_rgba8();
+
+    Some(CpuTexture {
+        width: image.width(),
+        height: image.height(),
+        data: TextureData::RgbaU8(image.pixels().map(|p| p.0).collect()),
+        ..Default::default()
+    })
+}
+
+/// Joins a relative path onto a directory, resolving `.` and `..` and using `/` as separator
+fn resolve_path(directory : &str, path : &str) -> String
+{
+    let path = path.replace('\\', "/");
+    let mut components : Vec<&str> = Vec::new();
+
+    for component in directory.split('/').chain(path.split('/')) {
+        match component
+        {
+            "" | "." => {}
+            ".." if components.last().is_some_and(|c| *c != "..") => { components.pop(); }
+            _ => components.push(component),
+        }
+    }
+
+    components.join("/")
+}
+
+/// Joins lines ending in a backslash with the line after it
+fn logical_lines(obj : &str) -> Vec<String>
+{
+    let mut lines = Vec::new();
+    let mut pending = String::new();
+
+    for line in obj.lines() {
+        match line.trim_end().strip_suffix('\\') {
+            Some(start) => {
+                pending.push_str(start);
+                pending.push(' ');
+            }
+            None => {
+                pending.push_str(line);
+                lines.push(std::mem::take(&mut pending));
+            }
+        }
+    }
+
+    if !pending.is_empty()
+    {
+        lines.push(pending);
+    }
+
+    lines
+}
+
+/// Resolves a `v/vt/vn` reference to a vertex and uv index, negative indices count back from the last one
+fn reference(token : &str, vertex_count : usize, uv_count : usize, line_number : usize) -> Result<(usize, Option<usize>), ParseError>
+{
+    let mut indices = token.split('/');
+    let vertex = resolve_index(indices.next().unwrap_or(""), vertex_count)
+        .ok_or_else(|| ParseError::ParseError(format!("Invalid vertex reference {} on line {}", token, line_number + 1)))?;
+
+    // Uvs are optional, so a broken one only loses the texture
+    let uv = indices.next().filter(|uv| !uv.is_empty()).and_then(|uv| resolve_index(uv, uv_count));
+
+    Ok((vertex, uv))
+}
+
+fn resolve_index(index : &str, count : usize) -> Option<usize>
+{
+    let index = index.parse::<i64>().ok()?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+
+    if resolved < 0 || resolved >= count as i64
+    {
+        return None;
+    }
+
+    Some(resolved as usize)
+}
+
+fn model_size(vertices : &[Vector3<f64>]) -> f64
+{
+    if vertices.is_empty()
+    {
+        return 0.0;
+    }
+
+    let mut min = vertices[0];
+    let mut max = vertices[0];
+
+    for v in vertices {
+        min = Vector3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+        max = Vector3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+    }
+
+    (max - min).magnitude()
+}
+
+fn add_tube(positions : &mut Vec<Vector3<f64>>, indices : &mut Vec<u32>, p1 : Vector3<f64>, p2 : Vector3<f64>, radius : f64)
+{
+    let length = (p2 - p1).magnitude();
+
+    if length <= 0.0
+    {
+        return;
+    }
+
+    let mut cylinder = CpuMesh::cylinder(3);
+    let transform = Mat4::from_translation(p1.cast::<f32>().unwrap())
+        * Into::<Mat4>::into(Quat::from_arc(
+            vec3(1.0, 0.0, 0.0),
+            (p2 - p1).normalize().cast::<f32>().unwrap(),
+            None,
+        ))
+        * Mat4::from_nonuniform_scale(length as f32, radius as f32, radius as f32);
+
+    cylinder.transform(transform).unwrap();
+
+    let offset = positions.len() as u32;
+    positions.extend(cylinder.positions.into_f64());
+    indices.extend(cylinder.indices.into_u32().unwrap().iter().map(|i| *i + offset));
+}
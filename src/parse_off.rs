@@ -0,0 +1,168 @@
+use three_d::*;
+use crate::geometry::triangulate;
+use crate::parse_mesh::{ParseError, UNSPECIFIED_COLOR};
+
+// http://www.geomview.org/docs/html/OFF.html
+
+/// Parses an ascii Geomview off file, including the ST/C/N/4 header prefixes. Vertex and face colors are kept.
+pub fn parse(data : &str) -> Result<CpuMesh, ParseError>
+{
+    let mut lines = data
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim())
+        .filter(|l| !l.is_empty());
+
+    let header = lines.next().ok_or_else(|| ParseError::ParseError(String::from("Off file is empty")))?;
+    let mut tokens : Vec<&str> = header.split_whitespace().collect();
+    let keyword = tokens.remove(0);
+
+    let prefix = keyword
+        .strip_suffix("OFF")
+        .ok_or_else(|| ParseError::ParseError(String::from("Not an off file")))?;
+
+    if tokens.first() == Some(&"BINARY")
+    {
+        return Err(ParseError::ParseError(String::from("Binary off files are not supported")));
+    }
+
+    let has_uvs = prefix.contains("ST");
+    let has_colors = prefix.contains('C');
+    let has_normals = prefix.contains('N');
+    let has_w = prefix.contains('4');
+
+    // The dimension line and the counts may share the header line, or follow on their own lines
+    let mut header_values : Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+    let needed = if prefix.ends_with('n') { 4 } else { 3 };
+
+    while header_values.len() < needed {
+        let line = lines.next().ok_or_else(|| ParseError::ParseError(String::from("Off file has no element counts")))?;
+        header_values.extend(line.split_whitespace().map(|t| t.to_string()));
+    }
+
+    let count = |value : &str| value
+        .parse::<usize>()
+        .map_err(|_| ParseError::ParseError(format!("Invalid off element count {}", value)));
+
+    let (dimension, counts) = if prefix.ends_with('n') { (count(&header_values[0])?, &header_values[1..]) } else { (3, &header_values[..]) };
+    let dimension = dimension.saturating_add(if has_w { 1 } else { 0 });
+    let vertex_count = count(&counts[0])?;
+    let face_count = count(&counts[1])?;
+
+    if dimension < 3
+    {
+        return Err(ParseError::ParseError(format!("Off files with {} dimensions are not supported", dimension)));
+    }
+
+    // The counts are not reserved up front, a broken header would otherwise allocate before the missing lines are noticed
+    let mut vertices : Vec<Vector3<f64>> = Vec::new();
+    let mut vertex_colors : Vec<Srgba> = Vec::new();
+
+    for _ in 0..vertex_count {
+        let line = lines.next().ok_or_else(|| ParseError::ParseError(String::from("Unexpected end of off vertices")))?;
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()?;
+
+        if values.len() < dimension
+        {
+            return Err(ParseError::ParseError(format!("Off vertex has too few coordinates: {}", line)));
+        }
+
+        // Homogeneous coordinates are divided by w
+        let w = if has_w && values[dimension - 1] != 0.0 { values[dimension - 1] } else { 1.0 };
+        vertices.push(Vector3::new(values[0], values[1], values[2]) / w);
+
+        if has_colors
+        {
+            let start = dimension + if has_normals { dimension } else { 0 };
+            let end = values.len() - if has_uvs { 2 } else { 0 };
+            vertex_colors.push(parse_color(&values[start.min(end)..end]).unwrap_or(UNSPECIFIED_COLOR));
+        }
+    }
+
+    let mut triangles : Vec<[u32; 3]> = Vec::new();
+    let mut face_colors : Vec<Option<Srgba>> = Vec::new();
+
+    for _ in 0..face_count {
+        let line = match lines.next() {
+            Some(line) => line,
+            None => break,
+        };
+
+        let tokens : Vec<&str> = line.split_whitespace().collect();
+        let size = count(tokens[0])?;
+
+        if tokens.len() <= size
+        {
+            return Err(ParseError::ParseError(format!("Off face has too few vertices: {}", line)));
+        }
+
+        let polygon = tokens[1..size + 1]
+            .iter()
+            .map(|t| count(t))
+            .collect::<Result<Vec<usize>, _>>()?;
+
+        if polygon.iter().any(|i| *i >= vertices.len())
+        {
+            return Err(ParseError::ParseError(String::from("Off face references a vertex that does not exist")));
+        }
+
+        let color = tokens[size + 1..]
+            .iter()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()?;
+
+        // A single value is an index into a color map, which is not part of the file
+        let color = parse_color(&color);
+
+        for triangle in triangulate(&vertices, &polygon) {
+            triangles.push(triangle.map(|i| polygon[i] as u32));
+            face_colors.push(color);
+        }
+    }
+
+    if triangles.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("Off file contains no faces")));
+    }
+
+    let positions : Vec<Vec3> = vertices.iter().map(|v| vec3(v.x as f32, v.y as f32, v.z as f32)).collect();
+
+    // Face colors need their own vertices, as neighbouring faces can have different colors
+    if face_colors.iter().any(|c| c.is_some())
+    {
+        return Ok(CpuMesh {
+            positions: Positions::F32(triangles.iter().flatten().map(|i| positions[*i as usize]).collect()),
+            indices: Indices::U32((0..triangles.len() as u32 * 3).collect()),
+            colors: Some(triangles
+                .iter()
+                .zip(face_colors.iter())
+                .flat_map(|(t, c)| t.map(|i| c.or(vertex_colors.get(i as usize).copied()).unwrap_or(UNSPECIFIED_COLOR)))
+                .collect()),
+            ..Default::default()
+        });
+    }
+
+    Ok(CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(triangles.into_iter().flatten().collect()),
+        colors: if has_colors { Some(vertex_colors) } else { None },
+        ..Default::default()
+    })
+}
+
+/// Reads an rgb or rgba color, either as 0..1 floats or 0..255 integers
+fn parse_color(values : &[f64]) -> Option<Srgba>
+{
+    if values.len() < 3
+    {
+        return None;
+    }
+
+    let scale = if values[..3].iter().any(|v| *v > 1.0) { 1.0 } else { 255.0 };
+    let channel = |v : f64| (v * scale).clamp(0.0, 255.0).round() as u8;
+
+    // Alpha is ignored, fully transparent vertices would keep the default color
+    Some(Srgba::new_opaque(channel(values[0]), channel(values[1]), channel(values[2])))
+}
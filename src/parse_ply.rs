@@ -0,0 +1,379 @@
+use three_d::*;
+use crate::parse_mesh::ParseError;
+
+// http://paulbourke.net/dataformats/ply/
+
+/// Points of a point cloud are rendered as small octahedrons with this radius, relative to the size of the cloud
+const POINT_RADIUS : f32 = 0.003;
+/// Point clouds with more points than this are thinned out, every point costs 6 vertices
+const MAX_POINTS : usize = 1000000;
+/// Element counts come from the header, vertices are only reserved up to this many in advance
+const MAX_RESERVED_VERTICES : usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format
+{
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScalarType
+{
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType
+{
+    fn parse(name : &str) -> Result<ScalarType, ParseError>
+    {
+        match name
+        {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(ParseError::ParseError(format!("Unknown ply property type {}", name))),
+        }
+    }
+
+    fn size(&self) -> usize
+    {
+        match self
+        {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// Scales a color channel to 0..1, integer channels use their full range
+    fn normalize(&self, value : f64) -> f64
+    {
+        match self
+        {
+            ScalarType::U8 | ScalarType::I8 => value / 255.0,
+            ScalarType::U16 | ScalarType::I16 => value / 65535.0,
+            ScalarType::U32 | ScalarType::I32 => value / 4294967295.0,
+            ScalarType::F32 | ScalarType::F64 => value,
+        }
+    }
+}
+
+struct Property
+{
+    name: String,
+    data_type: ScalarType,
+    /// Type of the item count for list properties
+    count_type: Option<ScalarType>,
+}
+
+struct Element
+{
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the values of the body one at a time, regardless of the storage format
+struct Body<'a>
+{
+    format: Format,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Body<'a>
+{
+    fn read(&mut self, data_type : ScalarType) -> Result<f64, ParseError>
+    {
+        if self.format == Format::Ascii
+        {
+            while self.position < self.data.len() && self.data[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+
+            let start = self.position;
+
+            while self.position < self.data.len() && !self.data[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+
+            if start == self.position
+            {
+                return Err(ParseError::ParseError(String::from("Unexpected end of ply data")));
+            }
+
+            let token = String::from_utf8_lossy(&self.data[start..self.position]);
+            return token.parse::<f64>()
+                .map_err(|_| ParseError::ParseError(format!("Invalid ply value {}", token)));
+        }
+
+        let size = data_type.size();
+
+        if self.position + size > self.data.len()
+        {
+            return Err(ParseError::ParseError(String::from("Unexpected end of ply data")));
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        self.position += size;
+
+        if self.format == Format::BinaryBigEndian
+        {
+            bytes[..size].reverse();
+        }
+
+        Ok(match data_type
+        {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+}
+
+/// Parses an ascii or binary ply file. Vertex colors are kept, and files without faces are rendered as a point cloud.
+pub fn parse(data : &[u8]) -> Result<CpuMesh, ParseError>
+{
+    let (format, elements, body_start) = parse_header(data)?;
+    let mut body = Body { format, data: &data[body_start..], position: 0 };
+
+    let mut positions : Vec<Vec3> = Vec::new();
+    let mut colors : Vec<Srgba> = Vec::new();
+    let mut indices : Vec<u32> = Vec::new();
+    let mut has_colors = false;
+
+    for element in elements.iter() {
+        let property_index = |names : &[&str]| element.properties
+            .iter()
+            .position(|p| p.count_type.is_none() && names.contains(&p.name.as_str()));
+
+        let x = property_index(&["x"]);
+        let y = property_index(&["y"]);
+        let z = property_index(&["z"]);
+        let red = property_index(&["red", "r", "diffuse_red"]);
+        let green = property_index(&["green", "g", "diffuse_green"]);
+        let blue = property_index(&["blue", "b", "diffuse_blue"]);
+        let vertex_indices = element.properties
+            .iter()
+            .position(|p| p.count_type.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"));
+
+        let is_vertex = element.name == "vertex" && x.is_some() && y.is_some() && z.is_some();
+        let is_face = element.name == "face" && vertex_indices.is_some();
+        let vertex_colors = is_vertex && red.is_some() && green.is_some() && blue.is_some();
+        has_colors |= vertex_colors;
+
+        if is_vertex
+        {
+            positions.reserve(element.count.min(MAX_RESERVED_VERTICES));
+        }
+
+        let mut values = vec![0f64; element.properties.len()];
+        let mut polygon : Vec<u32> = Vec::new();
+
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.count_type
+                {
+                    Some(count_type) => {
+                        let count = body.read(count_type)? as usize;
+                        polygon.clear();
+
+                        for _ in 0..count {
+                            polygon.push(body.read(property.data_type)? as u32);
+                        }
+
+                        if is_face && Some(i) == vertex_indices && count >= 3
+                        {
+                            // Faces of scanned meshes are convex, so a fan is enough
+                            for j in 1..count - 1 {
+                                indices.extend([polygon[0], polygon[j], polygon[j + 1]]);
+                            }
+                        }
+                    }
+                    None => values[i] = body.read(property.data_type)?,
+                }
+            }
+
+            if is_vertex
+            {
+                positions.push(vec3(values[x.unwrap()] as f32, values[y.unwrap()] as f32, values[z.unwrap()] as f32));
+
+                if vertex_colors
+                {
+                    let channel = |index : usize| {
+                        let property = &element.properties[index];
+                        (property.data_type.normalize(values[index]).clamp(0.0, 1.0) * 255.0).round() as u8
+                    };
+
+                    // Alpha is ignored, fully transparent vertices would keep the default color
+                    colors.push(Srgba::new_opaque(channel(red.unwrap()), channel(green.unwrap()), channel(blue.unwrap())));
+                }
+            }
+        }
+    }
+
+    if positions.is_empty()
+    {
+        return Err(ParseError::MeshConvertError(String::from("Ply file contains no vertices")));
+    }
+
+    if indices.iter().any(|i| *i as usize >= positions.len())
+    {
+        return Err(ParseError::ParseError(String::from("Ply face references a vertex that does not exist")));
+    }
+
+    let colors = if has_colors && colors.len() == positions.len() { Some(colors) } else { None };
+
+    if indices.is_empty()
+    {
+        return Ok(point_cloud(&positions, colors.as_deref()));
+    }
+
+    Ok(CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(indices),
+        colors,
+        ..Default::default()
+    })
+}
+
+fn parse_header(data : &[u8]) -> Result<(Format, Vec<Element>, usize), ParseError>
+{
+    let mut format = None;
+    let mut elements : Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut first = true;
+
+    loop {
+        let end = data[position..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .ok_or_else(|| ParseError::ParseError(String::from("Ply header has no end_header")))?;
+
+        let line = String::from_utf8_lossy(&data[position..position + end]);
+        let tokens : Vec<&str> = line.split_whitespace().collect();
+        position += end + 1;
+
+        if first
+        {
+            if tokens.first() != Some(&"ply")
+            {
+                return Err(ParseError::ParseError(String::from("Not a ply file")));
+            }
+
+            first = false;
+            continue;
+        }
+
+        match tokens.as_slice()
+        {
+            ["format", name, ..] => {
+                format = Some(match *name
+                {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(ParseError::ParseError(format!("Unknown ply format {}", name))),
+                });
+            }
+            ["element", name, count] => {
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse::<usize>().map_err(|_| ParseError::ParseError(format!("Invalid ply element count {}", count)))?,
+                    properties: Vec::new(),
+                });
+            }
+            ["property", "list", count_type, data_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| ParseError::ParseError(String::from("Ply property outside of an element")))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    data_type: ScalarType::parse(data_type)?,
+                    count_type: Some(ScalarType::parse(count_type)?),
+                });
+            }
+            ["property", data_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| ParseError::ParseError(String::from("Ply property outside of an element")))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    data_type: ScalarType::parse(data_type)?,
+                    count_type: None,
+                });
+            }
+            ["end_header", ..] => break,
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| ParseError::ParseError(String::from("Ply header has no format")))?;
+    Ok((format, elements, position))
+}
+
+/// Turns every point into a small octahedron, so point clouds still render as a solid shape
+fn point_cloud(points : &[Vec3], colors : Option<&[Srgba]>) -> CpuMesh
+{
+    let mut min = points[0];
+    let mut max = points[0];
+
+    for p in points {
+        min = vec3(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = vec3(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+
+    let radius = ((max - min).magnitude() * POINT_RADIUS).max(f32::EPSILON);
+    let step = points.len().div_ceil(MAX_POINTS);
+    let count = points.len().div_ceil(step);
+
+    let corners = [
+        vec3(radius, 0.0, 0.0), vec3(-radius, 0.0, 0.0),
+        vec3(0.0, radius, 0.0), vec3(0.0, -radius, 0.0),
+        vec3(0.0, 0.0, radius), vec3(0.0, 0.0, -radius),
+    ];
+    let faces : [u32; 24] = [
+        0, 2, 4, 2, 1, 4, 1, 3, 4, 3, 0, 4,
+        2, 0, 5, 1, 2, 5, 3, 1, 5, 0, 3, 5,
+    ];
+
+    let mut positions = Vec::with_capacity(count * corners.len());
+    let mut indices = Vec::with_capacity(count * faces.len());
+    let mut point_colors = colors.map(|_| Vec::with_capacity(count * corners.len()));
+
+    for i in (0..points.len()).step_by(step) {
+        let offset = positions.len() as u32;
+
+        positions.extend(corners.iter().map(|c| points[i] + c));
+        indices.extend(faces.iter().map(|f| f + offset));
+
+        if let (Some(point_colors), Some(colors)) = (point_colors.as_mut(), colors)
+        {
+            point_colors.extend([colors[i]; 6]);
+        }
+    }
+
+    CpuMesh {
+        positions: Positions::F32(positions),
+        indices: Indices::U32(indices),
+        colors: point_colors,
+        ..Default::default()
+    }
+}
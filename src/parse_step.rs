@@ -0,0 +1,1053 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use three_d::*;
+use crate::geometry::{self, newell_normal};
+use crate::parse_mesh::ParseError;
+
+// ISO 10303-21, entity definitions from ISO 10303-42 (geometry and topology) and ISO 10303-242 (tessellation)
+
+/// Full circles and cylinders are approximated with this many segments
+const CIRCLE_SEGMENTS : usize = 32;
+/// Assemblies nested deeper than this are assumed to be cyclic
+const MAX_ASSEMBLY_DEPTH : usize = 64;
+
+enum Param
+{
+    Ref(u64),
+    Number(f64),
+    /// Strings are only names and descriptions, which are not needed for geometry
+    Str,
+    Enum(String),
+    List(Vec<Param>),
+    /// Typed values like `LENGTH_MEASURE(1.0)`, only the value is kept
+    Typed(Vec<Param>),
+    /// `$` and `*`
+    Null,
+}
+
+impl Param
+{
+    fn as_id(&self) -> Option<u64>
+    {
+        match self
+        {
+            Param::Ref(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64>
+    {
+        match self
+        {
+            Param::Number(n) => Some(*n),
+            Param::Typed(params) => params.first()?.as_number(),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> &[Param]
+    {
+        match self
+        {
+            Param::List(list) => list,
+            _ => &[],
+        }
+    }
+
+    fn as_bool(&self) -> bool
+    {
+        !matches!(self, Param::Enum(e) if e == "F")
+    }
+}
+
+/// Every entity instance of the data section. Complex instances have more than one type.
+struct Step
+{
+    entities: HashMap<u64, Vec<(String, Vec<Param>)>>,
+}
+
+impl Step
+{
+    /// Type and parameters of an entity, for complex instances this is the first type
+    fn entity(&self, id : u64) -> Option<(&str, &[Param])>
+    {
+        self.entities.get(&id)?.first().map(|(name, params)| (name.as_str(), params.as_slice()))
+    }
+
+    fn params(&self, id : u64, name : &str) -> Option<&[Param]>
+    {
+        self.entities.get(&id)?.iter().find(|(n, _)| n == name).map(|(_, params)| params.as_slice())
+    }
+
+    fn point(&self, id : u64) -> Option<Vector3<f64>>
+    {
+        let coordinates = self.params(id, "CARTESIAN_POINT")?.get(1)?.as_list();
+        let c = |i : usize| coordinates.get(i).and_then(|c| c.as_number()).unwrap_or(0.0);
+
+        Some(Vector3::new(c(0), c(1), c(2)))
+    }
+
+    fn direction(&self, id : Option<u64>) -> Option<Vector3<f64>>
+    {
+        let ratios = self.params(id?, "DIRECTION")?.get(1)?.as_list();
+        let r = |i : usize| ratios.get(i).and_then(|c| c.as_number()).unwrap_or(0.0);
+        let direction = Vector3::new(r(0), r(1), r(2));
+
+        if direction.magnitude() > 0.0 { Some(direction.normalize()) } else { None }
+    }
+
+    fn vertex(&self, id : u64) -> Option<Vector3<f64>>
+    {
+        self.point(self.params(id, "VERTEX_POINT")?.get(1)?.as_id()?)
+    }
+
+    /// Origin and axes of an `AXIS2_PLACEMENT_3D`
+    fn placement(&self, id : u64) -> Option<Placement>
+    {
+        let params = self.params(id, "AXIS2_PLACEMENT_3D")?;
+        let origin = self.point(params.get(1)?.as_id()?)?;
+        let z = self.direction(params.get(2).and_then(|p| p.as_id())).unwrap_or(Vector3::new(0.0, 0.0, 1.0));
+        let reference = self.direction(params.get(3).and_then(|p| p.as_id())).unwrap_or(Vector3::new(1.0, 0.0, 0.0));
+
+        let mut x = reference - z * reference.dot(z);
+
+        if x.magnitude() < 1e-9
+        {
+            x = if z.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+            x = x - z * x.dot(z);
+        }
+
+        let x = x.normalize();
+
+        Some(Placement { origin, x, y: z.cross(x), z })
+    }
+}
+
+struct Placement
+{
+    origin: Vector3<f64>,
+    x: Vector3<f64>,
+    y: Vector3<f64>,
+    z: Vector3<f64>,
+}
+
+impl Placement
+{
+    /// Transforms from the coordinates of the placement to the coordinates it is defined in
+    fn matrix(&self) -> Matrix4<f64>
+    {
+        Matrix4::from_cols(self.x.extend(0.0), self.y.extend(0.0), self.z.extend(0.0), self.origin.extend(1.0))
+    }
+}
+
+/// Places the shape representations of assembly components in the coordinates of the assembly.
+/// A `NEXT_ASSEMBLY_USAGE_OCCURRENCE` links a component to its assembly, its `CONTEXT_DEPENDENT_SHAPE_REPRESENTATION`
+/// names the representation relationship of the two shapes, whose `ITEM_DEFINED_TRANSFORMATION` moves the
+/// component's `AXIS2_PLACEMENT_3D` onto the one in the assembly. A component used more than once has an occurrence for every use.
+#[derive(Default)]
+struct Assembly
+{
+    /// Representations related without a transformation share their coordinates, e.g. a shape representation
+    /// and the B-rep representation holding its solids. Every representation points to one of its group.
+    groups: HashMap<u64, u64>,
+    /// Every placement of a group in its parent group
+    parents: HashMap<u64, Vec<(u64, Matrix4<f64>)>>,
+    /// Representation the geometric items belong to
+    owners: HashMap<u64, u64>,
+    /// World transforms of every group found so far
+    instances: HashMap<u64, Vec<Matrix4<f64>>>,
+}
+
+impl Assembly
+{
+    fn new(step : &Step) -> Assembly
+    {
+        let mut assembly = Assembly::default();
+        let mut placed = Vec::new();
+        // Product definition of the component of every occurrence, by the representation relationship that places it
+        let mut occurrences : HashMap<u64, u64> = HashMap::new();
+        // Shape representation of every product definition
+        let mut product_shapes : HashMap<u64, u64> = HashMap::new();
+        // Product definition a `PRODUCT_DEFINITION_SHAPE` describes, which is an occurrence for placed components
+        let shape_definition = |shape : u64| step.params(shape, "PRODUCT_DEFINITION_SHAPE")?.get(2)?.as_id();
+
+        for id in step.entities.keys() {
+            if let Some(params) = step.params(*id, "CONTEXT_DEPENDENT_SHAPE_REPRESENTATION")
+            {
+                let component = params.get(1)
+                    .and_then(|p| p.as_id())
+                    .and_then(shape_definition)
+                    .and_then(|occurrence| step.params(occurrence, "NEXT_ASSEMBLY_USAGE_OCCURRENCE")?.get(4)?.as_id());
+
+                if let (Some(relation), Some(component)) = (params.first().and_then(|p| p.as_id()), component)
+                {
+                    occurrences.insert(relation, component);
+                }
+            }
+
+            let product_shape = step.params(*id, "SHAPE_DEFINITION_REPRESENTATION")
+                .and_then(|params| Some((shape_definition(params.first()?.as_id()?)?, params.get(1)?.as_id()?)));
+
+            if let Some((product, shape)) = product_shape
+            {
+                product_shapes.insert(product, shape);
+            }
+
+            let Some(relation) = step.params(*id, "REPRESENTATION_RELATIONSHIP").or(step.params(*id, "SHAPE_REPRESENTATION_RELATIONSHIP")) else {
+                continue;
+            };
+
+            let (Some(rep_1), Some(rep_2)) = (relation.get(2).and_then(|r| r.as_id()), relation.get(3).and_then(|r| r.as_id())) else {
+                continue;
+            };
+
+            match step.params(*id, "REPRESENTATION_RELATIONSHIP_WITH_TRANSFORMATION").and_then(|t| t.first()?.as_id())
+            {
+                Some(transformation) => placed.push((*id, rep_1, rep_2, transformation)),
+                None => {
+                    let (a, b) = (assembly.group(rep_1), assembly.group(rep_2));
+                    assembly.groups.insert(a, b);
+                },
+            }
+        }
+
+        if placed.is_empty()
+        {
+            return assembly;
+        }
+
+        for (relation, rep_1, rep_2, transformation) in placed {
+            let Some(transformation) = step.params(transformation, "ITEM_DEFINED_TRANSFORMATION") else {
+                continue;
+            };
+
+            let placement = |i : usize| transformation.get(i).and_then(|p| p.as_id()).and_then(|p| step.placement(p)).map(|p| p.matrix());
+            let (Some(item_1), Some(item_2)) = (placement(2), placement(3)) else {
+                continue;
+            };
+
+            // rep_1 should be the component and rep_2 the assembly, the occurrence tells when a file has them the other way around
+            let reversed = occurrences.get(&relation)
+                .and_then(|component| product_shapes.get(component))
+                .is_some_and(|component| assembly.group(*component) == assembly.group(rep_2) && assembly.group(*component) != assembly.group(rep_1));
+
+            let (component, parent, transform) = match reversed
+            {
+                false => (rep_1, rep_2, item_2 * item_1.invert().unwrap_or(Matrix4::identity())),
+                true => (rep_2, rep_1, item_1 * item_2.invert().unwrap_or(Matrix4::identity())),
+            };
+
+            let (component, parent) = (assembly.group(component), assembly.group(parent));
+            assembly.parents.entry(component).or_default().push((parent, transform));
+        }
+
+        // Geometric items belong to the representation that lists them, directly or through the items they consist of
+        let mut representations : Vec<u64> = step.entities.keys().copied().filter(|id| is_representation(step, *id)).collect();
+        representations.sort();
+
+        for representation in representations {
+            let mut stack : Vec<u64> = step.entity(representation)
+                .and_then(|(_, params)| params.get(1))
+                .map(|items| items.as_list().iter().filter_map(|i| i.as_id()).collect())
+                .unwrap_or_default();
+
+            while let Some(id) = stack.pop() {
+                if assembly.owners.contains_key(&id) || is_representation(step, id)
+                {
+                    continue;
+                }
+
+                assembly.owners.insert(id, representation);
+
+                for (_, params) in step.entities.get(&id).into_iter().flatten() {
+                    references(params, &mut stack);
+                }
+            }
+        }
+
+        assembly
+    }
+
+    fn group(&self, mut representation : u64) -> u64
+    {
+        let mut depth = 0;
+
+        while let Some(next) = self.groups.get(&representation) {
+            if *next == representation || depth > MAX_ASSEMBLY_DEPTH
+            {
+                break;
+            }
+
+            representation = *next;
+            depth += 1;
+        }
+
+        representation
+    }
+
+    /// Transforms of every place a geometric item appears at in the model
+    fn item_instances(&mut self, item : u64) -> Vec<Matrix4<f64>>
+    {
+        match self.owners.get(&item)
+        {
+            Some(representation) => self.group_instances(self.group(*representation), 0),
+            None => vec![Matrix4::identity()],
+        }
+    }
+
+    fn group_instances(&mut self, group : u64, depth : usize) -> Vec<Matrix4<f64>>
+    {
+        if let Some(instances) = self.instances.get(&group)
+        {
+            return instances.clone();
+        }
+
+        let parents = match self.parents.get(&group)
+        {
+            Some(parents) if depth < MAX_ASSEMBLY_DEPTH => parents.clone(),
+            _ => return vec![Matrix4::identity()],
+        };
+
+        let instances : Vec<Matrix4<f64>> = parents
+            .iter()
+            .flat_map(|(parent, transform)| self.group_instances(*parent, depth + 1).into_iter().map(move |p| p * transform))
+            .collect();
+
+        self.instances.insert(group, instances.clone());
+        instances
+    }
+}
+
+/// Representations are the entities that list geometric items, such as `SHAPE_REPRESENTATION`
+/// or `ADVANCED_BREP_SHAPE_REPRESENTATION`
+fn is_representation(step : &Step, id : u64) -> bool
+{
+    step.entity(id).is_some_and(|(name, _)| name.ends_with("REPRESENTATION") && name != "CONTEXT_DEPENDENT_SHAPE_REPRESENTATION")
+}
+
+fn references(params : &[Param], ids : &mut Vec<u64>)
+{
+    for param in params {
+        match param
+        {
+            Param::Ref(id) => ids.push(*id),
+            Param::List(list) | Param::Typed(list) => references(list, ids),
+            _ => {},
+        }
+    }
+}
+
+#[derive(Default)]
+struct MeshBuilder
+{
+    positions: Vec<Vector3<f64>>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder
+{
+    fn add_triangle(&mut self, a : Vector3<f64>, b : Vector3<f64>, c : Vector3<f64>)
+    {
+        let offset = self.positions.len() as u32;
+        self.positions.extend([a, b, c]);
+        self.indices.extend([offset, offset + 1, offset + 2]);
+    }
+
+    /// Adds the triangles of an item once for every place it appears at
+    fn add_placed<F>(&mut self, assembly : &mut Assembly, id : u64, add : F)
+    where
+        F: FnOnce(&mut MeshBuilder)
+    {
+        let (first_position, first_index) = (self.positions.len(), self.indices.len());
+        add(self);
+
+        if self.positions.len() == first_position
+        {
+            return;
+        }
+
+        let instances = assembly.item_instances(id);
+        let (last_position, last_index) = (self.positions.len(), self.indices.len());
+
+        for transform in instances.iter().skip(1) {
+            let offset = (self.positions.len() - first_position) as u32;
+
+            for i in first_position..last_position {
+                self.positions.push((transform * self.positions[i].extend(1.0)).truncate());
+            }
+
+            for i in first_index..last_index {
+                self.indices.push(self.indices[i] + offset);
+            }
+        }
+
+        if let Some(transform) = instances.first()
+        {
+            for position in &mut self.positions[first_position..last_position] {
+                *position = (transform * position.extend(1.0)).truncate();
+            }
+        }
+    }
+}
+
+/// Parses a STEP file. Tessellated geometry is used when the file has it, otherwise planar and cylindrical
+/// B-rep faces are approximated. Other surface types are skipped.
+/// Assembly components are moved to their placements in the assembly, once for every time they are used.
+pub fn parse(data : &str) -> Result<CpuMesh, ParseError>
+{
+    let step = parse_data_section(data)?;
+    let mut assembly = Assembly::new(&step);
+    let mut mesh = MeshBuilder::default();
+
+    let mut ids : Vec<u64> = step.entities.keys().copied().collect();
+    ids.sort();
+
+    for id in ids.iter() {
+        mesh.add_placed(&mut assembly, *id, |mesh| add_tessellated(&step, *id, mesh));
+    }
+
+    if mesh.indices.is_empty()
+    {
+        let mut face_count = 0;
+
+        for id in ids.iter() {
+            if let Some(("ADVANCED_FACE" | "FACE_SURFACE", _)) = step.entity(*id)
+            {
+                face_count += 1;
+                mesh.add_placed(&mut assembly, *id, |mesh| add_face(&step, *id, mesh));
+            }
+        }
+
+        if mesh.indices.is_empty()
+        {
+            return Err(ParseError::TessellationError(if face_count > 0
+            {
+                format!("None of the {} faces are planar or cylindrical", face_count)
+            }
+            else
+            {
+                String::from("No tessellated geometry or faces found")
+            }));
+        }
+    }
+
+    Ok(CpuMesh {
+        positions: Positions::F64(mesh.positions),
+        indices: Indices::U32(mesh.indices),
+        ..Default::default()
+    })
+}
+
+fn parse_data_section(data : &str) -> Result<Step, ParseError>
+{
+    let start = data.find("DATA;")
+        .ok_or_else(|| ParseError::ParseError(String::from("STEP file has no DATA section")))?;
+
+    let mut entities = HashMap::new();
+    let mut parser = Parser { data: &data.as_bytes()[start + 5..], position: 0 };
+
+    loop {
+        parser.skip_whitespace();
+
+        if parser.peek() != Some(b'#')
+        {
+            break;
+        }
+
+        parser.position += 1;
+        let id = parser.integer()?;
+        parser.expect(b'=')?;
+        parser.skip_whitespace();
+
+        let mut records = Vec::new();
+
+        if parser.peek() == Some(b'(')
+        {
+            // Complex instance, a list of partial entities
+            parser.position += 1;
+
+            loop {
+                parser.skip_whitespace();
+
+                if parser.peek() == Some(b')')
+                {
+                    parser.position += 1;
+                    break;
+                }
+
+                records.push(parser.record()?);
+            }
+        }
+        else
+        {
+            records.push(parser.record()?);
+        }
+
+        parser.expect(b';')?;
+        entities.insert(id, records);
+    }
+
+    Ok(Step { entities })
+}
+
+struct Parser<'a>
+{
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn peek(&self) -> Option<u8>
+    {
+        self.data.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        loop {
+            match self.peek()
+            {
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(b'/') if self.data.get(self.position + 1) == Some(&b'*') => {
+                    self.position = match self.data[self.position + 2..].windows(2).position(|w| w == b"*/") {
+                        Some(end) => self.position + 2 + end + 2,
+                        None => self.data.len(),
+                    };
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn error(&self, message : &str) -> ParseError
+    {
+        ParseError::ParseError(format!("{} at byte {} of the STEP data section", message, self.position))
+    }
+
+    fn expect(&mut self, c : u8) -> Result<(), ParseError>
+    {
+        self.skip_whitespace();
+
+        if self.peek() != Some(c)
+        {
+            return Err(self.error(&format!("Expected '{}'", c as char)));
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    fn integer(&mut self) -> Result<u64, ParseError>
+    {
+        let start = self.position;
+
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+
+        std::str::from_utf8(&self.data[start..self.position])
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(|| self.error("Expected an entity id"))
+    }
+
+    fn keyword(&mut self) -> String
+    {
+        let start = self.position;
+
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'!') {
+            self.position += 1;
+        }
+
+        String::from_utf8_lossy(&self.data[start..self.position]).to_uppercase()
+    }
+
+    /// An entity type with its parameter list
+    fn record(&mut self) -> Result<(String, Vec<Param>), ParseError>
+    {
+        self.skip_whitespace();
+        let name = self.keyword();
+
+        if name.is_empty()
+        {
+            return Err(self.error("Expected an entity type"));
+        }
+
+        self.expect(b'(')?;
+        Ok((name, self.list_items()?))
+    }
+
+    /// Parameters up to and including the closing parenthesis
+    fn list_items(&mut self) -> Result<Vec<Param>, ParseError>
+    {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            match self.peek()
+            {
+                Some(b')') => {
+                    self.position += 1;
+                    return Ok(items);
+                }
+                Some(b',') => self.position += 1,
+                Some(_) => items.push(self.param()?),
+                None => return Err(self.error("Unexpected end of file")),
+            }
+        }
+    }
+
+    fn param(&mut self) -> Result<Param, ParseError>
+    {
+        self.skip_whitespace();
+
+        match self.peek()
+        {
+            Some(b'#') => {
+                self.position += 1;
+                Ok(Param::Ref(self.integer()?))
+            }
+            Some(b'$') | Some(b'*') => {
+                self.position += 1;
+                Ok(Param::Null)
+            }
+            Some(b'(') => {
+                self.position += 1;
+                Ok(Param::List(self.list_items()?))
+            }
+            Some(b'.') => {
+                self.position += 1;
+                let value = self.keyword();
+                self.expect(b'.')?;
+                Ok(Param::Enum(value))
+            }
+            Some(b'\'') => {
+                self.position += 1;
+                loop {
+                    match self.peek()
+                    {
+                        // Quotes inside strings are doubled
+                        Some(b'\'') if self.data.get(self.position + 1) == Some(&b'\'') => self.position += 2,
+                        Some(b'\'') => {
+                            self.position += 1;
+                            break;
+                        }
+                        Some(_) => self.position += 1,
+                        None => return Err(self.error("Unterminated string")),
+                    }
+                }
+
+                Ok(Param::Str)
+            }
+            Some(b'"') => {
+                // Binary values are not needed for geometry
+                self.position += 1;
+
+                while self.peek().is_some_and(|c| c != b'"') {
+                    self.position += 1;
+                }
+
+                self.position += 1;
+                Ok(Param::Null)
+            }
+            Some(c) if c == b'-' || c == b'+' || c.is_ascii_digit() => {
+                let start = self.position;
+                self.position += 1;
+
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == b'.' || c == b'E' || c == b'e'
+                    || ((c == b'-' || c == b'+') && matches!(self.data[self.position - 1], b'E' | b'e')))
+                {
+                    self.position += 1;
+                }
+
+                let text = String::from_utf8_lossy(&self.data[start..self.position]);
+                text.parse::<f64>()
+                    .map(Param::Number)
+                    .map_err(|_| self.error(&format!("Invalid number {}", text)))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let (_, params) = self.record()?;
+                Ok(Param::Typed(params))
+            }
+            _ => Err(self.error("Unexpected character")),
+        }
+    }
+}
+
+/// Adds the triangles of a tessellated face or surface set
+fn add_tessellated(step : &Step, id : u64, mesh : &mut MeshBuilder)
+{
+    let (name, params) = match step.entity(id) {
+        Some(entity) => entity,
+        None => return,
+    };
+
+    // Position of the point index list, the triangles come right after it
+    let pnindex_position = match name
+    {
+        "TRIANGULATED_FACE" | "COMPLEX_TRIANGULATED_FACE" => 5,
+        "TRIANGULATED_SURFACE_SET" | "COMPLEX_TRIANGULATED_SURFACE_SET" => 4,
+        _ => return,
+    };
+
+    let coordinates : Vec<Vector3<f64>> = params
+        .get(1)
+        .and_then(|c| c.as_id())
+        .and_then(|c| step.params(c, "COORDINATES_LIST"))
+        .and_then(|c| c.get(2))
+        .map(|points| points
+            .as_list()
+            .iter()
+            .map(|p| {
+                let p = p.as_list();
+                let c = |i : usize| p.get(i).and_then(|c| c.as_number()).unwrap_or(0.0);
+                Vector3::new(c(0), c(1), c(2))
+            })
+            .collect())
+        .unwrap_or_default();
+
+    let pnindex : Vec<usize> = params
+        .get(pnindex_position)
+        .map(|p| p.as_list().iter().filter_map(|i| i.as_number()).map(|i| i as usize).collect())
+        .unwrap_or_default();
+
+    // Indices are 1 based, and go through the point index list when there is one
+    let point = |index : &Param| -> Option<Vector3<f64>> {
+        let index = index.as_number()? as usize;
+        let index = if pnindex.is_empty() { index } else { *pnindex.get(index.checked_sub(1)?)? };
+        coordinates.get(index.checked_sub(1)?).copied()
+    };
+
+    let mut add_polygon_triangles = |triangles : &[[&Param; 3]]| {
+        for [a, b, c] in triangles {
+            if let (Some(a), Some(b), Some(c)) = (point(a), point(b), point(c))
+            {
+                mesh.add_triangle(a, b, c);
+            }
+        }
+    };
+
+    if name.starts_with("COMPLEX_")
+    {
+        let strips = params.get(pnindex_position + 1).map(|p| p.as_list()).unwrap_or(&[]);
+        let fans = params.get(pnindex_position + 2).map(|p| p.as_list()).unwrap_or(&[]);
+
+        for strip in strips {
+            let strip = strip.as_list();
+            let triangles : Vec<[&Param; 3]> = (2..strip.len())
+                .map(|i| if i % 2 == 0 { [&strip[i - 2], &strip[i - 1], &strip[i]] } else { [&strip[i - 1], &strip[i - 2], &strip[i]] })
+                .collect();
+            add_polygon_triangles(&triangles);
+        }
+
+        for fan in fans {
+            let fan = fan.as_list();
+            let triangles : Vec<[&Param; 3]> = (2..fan.len()).map(|i| [&fan[0], &fan[i - 1], &fan[i]]).collect();
+            add_polygon_triangles(&triangles);
+        }
+    }
+    else
+    {
+        let triangles : Vec<[&Param; 3]> = params
+            .get(pnindex_position + 1)
+            .map(|t| t.as_list())
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|t| match t.as_list() {
+                [a, b, c] => Some([a, b, c]),
+                _ => None,
+            })
+            .collect();
+
+        add_polygon_triangles(&triangles);
+    }
+}
+
+/// Approximates a planar or cylindrical B-rep face, faces on other surfaces are skipped
+fn add_face(step : &Step, id : u64, mesh : &mut MeshBuilder)
+{
+    let params = match step.entity(id) {
+        Some((_, params)) => params,
+        None => return,
+    };
+
+    let surface = match params.get(2).and_then(|s| s.as_id()).and_then(|s| step.entity(s)) {
+        Some(surface) => surface,
+        None => return,
+    };
+
+    let mut outer = None;
+    let mut holes = Vec::new();
+
+    for bound in params.get(1).map(|b| b.as_list()).unwrap_or(&[]) {
+        let bound_id = match bound.as_id() {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let (bound_type, bound_params) = match step.entity(bound_id) {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let mut points = match bound_params.get(1).and_then(|l| l.as_id()) {
+            Some(loop_id) => loop_points(step, loop_id),
+            None => continue,
+        };
+
+        if !bound_params.get(2).map(|o| o.as_bool()).unwrap_or(true)
+        {
+            points.reverse();
+        }
+
+        if points.len() < 2
+        {
+            continue;
+        }
+
+        if bound_type == "FACE_OUTER_BOUND" && outer.is_none()
+        {
+            outer = Some(points);
+        }
+        else
+        {
+            holes.push(points);
+        }
+    }
+
+    // Without an explicit outer bound, the largest loop is the outer one
+    if outer.is_none() && !holes.is_empty()
+    {
+        let largest = (0..holes.len())
+            .max_by(|a, b| newell_normal(&holes[*a]).magnitude().total_cmp(&newell_normal(&holes[*b]).magnitude()))
+            .unwrap();
+        outer = Some(holes.remove(largest));
+    }
+
+    let outer = match outer {
+        Some(outer) => outer,
+        None => return,
+    };
+
+    match surface
+    {
+        ("PLANE", _) => add_planar_face(outer, holes, mesh),
+        ("CYLINDRICAL_SURFACE", surface_params) => {
+            let placement = surface_params.get(1).and_then(|p| p.as_id()).and_then(|p| step.placement(p));
+            let radius = surface_params.get(2).and_then(|r| r.as_number());
+
+            if let (Some(placement), Some(radius)) = (placement, radius)
+            {
+                let boundary : Vec<Vector3<f64>> = outer.into_iter().chain(holes.into_iter().flatten()).collect();
+                add_cylindrical_face(&placement, radius, &boundary, mesh);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn add_planar_face(outer : Vec<Vector3<f64>>, holes : Vec<Vec<Vector3<f64>>>, mesh : &mut MeshBuilder)
+{
+    let normal = newell_normal(&outer);
+    let mut polygon = outer;
+
+    // Join every hole to the outline with a zero width bridge, so a single polygon can be ear clipped
+    for mut hole in holes {
+        if hole.len() < 3
+        {
+            continue;
+        }
+
+        if newell_normal(&hole).dot(normal) > 0.0
+        {
+            hole.reverse();
+        }
+
+        let (i, j) = (0..polygon.len())
+            .flat_map(|i| (0..hole.len()).map(move |j| (i, j)))
+            .min_by(|a, b| (polygon[a.0] - hole[a.1]).magnitude2().total_cmp(&(polygon[b.0] - hole[b.1]).magnitude2()))
+            .unwrap();
+
+        let mut bridged = Vec::with_capacity(polygon.len() + hole.len() + 2);
+        bridged.extend_from_slice(&polygon[..=i]);
+        bridged.extend_from_slice(&hole[j..]);
+        bridged.extend_from_slice(&hole[..=j]);
+        bridged.extend_from_slice(&polygon[i..]);
+        polygon = bridged;
+    }
+
+    let indices : Vec<usize> = (0..polygon.len()).collect();
+
+    for [a, b, c] in geometry::triangulate(&polygon, &indices) {
+        mesh.add_triangle(polygon[a], polygon[b], polygon[c]);
+    }
+}
+
+/// Covers the angle and height range the boundary of the face spans on the cylinder
+fn add_cylindrical_face(placement : &Placement, radius : f64, boundary : &[Vector3<f64>], mesh : &mut MeshBuilder)
+{
+    let mut angles : Vec<f64> = Vec::with_capacity(boundary.len());
+    let mut min_height = f64::MAX;
+    let mut max_height = f64::MIN;
+
+    for p in boundary {
+        let local = p - placement.origin;
+        let height = local.dot(placement.z);
+        let angle = local.dot(placement.y).atan2(local.dot(placement.x));
+
+        angles.push(if angle < 0.0 { angle + 2.0 * PI } else { angle });
+        min_height = min_height.min(height);
+        max_height = max_height.max(height);
+    }
+
+    angles.sort_by(|a, b| a.total_cmp(b));
+
+    // The face covers everything except the largest gap between boundary points
+    let mut start = angles[0];
+    let mut range = 2.0 * PI;
+    let mut largest_gap = angles[0] + 2.0 * PI - angles[angles.len() - 1];
+
+    for i in 1..angles.len() {
+        if angles[i] - angles[i - 1] > largest_gap
+        {
+            largest_gap = angles[i] - angles[i - 1];
+            start = angles[i];
+        }
+    }
+
+    if largest_gap > 2.0 * PI / CIRCLE_SEGMENTS as f64 * 1.5
+    {
+        range = 2.0 * PI - largest_gap;
+    }
+
+    let segments = ((range / (2.0 * PI) * CIRCLE_SEGMENTS as f64).ceil() as usize).max(1);
+    let point = |angle : f64, height : f64| placement.origin
+        + placement.x * (radius * angle.cos())
+        + placement.y * (radius * angle.sin())
+        + placement.z * height;
+
+    for i in 0..segments {
+        let a0 = start + range * i as f64 / segments as f64;
+        let a1 = start + range * (i + 1) as f64 / segments as f64;
+
+        mesh.add_triangle(point(a0, min_height), point(a1, min_height), point(a1, max_height));
+        mesh.add_triangle(point(a0, min_height), point(a1, max_height), point(a0, max_height));
+    }
+}
+
+/// Points along a loop, circles and ellipses are sampled and other curves are straight lines between their vertices
+fn loop_points(step : &Step, id : u64) -> Vec<Vector3<f64>>
+{
+    let (name, params) = match step.entity(id) {
+        Some(entity) => entity,
+        None => return Vec::new(),
+    };
+
+    let items = params.get(1).map(|i| i.as_list()).unwrap_or(&[]);
+
+    match name
+    {
+        "POLY_LOOP" => items.iter().filter_map(|p| step.point(p.as_id()?)).collect(),
+        "EDGE_LOOP" => items.iter().filter_map(|e| e.as_id()).flat_map(|e| edge_points(step, e)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Points along an oriented edge, from its start up to but not including its end
+fn edge_points(step : &Step, id : u64) -> Vec<Vector3<f64>>
+{
+    let oriented = match step.params(id, "ORIENTED_EDGE") {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+
+    let edge = match oriented.get(3).and_then(|e| e.as_id()).and_then(|e| step.params(e, "EDGE_CURVE")) {
+        Some(e) => e,
+        None => return Vec::new(),
+    };
+
+    let start = edge.get(1).and_then(|v| v.as_id()).and_then(|v| step.vertex(v));
+    let end = edge.get(2).and_then(|v| v.as_id()).and_then(|v| step.vertex(v));
+    let same_sense = edge.get(4).map(|s| s.as_bool()).unwrap_or(true);
+
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Vec::new(),
+    };
+
+    let mut points = match edge.get(3).and_then(|c| c.as_id()) {
+        Some(curve) => curve_points(step, curve, start, end, same_sense),
+        None => vec![start],
+    };
+
+    points.push(end);
+
+    if !oriented.get(4).map(|o| o.as_bool()).unwrap_or(true)
+    {
+        points.reverse();
+    }
+
+    points.pop();
+    points
+}
+
+/// Samples a curve from start up to but not including end
+fn curve_points(step : &Step, id : u64, start : Vector3<f64>, end : Vector3<f64>, same_sense : bool) -> Vec<Vector3<f64>>
+{
+    let (name, params) = match step.entity(id) {
+        Some(entity) => entity,
+        None => return vec![start],
+    };
+
+    match name
+    {
+        // Curves on surfaces and trimmed curves wrap the curve that defines the shape
+        "SURFACE_CURVE" | "SEAM_CURVE" | "TRIMMED_CURVE" => match params.get(1).and_then(|c| c.as_id()) {
+            Some(basis) => curve_points(step, basis, start, end, same_sense),
+            None => vec![start],
+        },
+        "CIRCLE" | "ELLIPSE" => {
+            let placement = params.get(1).and_then(|p| p.as_id()).and_then(|p| step.placement(p));
+            let semi_axis_1 = params.get(2).and_then(|r| r.as_number());
+            let semi_axis_2 = if name == "CIRCLE" { semi_axis_1 } else { params.get(3).and_then(|r| r.as_number()) };
+
+            match (placement, semi_axis_1, semi_axis_2)
+            {
+                (Some(placement), Some(a), Some(b)) if a > 0.0 && b > 0.0 => {
+                    let angle = |p : Vector3<f64>| {
+                        let local = p - placement.origin;
+                        (local.dot(placement.y) / b).atan2(local.dot(placement.x) / a)
+                    };
+
+                    let from = angle(start);
+                    let mut sweep = (angle(end) - from).rem_euclid(2.0 * PI);
+
+                    if sweep < 1e-9
+                    {
+                        sweep = 2.0 * PI;
+                    }
+
+                    // Against the curve direction the edge takes the other way around
+                    if !same_sense
+                    {
+                        sweep = if sweep >= 2.0 * PI { -2.0 * PI } else { sweep - 2.0 * PI };
+                    }
+
+                    let segments = ((sweep.abs() / (2.0 * PI) * CIRCLE_SEGMENTS as f64).ceil() as usize).max(1);
+
+                    (0..segments)
+                        .map(|i| {
+                            let t = from + sweep * i as f64 / segments as f64;
+                            placement.origin + placement.x * (a * t.cos()) + placement.y * (b * t.sin())
+                        })
+                        .collect()
+                }
+                _ => vec![start],
+            }
+        }
+        _ => vec![start],
+    }
+}
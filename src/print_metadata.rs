@@ -0,0 +1,350 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+use serde_json::{json, Value};
+use zip::ZipArchive;
+use crate::bgcode;
+use crate::input_format::InputFormat;
+use crate::parse_3mf;
+use crate::parse_mesh::ParseError;
+
+/// Slicers write their summary and settings at the start or the end of a G-code file,
+/// only this much of either end is read from large files
+const SCAN_SIZE : u64 = 512 * 1024;
+
+/// Bambu Studio and OrcaSlicer estimates and filament usage of every sliced plate
+const BAMBU_SLICE_INFO_PATH : &str = "Metadata/slice_info.config";
+const MODEL_PATH : &str = "3D/3dmodel.model";
+
+/// Print settings and estimates written by slicers. Fields the file does not mention stay empty.
+#[derive(Default)]
+pub struct PrintMetadata
+{
+    /// Slicer name and version, e.g. `PrusaSlicer 2.7.1+win64`
+    pub slicer: Option<String>,
+    pub printer_model: Option<String>,
+    pub estimated_time_seconds: Option<u64>,
+    /// Filament used by all extruders together
+    pub filament_used_mm: Option<f64>,
+    pub filament_used_g: Option<f64>,
+    pub filament_cost: Option<f64>,
+    pub layer_height: Option<f64>,
+    pub nozzle_diameter: Option<f64>,
+    /// Filament type and color of every extruder
+    pub filament_types: Vec<String>,
+    pub filament_colors: Vec<String>,
+}
+
+/// Reads the metadata of a sliced or slicer project file. Other formats have none.
+pub fn read_file(path : &str, format : InputFormat) -> Result<Option<PrintMetadata>, ParseError>
+{
+    let mut metadata = PrintMetadata::default();
+
+    match format
+    {
+        InputFormat::Gcode => read_gcode(path, &mut metadata)?,
+        InputFormat::Bgcode => {
+            let mut handle = File::open(path)?;
+            let file = bgcode::read(&mut handle)?;
+
+            for (key, value) in file.file_metadata.iter()
+                .chain(file.printer_metadata.iter())
+                .chain(file.print_metadata.iter())
+                .chain(file.slicer_metadata.iter())
+            {
+                metadata.add(key, value);
+            }
+        },
+        InputFormat::ThreeMf => read_3mf(path, &mut metadata)?,
+        _ => return Ok(None),
+    }
+
+    Ok(Some(metadata))
+}
+
+impl PrintMetadata
+{
+    pub fn to_json(&self) -> String
+    {
+        let value = json!({
+            "slicer": self.slicer,
+            "printer_model": self.printer_model,
+            "estimated_time_seconds": self.estimated_time_seconds,
+            "filament_used_mm": self.filament_used_mm,
+            "filament_used_g": self.filament_used_g,
+            "filament_cost": self.filament_cost,
+            "layer_height": self.layer_height,
+            "nozzle_diameter": self.nozzle_diameter,
+            "filament_types": self.filament_types,
+            "filament_colors": self.filament_colors,
+        });
+
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+
+    /// Takes a setting or estimate under any of the names slicers use for it. The first value found
+    /// for a field is kept, slicers list the estimate for the normal mode before the silent mode.
+    fn add(&mut self, key : &str, value : &str)
+    {
+        let key = key.trim().to_lowercase();
+        let value = value.trim().trim_matches('"');
+
+        if value.is_empty()
+        {
+            return;
+        }
+
+        match key.as_str()
+        {
+            "producer" => set(&mut self.slicer, Some(value.to_string())),
+            "printer_model" | "target_machine.name" => set(&mut self.printer_model, Some(value.to_string())),
+            "estimated printing time (normal mode)" | "estimated printing time" | "total estimated time" | "time" | "print.time" | "build time" =>
+                set(&mut self.estimated_time_seconds, parse_duration(value)),
+            "filament used [mm]" | "total filament length [mm]" | "filament length" => set(&mut self.filament_used_mm, parse_total(value)),
+            // Cura writes meters
+            "filament used" => set(&mut self.filament_used_mm, parse_total(&value.replace('m', "")).map(|m| (m * 1e6).round() / 1e3)),
+            "filament used [g]" | "total filament used [g]" | "total filament weight [g]" | "plastic weight" => set(&mut self.filament_used_g, parse_total(value)),
+            "filament cost" | "total filament cost" | "material cost" => set(&mut self.filament_cost, parse_total(value)),
+            "layer_height" | "layer height" => set(&mut self.layer_height, parse_number(value)),
+            "nozzle_diameter" | "extruder_train.0.nozzle.diameter" => set(&mut self.nozzle_diameter, parse_number(value)),
+            "filament_type" if self.filament_types.is_empty() => self.filament_types = parse_list(value),
+            "filament_colour" | "filament_color" if self.filament_colors.is_empty() => self.filament_colors = parse_list(value),
+            _ => {},
+        }
+    }
+
+    /// Reads a comment line, e.g. `; filament used [g] = 3.71`, `;TIME:6666` or `; generated by PrusaSlicer 2.7.1 on ...`
+    fn add_comment(&mut self, comment : &str)
+    {
+        let lower = comment.to_lowercase();
+
+        for prefix in ["generated by ", "generated with ", "g-code generated by "] {
+            if lower.starts_with(prefix)
+            {
+                let generator = &comment[prefix.len()..];
+                let generator = generator.split(" on ").next().unwrap_or(generator);
+                set(&mut self.slicer, Some(generator.trim().to_string()));
+                return;
+            }
+        }
+
+        // Bambu Studio puts several estimates on one line, separated like comments
+        for part in comment.split("; ") {
+            let pair = match part.find('=')
+            {
+                Some(_) => part.split_once('='),
+                None => part.split_once(':'),
+            };
+
+            if let Some((key, value)) = pair
+            {
+                self.add(key, value);
+            }
+        }
+    }
+}
+
+fn set<T>(field : &mut Option<T>, value : Option<T>)
+{
+    if field.is_none()
+    {
+        *field = value;
+    }
+}
+
+fn read_gcode(path : &str, metadata : &mut PrintMetadata) -> Result<(), ParseError>
+{
+    let mut handle = File::open(path)?;
+    let length = handle.metadata()?.len();
+    let mut data = Vec::new();
+
+    if length <= SCAN_SIZE * 2
+    {
+        handle.read_to_end(&mut data)?;
+    }
+    else
+    {
+        (&mut handle).take(SCAN_SIZE).read_to_end(&mut data)?;
+        data.push(b'\n');
+        handle.seek(SeekFrom::End(-(SCAN_SIZE as i64)))?;
+        handle.read_to_end(&mut data)?;
+    }
+
+    for line in String::from_utf8_lossy(&data).lines() {
+        if let Some(comment) = line.trim().strip_prefix(';')
+        {
+            metadata.add_comment(comment.trim());
+        }
+    }
+
+    Ok(())
+}
+
+fn read_3mf(path : &str, metadata : &mut PrintMetadata) -> Result<(), ParseError>
+{
+    let handle = File::open(path)?;
+    let mut zip = ZipArchive::new(handle)?;
+
+    if let Some(slice_info) = read_optional_file(&mut zip, BAMBU_SLICE_INFO_PATH, u64::MAX)?
+    {
+        read_slice_info(&slice_info, metadata)?;
+    }
+
+    if let Some(project_settings) = read_optional_file(&mut zip, parse_3mf::BAMBU_PROJECT_SETTINGS_PATH, u64::MAX)?
+    {
+        let settings : Value = serde_json::from_str(&project_settings)
+            .map_err(|e| ParseError::ParseError(format!("Invalid project settings: {}", e)))?;
+
+        for (key, value) in settings.as_object().into_iter().flatten() {
+            // Per extruder settings are arrays
+            let value = match value
+            {
+                Value::String(value) => value.clone(),
+                Value::Array(values) => values.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(";"),
+                _ => continue,
+            };
+
+            metadata.add(key, &value);
+        }
+    }
+
+    // Same format as the settings at the end of a PrusaSlicer G-code file
+    if let Some(config) = read_optional_file(&mut zip, parse_3mf::PRUSA_CONFIG_PATH, u64::MAX)?
+    {
+        for line in config.lines() {
+            if let Some(comment) = line.trim().strip_prefix(';')
+            {
+                metadata.add_comment(comment.trim());
+            }
+        }
+    }
+
+    // The application that wrote the model, e.g. `BambuStudio-01.09.00.70`, is named near the start of the model file
+    if let Some(model) = read_optional_file(&mut zip, MODEL_PATH, SCAN_SIZE)?
+    {
+        let regex_application = Regex::new(r#"<metadata\s+name="Application"\s*>([^<]*)<"#).unwrap();
+
+        if let Some(caps) = regex_application.captures(&model)
+        {
+            metadata.add("producer", caps.get(1).unwrap().as_str());
+        }
+    }
+
+    Ok(())
+}
+
+/// `<plate>` elements with `<metadata key="prediction">` in seconds, `<metadata key="weight">` in grams
+/// and a `<filament>` element for every filament used. The plates are added up.
+fn read_slice_info(xml : &str, metadata : &mut PrintMetadata) -> Result<(), ParseError>
+{
+    let mut reader = Reader::from_str(xml);
+    let mut seconds : Option<u64> = None;
+    let mut grams : Option<f64> = None;
+    let mut meters : Option<f64> = None;
+    let mut types = Vec::new();
+    let mut colors = Vec::new();
+
+    loop
+    {
+        match reader.read_event()?
+        {
+            Event::Start(e) | Event::Empty(e) => {
+                match e.local_name().as_ref()
+                {
+                    b"metadata" => {
+                        let value = parse_3mf::attribute(&e, b"value")?.and_then(|v| parse_number(&v));
+
+                        match (parse_3mf::attribute(&e, b"key")?.as_deref(), value)
+                        {
+                            (Some("prediction"), Some(value)) => *seconds.get_or_insert(0) += value as u64,
+                            (Some("weight"), Some(value)) => *grams.get_or_insert(0.0) += value,
+                            _ => {},
+                        }
+                    },
+                    b"filament" => {
+                        if let Some(used) = parse_3mf::attribute(&e, b"used_m")?.and_then(|v| parse_number(&v))
+                        {
+                            *meters.get_or_insert(0.0) += used;
+                        }
+
+                        types.extend(parse_3mf::attribute(&e, b"type")?);
+                        colors.extend(parse_3mf::attribute(&e, b"color")?);
+                    },
+                    _ => {},
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    set(&mut metadata.estimated_time_seconds, seconds);
+    set(&mut metadata.filament_used_g, grams);
+    set(&mut metadata.filament_used_mm, meters.map(|m| m * 1000.0));
+
+    if metadata.filament_types.is_empty()
+    {
+        metadata.filament_types = types;
+    }
+
+    if metadata.filament_colors.is_empty()
+    {
+        metadata.filament_colors = colors;
+    }
+
+    Ok(())
+}
+
+fn read_optional_file<R>(zip : &mut ZipArchive<R>, path : &str, limit : u64) -> Result<Option<String>, ParseError>
+where
+    R: Read + Seek
+{
+    let Ok(file) = zip.by_name(path) else {
+        return Ok(None);
+    };
+
+    let mut data = Vec::new();
+    file.take(limit).read_to_end(&mut data)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Durations such as `1d 2h 3m 4s`, `1 hours 2 minutes` or plain seconds
+fn parse_duration(value : &str) -> Option<u64>
+{
+    let regex_part = Regex::new(r"(\d+(?:\.\d+)?)\s*([a-z]*)").unwrap();
+    let mut seconds = None;
+
+    for caps in regex_part.captures_iter(&value.to_lowercase()) {
+        let amount : f64 = caps.get(1).unwrap().as_str().parse().ok()?;
+        let unit = match caps.get(2).unwrap().as_str().chars().next()
+        {
+            Some('d') => 86400.0,
+            Some('h') => 3600.0,
+            Some('m') => 60.0,
+            _ => 1.0,
+        };
+
+        *seconds.get_or_insert(0.0) += amount * unit;
+    }
+
+    seconds.map(|s : f64| s.round() as u64)
+}
+
+/// The first number of a value, e.g. `0.4` for the per extruder list `0.4,0.6` or for `0.4 mm`
+fn parse_number(value : &str) -> Option<f64>
+{
+    value.split([',', ';']).next()?.split_whitespace().next()?.parse().ok()
+}
+
+/// Adds up a per extruder list such as `1234.5, 22.1`
+fn parse_total(value : &str) -> Option<f64>
+{
+    value.split([',', ';']).filter_map(parse_number).reduce(|a, b| a + b)
+}
+
+fn parse_list(value : &str) -> Vec<String>
+{
+    value.split([',', ';']).map(|v| v.trim().trim_matches('"').to_string()).filter(|v| !v.is_empty()).collect()
+}
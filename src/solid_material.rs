@@ -8,6 +8,11 @@ use three_d::*;
 pub struct SolidMaterial {
     /// Base surface color.
     pub color: Srgba,
+    /// Whether the per vertex colors of the geometry replace the base surface color.
+    /// Vertices with a fully transparent color keep the base surface color.
+    pub use_vertex_colors: bool,
+    /// An optional texture which replaces the base surface color, sampled with the uv coordinates of the geometry.
+    pub texture: Option<Texture2DRef>,
     /// Render states.
     pub render_states: RenderStates,
     /// Whether this material should be treated as a transparent material (An object needs to be rendered differently depending on whether it is transparent or opaque).
@@ -28,6 +33,11 @@ impl SolidMaterial {
     pub fn new_opaque(context: &Context, cpu_material: &CpuMaterial) -> Self {
         Self {
             color: cpu_material.albedo,
+            use_vertex_colors: false,
+            texture: cpu_material
+                .albedo_texture
+                .as_ref()
+                .map(|cpu_texture| Texture2DRef::from_cpu_texture(context, cpu_texture)),
             is_transparent: false,
             render_states: RenderStates::default(),
         }
@@ -37,6 +47,11 @@ impl SolidMaterial {
     pub fn new_transparent(context: &Context, cpu_material: &CpuMaterial) -> Self {
         Self {
             color: cpu_material.albedo,
+            use_vertex_colors: false,
+            texture: cpu_material
+                .albedo_texture
+                .as_ref()
+                .map(|cpu_texture| Texture2DRef::from_cpu_texture(context, cpu_texture)),
             is_transparent: true,
             render_states: RenderStates {
                 write_mask: WriteMask::COLOR,
@@ -50,6 +65,8 @@ impl SolidMaterial {
     pub fn from_physical_material(physical_material: &PhysicalMaterial) -> Self {
         Self {
             color: physical_material.albedo,
+            use_vertex_colors: false,
+            texture: physical_material.albedo_texture.clone(),
             render_states: physical_material.render_states,
             is_transparent: physical_material.is_transparent,
         }
@@ -64,11 +81,18 @@ impl FromCpuMaterial for SolidMaterial {
 
 impl Material for SolidMaterial {
     fn id(&self) -> EffectMaterialId {
-        EffectMaterialId(0x0000)
+        if self.texture.is_some() {
+            EffectMaterialId(0x0001)
+        } else {
+            EffectMaterialId(0x0000)
+        }
     }
 
     fn fragment_shader_source(&self, _lights: &[&dyn Light]) -> String {
         let mut shader = String::new();
+        if self.texture.is_some() {
+            shader.push_str("#define USE_TEXTURE\nin vec2 uvs;\n");
+        }
         shader.push_str(ColorMapping::fragment_shader_source());
         shader.push_str(include_str!("solid_material_shader.frag"));
         shader
@@ -76,7 +100,12 @@ impl Material for SolidMaterial {
 
     fn use_uniforms(&self, program: &Program, viewer: &dyn Viewer, _lights: &[&dyn Light]) {
         program.use_uniform("surfaceColor", self.color.to_linear_srgb());
+        program.use_uniform("useVertexColors", if self.use_vertex_colors { 1.0f32 } else { 0.0f32 });
         program.use_uniform_if_required("cameraPosition", viewer.position());
+        if let Some(ref tex) = self.texture {
+            program.use_uniform("textureTransformation", tex.transformation);
+            program.use_texture("tex", tex);
+        }
     }
 
     fn render_states(&self) -> RenderStates {
@@ -1,7 +1,14 @@
 uniform vec4 surfaceColor;
 uniform vec3 cameraPosition;
+uniform float useVertexColors;
 
 in vec3 pos;
+in vec4 col;
+
+#ifdef USE_TEXTURE
+uniform sampler2D tex;
+uniform mat3 textureTransformation;
+#endif
 
 layout (location = 0) out vec4 outColor;
 
@@ -22,8 +29,13 @@ void main()
     // Soft rim light effect
     float rim = pow(1.0 - max(dot(viewDir, normal), 0.0), 3.0);
 
-    // Merge colors
-    vec3 baseColor = surfaceColor.xyz;
+    // Merge colors, vertex colors with alpha 0 keep the surface color
+    vec3 baseColor = mix(surfaceColor.xyz, col.xyz, col.w * useVertexColors);
+
+#ifdef USE_TEXTURE
+    // Textures are stored in sRGB, the other colors are linear
+    baseColor = pow(texture(tex, (textureTransformation * vec3(uvs, 1.0)).xy).rgb, vec3(2.2));
+#endif
     vec3 shadedColor = baseColor * diffuse + rim * 0.2;
     
     outColor = vec4(shadedColor, 1.0);
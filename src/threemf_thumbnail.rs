@@ -0,0 +1,179 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use regex::Regex;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+use crate::parse_mesh::ParseError;
+
+const THUMBNAIL_PATH : &str = "Metadata/thumbnail.png";
+const RELS_PATH : &str = "_rels/.rels";
+const CONTENT_TYPES_PATH : &str = "[Content_Types].xml";
+const THUMBNAIL_RELATIONSHIP_TYPE : &str = "http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail";
+
+/// Whether a 3mf file already has a package thumbnail, either at the usual path or wherever
+/// its package relationships point, e.g. `/Metadata/plate_1.png` for Bambu Studio projects
+pub fn has_thumbnail(path : &str) -> Result<bool, ParseError>
+{
+    let handle = File::open(path)?;
+    let mut zip = ZipArchive::new(handle)?;
+
+    if zip.index_for_name(THUMBNAIL_PATH).is_some()
+    {
+        return Ok(true);
+    }
+
+    let Ok(mut file) = zip.by_name(RELS_PATH) else {
+        return Ok(false);
+    };
+
+    let mut rels = String::new();
+    file.read_to_string(&mut rels)?;
+
+    Ok(thumbnail_relationship(&rels).is_some())
+}
+
+/// Stores a png as the package thumbnail of a 3mf file.
+/// Every other entry is copied over without recompressing, then the new archive is renamed over the original.
+pub fn write_file(path : &str, png : &[u8]) -> Result<(), ParseError>
+{
+    let handle = File::open(path)?;
+    let mut zip = ZipArchive::new(handle)?;
+
+    let mut rels = None;
+    let mut content_types = None;
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+
+        if file.name() == RELS_PATH || file.name() == CONTENT_TYPES_PATH
+        {
+            let mut buffer = String::new();
+            file.read_to_string(&mut buffer)?;
+
+            if file.name() == RELS_PATH
+            {
+                rels = Some(buffer);
+            }
+            else
+            {
+                content_types = Some(buffer);
+            }
+        }
+    }
+
+    let temp_path = PathBuf::from(format!("{}.tmp", path));
+    // The new archive would otherwise get default permissions, e.g. losing the group write access of a shared folder
+    let permissions = fs::metadata(path)?.permissions();
+    let result = write_archive(&mut zip, &temp_path, png, rels, content_types)
+        .and_then(|_| fs::set_permissions(&temp_path, permissions).map_err(ParseError::from));
+
+    if let Err(e) = result.and_then(|_| fs::rename(&temp_path, path).map_err(ParseError::from))
+    {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn write_archive(
+    zip : &mut ZipArchive<File>,
+    temp_path : &PathBuf,
+    png : &[u8],
+    rels : Option<String>,
+    content_types : Option<String>,
+) -> Result<(), ParseError>
+{
+    let mut writer = ZipWriter::new(File::create(temp_path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for i in 0..zip.len() {
+        let file = zip.by_index_raw(i)?;
+        let name = file.name();
+
+        if name == THUMBNAIL_PATH || name == RELS_PATH || name == CONTENT_TYPES_PATH
+        {
+            continue;
+        }
+
+        writer.raw_copy_file(file)?;
+    }
+
+    writer.start_file(CONTENT_TYPES_PATH, options)?;
+    writer.write_all(add_png_content_type(content_types).as_bytes())?;
+
+    writer.start_file(RELS_PATH, options)?;
+    writer.write_all(add_thumbnail_relationship(rels).as_bytes())?;
+
+    // Png data is already compressed
+    writer.start_file(THUMBNAIL_PATH, SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+    writer.write_all(png)?;
+
+    writer.finish()?;
+    Ok(())
+}
+
+fn add_png_content_type(content_types : Option<String>) -> String
+{
+    let content_types = content_types.unwrap_or_else(|| String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\n</Types>\n"
+    ));
+
+    let regex_png = Regex::new(r#"(?i)<Default\s[^>]*Extension\s*=\s*"png""#).unwrap();
+
+    if regex_png.is_match(&content_types)
+    {
+        return content_types;
+    }
+
+    insert_before_closing_tag(&content_types, "</Types>", "<Default Extension=\"png\" ContentType=\"image/png\"/>")
+}
+
+fn add_thumbnail_relationship(rels : Option<String>) -> String
+{
+    let rels = rels.unwrap_or_else(|| String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\n</Relationships>\n"
+    ));
+
+    let regex_target = Regex::new(r#"Target\s*=\s*"[^"]*""#).unwrap();
+    let target = format!("Target=\"/{}\"", THUMBNAIL_PATH);
+
+    // Point an existing package thumbnail relationship at the new image
+    if let Some(existing) = thumbnail_relationship(&rels)
+    {
+        let updated = regex_target.replace(existing.as_str(), target.as_str());
+        return format!("{}{}{}", &rels[..existing.start()], updated, &rels[existing.end()..]);
+    }
+
+    let mut id = 0;
+    while rels.contains(&format!("Id=\"rel{}\"", id))
+    {
+        id += 1;
+    }
+
+    insert_before_closing_tag(
+        &rels,
+        "</Relationships>",
+        &format!("<Relationship {} Id=\"rel{}\" Type=\"{}\"/>", target, id, THUMBNAIL_RELATIONSHIP_TYPE),
+    )
+}
+
+/// The `<Relationship>` element of the package thumbnail
+fn thumbnail_relationship(rels : &str) -> Option<regex::Match<'_>>
+{
+    let regex_relationship = Regex::new(r"<Relationship\s[^>]*>").unwrap();
+
+    regex_relationship
+        .find_iter(rels)
+        .find(|m| m.as_str().contains(&format!("\"{}\"", THUMBNAIL_RELATIONSHIP_TYPE)))
+}
+
+fn insert_before_closing_tag(xml : &str, closing_tag : &str, element : &str) -> String
+{
+    match xml.rfind(closing_tag)
+    {
+        Some(index) => format!("{} {}\n{}", &xml[..index], element, &xml[index..]),
+        None => format!("{}{}\n", xml, element),
+    }
+}